@@ -1,19 +1,32 @@
 mod base64;
+mod observer;
 mod sha1;
 mod websocket;
 
+use std::env;
 use std::net::{TcpListener, TcpStream};
 use std::thread;
 
+use observer::StderrObserver;
 use websocket::WebSocket;
 
 /// Handles a connection using our websockets
 ///
-/// We create a new WebSocket instance, pass it the stream and then connect.
+/// We create a new WebSocket instance, pass it the stream and then connect. If
+/// running verbosely, every frame on the connection is logged to stderr.
 ///
-fn handle_client(stream: TcpStream) {
+fn handle_client(stream: TcpStream, verbose: bool) {
+    let peer = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+
     let mut ws = WebSocket::new(stream);
 
+    if verbose {
+        ws.set_observer(Box::new(StderrObserver::new(peer)));
+    }
+
     match ws.connect() {
         Ok(()) => {
             println!("WebSocket connection established");
@@ -35,9 +48,13 @@ fn handle_client(stream: TcpStream) {
 /// Listens for incoming connections
 ///
 /// We listen to incoming connections and create new threads for each one of them.
+/// Passing -v or --verbose turns on frame-level logging.
 ///
 fn main() {
-    //
+    let verbose = env::args()
+        .skip(1)
+        .any(|arg| arg == "-v" || arg == "--verbose");
+
     let listener = TcpListener::bind("127.0.0.1:8080").expect("Could not bind to port");
     println!("WebSocket server is running on ws://127.0.0.1:8080/");
 
@@ -45,7 +62,7 @@ fn main() {
         match stream {
             Ok(stream) => {
                 thread::spawn(move || {
-                    handle_client(stream);
+                    handle_client(stream, verbose);
                 });
             }
             Err(e) => {
@@ -0,0 +1,80 @@
+//! Observer
+//!
+//! Hooks for watching websocket frames as they come in and go out. This keeps
+//! protocol debugging out of the parsing and sending code itself.
+//!
+
+use std::fmt;
+
+/// Direction
+///
+/// Whether a frame was read from the client or written to it.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Direction::Incoming => write!(f, "<-"),
+            Direction::Outgoing => write!(f, "->"),
+        }
+    }
+}
+
+/// FrameObserver
+///
+/// Invoked once for every frame parsed or sent. The opcode is the raw 4-bit
+/// value from the frame header and length is the payload length in bytes.
+///
+pub trait FrameObserver: Send {
+    fn on_frame(&mut self, direction: Direction, opcode: u8, length: usize);
+}
+
+/// StderrObserver
+///
+/// The default observer, which writes a single line per frame to stderr. The
+/// peer is whatever label we want to attach to the lines, usually the client
+/// address.
+///
+pub struct StderrObserver {
+    peer: String,
+}
+
+impl StderrObserver {
+    pub fn new(peer: String) -> StderrObserver {
+        StderrObserver { peer }
+    }
+}
+
+impl FrameObserver for StderrObserver {
+    fn on_frame(&mut self, direction: Direction, opcode: u8, length: usize) {
+        eprintln!(
+            "[{}] {} {} (0x{:X}) {} bytes",
+            self.peer,
+            direction,
+            opcode_name(opcode),
+            opcode,
+            length
+        );
+    }
+}
+
+/// Names the opcode
+///
+/// Returns a readable name for the opcodes defined in RFC 6455.
+///
+pub fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x00 => "CONTINUATION",
+        0x01 => "TEXT",
+        0x02 => "BINARY",
+        0x08 => "CLOSE",
+        0x09 => "PING",
+        0x0A => "PONG",
+        _ => "UNKNOWN",
+    }
+}
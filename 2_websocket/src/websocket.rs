@@ -6,6 +6,7 @@
 //!
 
 use crate::base64::Base64;
+use crate::observer::{Direction, FrameObserver};
 use crate::sha1::Sha1;
 
 use std::fmt;
@@ -89,18 +90,40 @@ impl From<str::Utf8Error> for WebSocketError {
 
 /// Defines the WebSocket
 ///
-/// For now the WebSocket is only composed of a TcpStream, but normally we'd
-/// want to attach other information about the connection to it.
+/// For now the WebSocket is only composed of a TcpStream and an optional frame
+/// observer, but normally we'd want to attach other information about the
+/// connection to it.
 ///
 pub struct WebSocket {
     stream: TcpStream,
+    observer: Option<Box<dyn FrameObserver>>,
 }
 
 impl WebSocket {
     /// Creates the WebSocket instance
     ///
     pub fn new(stream: TcpStream) -> WebSocket {
-        WebSocket { stream }
+        WebSocket {
+            stream,
+            observer: None,
+        }
+    }
+
+    /// Attaches a frame observer
+    ///
+    /// The observer will be called for every frame parsed and sent on this
+    /// connection.
+    ///
+    pub fn set_observer(&mut self, observer: Box<dyn FrameObserver>) {
+        self.observer = Some(observer);
+    }
+
+    /// Notifies the observer, if there is one, of a frame
+    ///
+    fn observe(&mut self, direction: Direction, opcode: u8, length: usize) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.on_frame(direction, opcode, length);
+        }
     }
 
     /// Connect the websocket
@@ -367,6 +390,8 @@ impl WebSocket {
             data.push(buffer[offset + i] ^ mask[i % 4]);
         }
 
+        self.observe(Direction::Incoming, opcode, payload_len);
+
         // Return the opcode and data if given
         Ok(match opcode {
             0x01 => Frame::Text(data),   // text frame
@@ -386,6 +411,7 @@ impl WebSocket {
     ///
     fn send_ping(&mut self) -> io::Result<usize> {
         println!("Ping sent");
+        self.observe(Direction::Outgoing, 0x09, 0);
         self.stream.write(&[0x89, 0x00])
     }
 
@@ -397,6 +423,7 @@ impl WebSocket {
     ///
     fn send_pong(&mut self) -> Result<(), WebSocketError> {
         println!("Pong sent");
+        self.observe(Direction::Outgoing, 0x0A, 0);
         self.stream.write(&[0x8A, 0x00])?;
         Ok(()) // Opcode for pong is 0xA and FIN set
     }
@@ -428,6 +455,8 @@ impl WebSocket {
         // Append the data itself as bytes.
         frame.extend_from_slice(data_bytes);
 
+        self.observe(Direction::Outgoing, 0x01, length);
+
         self.stream.write_all(&frame)?;
         self.stream.flush()?;
         Ok(())
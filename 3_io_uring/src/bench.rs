@@ -0,0 +1,216 @@
+/// NOP benchmark
+///
+/// Pushes a large number of NOPs through rings of different sizes to show what
+/// the ring itself costs. A NOP does no work in the kernel, so everything being
+/// measured is the submission and completion machinery:
+///
+///     depth: the size of the submission queue, and so the most NOPs in flight
+///     batch: how many NOPs are prepared before each call to io_uring_submit
+///
+/// Bigger batches mean fewer syscalls per NOP, which is where most of the
+/// throughput comes from. Latency is measured per NOP from just before its
+/// batch is submitted to when its completion is read.
+///
+use crate::*;
+use std::io;
+use std::ptr::null_mut;
+use std::time::{Duration, Instant};
+
+/// Benchmark settings
+///
+/// Every combination of depth and batch size is run, skipping batches that
+/// don't fit in the queue.
+///
+pub struct Config {
+    pub count: usize,
+    pub depths: Vec<u32>,
+    pub batches: Vec<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            count: 1_000_000,
+            depths: vec![32, 128, 512],
+            batches: vec![1, 8, 32],
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from the command line
+    ///
+    ///     bench [count] [depths] [batches]
+    ///
+    /// where depths and batches are comma-separated lists, e.g.
+    /// `bench 100000 64,256 1,16`. Anything left out keeps its default. A
+    /// count of 0 is refused, since there'd be no latencies to report.
+    ///
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> io::Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(count) = args.next() {
+            config.count = parse(&count)?;
+        }
+        if config.count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The count must be non-zero",
+            ));
+        }
+        if let Some(depths) = args.next() {
+            config.depths = parse_list(&depths)?;
+        }
+        if let Some(batches) = args.next() {
+            config.batches = parse_list(&batches)?;
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid number: {}", value),
+        )
+    })
+}
+
+fn parse_list(value: &str) -> io::Result<Vec<u32>> {
+    value.split(',').map(parse).collect()
+}
+
+/// Results of a single run
+struct Report {
+    elapsed: Duration,
+    submits: usize,
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    /// The latency below which the given fraction of NOPs completed
+    fn percentile(&self, fraction: f64) -> Duration {
+        let index = ((self.latencies.len() - 1) as f64 * fraction).round() as usize;
+        self.latencies[index]
+    }
+}
+
+/// Runs the benchmark and prints a table of the results
+pub fn run(config: &Config) -> io::Result<()> {
+    println!("{} NOPs per run", config.count);
+    println!(
+        "{:>6} {:>6} {:>12} {:>10} {:>10} {:>10} {:>10}",
+        "depth", "batch", "ops/s", "submits", "p50 us", "p99 us", "p99.9 us"
+    );
+
+    for &depth in &config.depths {
+        for &batch in &config.batches {
+            if batch == 0 || batch > depth {
+                continue;
+            }
+
+            let mut report = nops(config.count, depth, batch)?;
+            report.latencies.sort_unstable();
+
+            println!(
+                "{:>6} {:>6} {:>12.0} {:>10} {:>10.1} {:>10.1} {:>10.1}",
+                depth,
+                batch,
+                config.count as f64 / report.elapsed.as_secs_f64(),
+                report.submits,
+                micros(report.percentile(0.5)),
+                micros(report.percentile(0.99)),
+                micros(report.percentile(0.999)),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+/// Runs count NOPs through a new ring of the given depth
+///
+/// The ring is torn down again whether or not the run succeeded.
+///
+fn nops(count: usize, depth: u32, batch: u32) -> io::Result<Report> {
+    let mut ring = setup_io_uring(depth)?;
+    let result = run_nops(&mut ring, count, depth, batch);
+    unsafe { io_uring_queue_exit(&mut ring) };
+    result
+}
+
+/// The NOP loop
+///
+/// Each NOP's user_data is its index, which is used to look up when it was
+/// submitted. Completions are read in bulk with io_uring_peek_batch_cqe after
+/// every submit, and we only block when there's no room left for another
+/// batch.
+///
+fn run_nops(ring: &mut io_uring, count: usize, depth: u32, batch: u32) -> io::Result<Report> {
+    let mut submitted_at = vec![Instant::now(); count];
+    let mut latencies = Vec::with_capacity(count);
+    let mut cqes: Vec<*mut io_uring_cqe> = vec![null_mut(); depth as usize * 2];
+
+    let mut next = 0;
+    let mut in_flight = 0;
+    let mut submits = 0;
+    let start = Instant::now();
+
+    while latencies.len() < count {
+        // Queue up another batch if there's room for it
+        let size = (batch as usize).min(count - next);
+        let room = size > 0 && in_flight + size <= depth as usize;
+
+        if room {
+            let now = Instant::now();
+
+            for _ in 0..size {
+                unsafe {
+                    let sqe = io_uring_get_sqe(ring);
+                    io_uring_prep_nop(sqe);
+                    (*sqe).user_data = next as u64;
+                }
+                submitted_at[next] = now;
+                next += 1;
+            }
+
+            let ret = unsafe { io_uring_submit(ring) };
+            if ret < 0 {
+                return Err(io::Error::from_raw_os_error(-ret));
+            }
+            in_flight += size;
+            submits += 1;
+        } else {
+            // Nothing more can be submitted until something completes
+            let mut cqe: *mut io_uring_cqe = null_mut();
+            let ret = unsafe { io_uring_wait_cqe(ring, &mut cqe) };
+            if ret < 0 {
+                return Err(io::Error::from_raw_os_error(-ret));
+            }
+        }
+
+        // Read whatever has completed so far
+        let ready = unsafe { io_uring_peek_batch_cqe(ring, cqes.as_mut_ptr(), cqes.len() as u32) };
+        let now = Instant::now();
+
+        for &cqe in &cqes[..ready as usize] {
+            let index = unsafe { (*cqe).user_data } as usize;
+            latencies.push(now - submitted_at[index]);
+        }
+
+        unsafe { io_uring_cq_advance(ring, ready) };
+        in_flight -= ready as usize;
+    }
+
+    Ok(Report {
+        elapsed: start.elapsed(),
+        submits,
+        latencies,
+    })
+}
@@ -0,0 +1,268 @@
+/// File copy
+///
+/// Copies a file by splitting it into chunks and issuing a read and a write
+/// for each one. The two are linked, so the kernel won't start the write until
+/// the read has completed, and we never have to look at the data ourselves:
+///
+///     read(src, buffer, offset) --link--> write(dst, buffer, offset)
+///
+/// Several of these pairs are in flight at once, each with its own buffer.
+///
+/// If a read comes back short, the write after it is completed with
+/// -ECANCELED instead of running. The part that was read is still in the
+/// buffer, so the pair writes just that on its own, and the rest is queued as
+/// a smaller chunk of its own. A short write is handled the same way. A read
+/// that gets nothing at all means the file ended early, which is an error, as
+/// is a chunk that keeps coming up short.
+///
+/// Only regular files can be copied, since the size has to be known up front.
+///
+use crate::*;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr::null_mut;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const PAIRS: usize = 8;
+
+/// How many times the rest of a chunk can be queued again after coming up
+/// short before we give up
+const RETRIES: u32 = 8;
+
+/// IOSQE_IO_LINK, which bindgen can't work out from the header's enum
+const IOSQE_IO_LINK: u8 = 1 << 2;
+
+/// A piece of the file still to be copied
+#[derive(Clone, Copy)]
+struct Chunk {
+    offset: u64,
+    len: usize,
+    retries: u32,
+}
+
+impl Chunk {
+    /// What's left of the chunk once the first done bytes are copied
+    fn rest(self, done: usize) -> io::Result<Chunk> {
+        if self.retries == RETRIES {
+            return Err(io::Error::other(format!(
+                "Gave up on the chunk at {} after {} short reads or writes",
+                self.offset, RETRIES
+            )));
+        }
+
+        Ok(Chunk {
+            offset: self.offset + done as u64,
+            len: self.len - done,
+            retries: self.retries + 1,
+        })
+    }
+}
+
+/// A read/write pair that's in flight
+///
+/// The buffer is shared by the read and the write, which is why it has to stay
+/// put until the write completes. read is how much the read got.
+///
+struct Pair {
+    chunk: Chunk,
+    buffer: Vec<u8>,
+    read: usize,
+    in_flight: bool,
+}
+
+/// Copies src to dst
+pub fn run(src: &str, dst: &str) -> io::Result<()> {
+    let input = File::open(src)?;
+    let output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dst)?;
+    let metadata = input.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} isn't a regular file", src),
+        ));
+    }
+    let size = metadata.len();
+
+    let mut ring = setup_io_uring((PAIRS * 2) as u32)?;
+    let result = copy(&mut ring, &input, &output, size);
+    unsafe { io_uring_queue_exit(&mut ring) };
+    result?;
+
+    // Files like the ones in /proc claim to be empty, and others can grow
+    // while we copy them, so make sure there was nothing after the end
+    if input.read_at(&mut [0], size)? != 0 {
+        return Err(io::Error::other(format!(
+            "{} is bigger than the {} bytes it claimed to be",
+            src, size
+        )));
+    }
+
+    println!("Copied {} bytes from {} to {}", size, src, dst);
+    Ok(())
+}
+
+/// The copy loop
+///
+/// Keeps up to PAIRS chunks in flight. A pair's user_data is its index times
+/// two for the read and plus one for the write, so each completion can be
+/// traced back to its pair.
+///
+fn copy(ring: &mut io_uring, input: &File, output: &File, size: u64) -> io::Result<()> {
+    let mut pending: VecDeque<Chunk> = (0..size)
+        .step_by(CHUNK_SIZE)
+        .map(|offset| Chunk {
+            offset,
+            len: CHUNK_SIZE.min((size - offset) as usize),
+            retries: 0,
+        })
+        .collect();
+
+    let mut pairs: Vec<Pair> = (0..PAIRS)
+        .map(|_| Pair {
+            chunk: Chunk {
+                offset: 0,
+                len: 0,
+                retries: 0,
+            },
+            buffer: vec![0; CHUNK_SIZE],
+            read: 0,
+            in_flight: false,
+        })
+        .collect();
+
+    loop {
+        // Start a read/write pair for every free buffer
+        for (index, pair) in pairs.iter_mut().enumerate() {
+            if pair.in_flight {
+                continue;
+            }
+            let Some(chunk) = pending.pop_front() else {
+                break;
+            };
+
+            pair.chunk = chunk;
+            pair.in_flight = true;
+            queue_pair(ring, input, output, index, pair)?;
+        }
+
+        if pairs.iter().all(|pair| !pair.in_flight) {
+            return Ok(());
+        }
+
+        let ret = unsafe { io_uring_submit(ring) };
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+
+        // Handle one completion, then go around to refill
+        let mut cqe: *mut io_uring_cqe = null_mut();
+        let ret = unsafe { io_uring_wait_cqe(ring, &mut cqe) };
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+
+        let (user_data, res) = unsafe { ((*cqe).user_data, (*cqe).res) };
+        unsafe { io_uring_cqe_seen(ring, cqe) };
+
+        let pair = &mut pairs[user_data as usize / 2];
+        let is_write = user_data % 2 == 1;
+
+        if !is_write {
+            if res < 0 {
+                return Err(io::Error::from_raw_os_error(-res));
+            } else if res == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Source ended early, at {}", pair.chunk.offset),
+                ));
+            }
+            pair.read = res as usize;
+            continue;
+        }
+
+        let chunk = pair.chunk;
+
+        if res == -(ECANCELED as i32) && pair.read > 0 && pair.read < chunk.len {
+            // The read came back short, so write what it got by itself
+            pending.push_back(chunk.rest(pair.read)?);
+            pair.chunk.len = pair.read;
+            queue_write(ring, output, user_data, pair)?;
+            continue;
+        }
+
+        pair.in_flight = false;
+
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        } else if res == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("Write at {} made no progress", chunk.offset),
+            ));
+        } else if (res as usize) < chunk.len {
+            pending.push_back(chunk.rest(res as usize)?);
+        }
+    }
+}
+
+/// Queues a linked read and write for a pair's chunk
+fn queue_pair(
+    ring: &mut io_uring,
+    input: &File,
+    output: &File,
+    index: usize,
+    pair: &mut Pair,
+) -> io::Result<()> {
+    let Chunk { offset, len, .. } = pair.chunk;
+    let buffer = pair.buffer.as_mut_ptr();
+    pair.read = 0;
+
+    unsafe {
+        if io_uring_sq_space_left(ring) < 2 {
+            return Err(io::Error::other("Submission queue is full"));
+        }
+        let read = io_uring_get_sqe(ring);
+
+        io_uring_prep_read(
+            read,
+            input.as_raw_fd(),
+            buffer as *mut _,
+            len as u32,
+            offset,
+        );
+        (*read).flags |= IOSQE_IO_LINK;
+        (*read).user_data = index as u64 * 2;
+    }
+
+    queue_write(ring, output, index as u64 * 2 + 1, pair)
+}
+
+/// Queues the write of a pair's chunk, from what's already in its buffer
+fn queue_write(ring: &mut io_uring, output: &File, user_data: u64, pair: &Pair) -> io::Result<()> {
+    let Chunk { offset, len, .. } = pair.chunk;
+
+    unsafe {
+        let write = io_uring_get_sqe(ring);
+        if write.is_null() {
+            return Err(io::Error::other("Submission queue is full"));
+        }
+
+        io_uring_prep_write(
+            write,
+            output.as_raw_fd(),
+            pair.buffer.as_ptr() as *const _,
+            len as u32,
+            offset,
+        );
+        (*write).user_data = user_data;
+    }
+
+    Ok(())
+}
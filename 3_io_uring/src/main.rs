@@ -7,6 +7,10 @@
 #[cfg(not(rust_analyzer))]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+mod bench;
+mod copy;
+
+use std::env;
 use std::io;
 use std::mem::zeroed;
 use std::ptr::null_mut;
@@ -74,6 +78,25 @@ fn wait_for_completion(ring: &mut io_uring) -> io::Result<()> {
 }
 
 fn main() -> io::Result<()> {
+    // Run the NOP benchmark or the file copy instead of the single NOP
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("bench") => {
+            let config = bench::Config::from_args(args)?;
+            return bench::run(&config);
+        }
+        Some("copy") => {
+            let (Some(src), Some(dst)) = (args.next(), args.next()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Usage: copy <src> <dst>",
+                ));
+            };
+            return copy::run(&src, &dst);
+        }
+        _ => {}
+    }
+
     let queue_depth: u32 = 1;
     let mut ring = setup_io_uring(queue_depth)?;
 
@@ -3,6 +3,12 @@ use std::path::PathBuf;
 use std::process::Command;
 
 fn main() {
+    // The raw-uring backend talks to the kernel directly, so there's nothing
+    // to generate or link.
+    if env::var_os("CARGO_FEATURE_RAW_URING").is_some() {
+        return;
+    }
+
     println!("cargo:rustc-link-search=native=/usr/lib");
     println!("cargo:rustc-link-lib=dylib=uring");
 
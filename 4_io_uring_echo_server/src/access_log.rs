@@ -0,0 +1,99 @@
+/// Access log
+///
+/// An append-only file (see --access-log) with a line for every connection
+/// that opens, closes or is rejected:
+///
+///     2026-10-16 09:06:40.659 open 127.0.0.1:52246 fd=6
+///     2026-10-16 09:06:40.762 close 127.0.0.1:52246 fd=6 in=11 out=11 secs=0.103 reason=peer-closed
+///     2026-10-16 09:06:41.020 reject 127.0.0.1:52250 fd=7
+///
+/// The lines are written through the same ring as the sockets, so writing
+/// the log never blocks the event loop. Only one write is in flight at a
+/// time, which keeps the lines in order, and whatever comes in meanwhile is
+/// collected and goes out with the next one. The file is opened with
+/// O_APPEND, so workers sharing it each append whole lines.
+///
+use crate::connection::{CloseReason, Connection};
+use crate::log;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+pub struct AccessLog {
+    file: File,
+    pending: Vec<u8>,
+    writing: bool,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file,
+            pending: Vec::new(),
+            writing: false,
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    pub fn opened(&mut self, fd: RawFd, connection: &Connection) {
+        let line = format!("open {} fd={}", peer(connection.peer), fd);
+        self.push(&line);
+    }
+
+    pub fn closed(&mut self, fd: RawFd, connection: &Connection, reason: CloseReason) {
+        let line = format!(
+            "close {} fd={} in={} out={} secs={:.3} reason={}",
+            peer(connection.peer),
+            fd,
+            connection.bytes_in,
+            connection.bytes_out,
+            connection.age().as_secs_f64(),
+            reason
+        );
+        self.push(&line);
+    }
+
+    pub fn rejected(&mut self, fd: RawFd, addr: Option<SocketAddr>) {
+        let line = format!("reject {} fd={}", peer(addr), fd);
+        self.push(&line);
+    }
+
+    /// Takes everything waiting to be written, unless a write is in flight
+    ///
+    /// The caller writes it and calls written once that completes.
+    ///
+    pub fn take_pending(&mut self) -> Option<Vec<u8>> {
+        if self.writing || self.pending.is_empty() {
+            return None;
+        }
+
+        self.writing = true;
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// Marks the write in flight as done
+    pub fn written(&mut self) {
+        self.writing = false;
+    }
+
+    fn push(&mut self, line: &str) {
+        self.pending.extend_from_slice(log::timestamp().as_bytes());
+        self.pending.push(b' ');
+        self.pending.extend_from_slice(line.as_bytes());
+        self.pending.push(b'\n');
+    }
+}
+
+fn peer(addr: Option<SocketAddr>) -> String {
+    match addr {
+        Some(addr) => addr.to_string(),
+        None => "unknown".to_string(),
+    }
+}
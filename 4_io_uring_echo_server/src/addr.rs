@@ -0,0 +1,145 @@
+/// Socket addresses
+///
+/// Conversions between std's SocketAddr and the C sockaddr the kernel fills in
+/// or reads. Only IPv4 and IPv6 are handled. The layouts of sockaddr_in and
+/// sockaddr_in6 are read straight out of a sockaddr_storage, which is large
+/// enough and aligned for either of them:
+///
+///     sockaddr_in:  family (2), port (2), address (4)
+///     sockaddr_in6: family (2), port (2), flowinfo (4), address (16), scope id (4)
+///
+/// The port and address are in network byte order, the rest in host order.
+///
+use crate::bindings::*;
+use std::mem::{size_of, zeroed};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::slice;
+
+const SOCKADDR_IN_LEN: usize = 16;
+const SOCKADDR_IN6_LEN: usize = 28;
+
+/// Reads an address the kernel filled in
+///
+/// The len is what the kernel wrote back to addrlen. Returns None for any
+/// family other than IPv4 and IPv6, or if len is too short for the family.
+///
+pub fn to_socket_addr(storage: &sockaddr_storage, len: socklen_t) -> Option<SocketAddr> {
+    let bytes = as_bytes(storage);
+    let len = len as usize;
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+
+    match storage.ss_family as u32 {
+        AF_INET if len >= SOCKADDR_IN_LEN => {
+            let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+        }
+        AF_INET6 if len >= SOCKADDR_IN6_LEN => {
+            let flowinfo = u32::from_ne_bytes(bytes[4..8].try_into().ok()?);
+            let ip: [u8; 16] = bytes[8..24].try_into().ok()?;
+            let scope_id = u32::from_ne_bytes(bytes[24..28].try_into().ok()?);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(ip),
+                port,
+                flowinfo,
+                scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Writes an address for the kernel to read
+///
+/// Returns the storage along with the length to pass as addrlen.
+///
+pub fn from_socket_addr(addr: &SocketAddr) -> (sockaddr_storage, socklen_t) {
+    let mut storage: sockaddr_storage = unsafe { zeroed() };
+    let bytes = as_bytes_mut(&mut storage);
+    let (family, len) = match addr {
+        SocketAddr::V4(v4) => {
+            bytes[4..8].copy_from_slice(&v4.ip().octets());
+            (AF_INET, SOCKADDR_IN_LEN)
+        }
+        SocketAddr::V6(v6) => {
+            bytes[4..8].copy_from_slice(&v6.flowinfo().to_ne_bytes());
+            bytes[8..24].copy_from_slice(&v6.ip().octets());
+            bytes[24..28].copy_from_slice(&v6.scope_id().to_ne_bytes());
+            (AF_INET6, SOCKADDR_IN6_LEN)
+        }
+    };
+    bytes[0..2].copy_from_slice(&(family as sa_family_t).to_ne_bytes());
+    bytes[2..4].copy_from_slice(&addr.port().to_be_bytes());
+
+    (storage, len as socklen_t)
+}
+
+/// Turns an IPv4-mapped IPv6 address back into the IPv4 one
+///
+/// A dual-stack listener sees IPv4 peers as ::ffff:a.b.c.d, which is really
+/// an IPv4 connection and reads better as one. Native IPv6 addresses,
+/// including ::1, are left alone.
+///
+pub fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// The size of sockaddr_storage, which is what addrlen starts out as
+pub fn storage_len() -> socklen_t {
+    size_of::<sockaddr_storage>() as socklen_t
+}
+
+fn as_bytes(storage: &sockaddr_storage) -> &[u8] {
+    unsafe {
+        slice::from_raw_parts(
+            storage as *const _ as *const u8,
+            size_of::<sockaddr_storage>(),
+        )
+    }
+}
+
+fn as_bytes_mut(storage: &mut sockaddr_storage) -> &mut [u8] {
+    unsafe {
+        slice::from_raw_parts_mut(storage as *mut _ as *mut u8, size_of::<sockaddr_storage>())
+    }
+}
+
+/// Accept slot
+///
+/// Somewhere for an accept to write the peer's address. Without one accept
+/// only gives us the new fd and we never learn who connected. Slots are boxed
+/// so the pointers given to the kernel stay valid while the slot is moved
+/// around, and have to be kept until the accept completes.
+///
+pub struct AcceptSlot {
+    addr: sockaddr_storage,
+    len: socklen_t,
+}
+
+impl AcceptSlot {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {
+            addr: unsafe { zeroed() },
+            len: storage_len(),
+        })
+    }
+
+    /// The addr and addrlen pointers to pass to an accept
+    pub fn as_mut_ptrs(&mut self) -> (*mut sockaddr, *mut socklen_t) {
+        self.len = storage_len();
+        (&mut self.addr as *mut _ as *mut _, &mut self.len)
+    }
+
+    /// The peer's address, once the accept has completed
+    ///
+    /// IPv4 peers of a dual-stack listener come back as plain IPv4.
+    ///
+    pub fn peer(&self) -> Option<SocketAddr> {
+        to_socket_addr(&self.addr, self.len).map(unmap)
+    }
+}
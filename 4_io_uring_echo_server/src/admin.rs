@@ -0,0 +1,173 @@
+/// Admin socket
+///
+/// An optional Unix socket (see --admin) for poking at a running server. It
+/// takes one command per line and answers each one:
+///
+///     stats       the connection table: fd, peer, bytes in and out, and how
+///                 long each connection has been idle
+///     kick <fd>   closes that connection
+///
+/// For example, with socat:
+///
+///     echo stats | socat - UNIX-CONNECT:/tmp/echo.sock
+///
+/// Admin connections go through the same ring as everything else (see
+/// EchoServer), so the server ends up with two listeners to accept on. They
+/// aren't counted as connections, and aren't subject to the timeouts or the
+/// connection limit.
+///
+use crate::connection::Connection;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How long a command may be, newline included
+const REQUEST_SIZE: usize = 256;
+
+/// The listener, which removes its socket file when dropped
+pub struct AdminSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl AdminSocket {
+    /// Listens at path
+    ///
+    /// A socket file left behind by a server that didn't get to clean up is
+    /// replaced. Anything else at path is left alone and binding fails.
+    ///
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for AdminSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// What an admin connection has sent so far
+///
+/// Zeroed up front so the kernel can receive straight into the part that
+/// isn't filled yet. Complete lines are taken off the front as commands.
+///
+pub struct AdminRequest {
+    data: Box<[u8; REQUEST_SIZE]>,
+    len: usize,
+}
+
+impl AdminRequest {
+    pub fn new() -> Self {
+        Self {
+            data: Box::new([0; REQUEST_SIZE]),
+            len: 0,
+        }
+    }
+
+    /// Where to receive into and how much room is left
+    pub fn spare(&mut self) -> (*mut u8, usize) {
+        (self.data[self.len..].as_mut_ptr(), REQUEST_SIZE - self.len)
+    }
+
+    /// Marks len more bytes as received
+    pub fn filled(&mut self, len: usize) {
+        self.len = (self.len + len).min(REQUEST_SIZE);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == REQUEST_SIZE
+    }
+
+    /// Takes the next complete line, without its newline
+    pub fn take_line(&mut self) -> Option<String> {
+        let newline = self.data[..self.len].iter().position(|&b| b == b'\n')?;
+        let line = String::from_utf8_lossy(&self.data[..newline])
+            .trim()
+            .to_string();
+
+        self.data.copy_within(newline + 1..self.len, 0);
+        self.len -= newline + 1;
+        Some(line)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Stats,
+    Kick(RawFd),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+
+        match (words.next(), words.next(), words.next()) {
+            (Some("stats"), None, _) => Ok(Command::Stats),
+            (Some("kick"), Some(fd), None) => fd
+                .parse()
+                .map(Command::Kick)
+                .map_err(|_| format!("Invalid fd: {}", fd)),
+            (Some("kick"), _, _) => Err("Usage: kick <fd>".to_string()),
+            _ => Err(format!("Unknown command: {}", s)),
+        }
+    }
+}
+
+/// The connection table, one connection per line in fd order
+pub fn stats_table(connections: &HashMap<RawFd, Connection>) -> String {
+    let mut fds: Vec<&RawFd> = connections.keys().collect();
+    fds.sort();
+
+    let mut table = format!(
+        "{:<6} {:<40} {:>12} {:>12} {:>8}\n",
+        "fd", "peer", "bytes in", "bytes out", "idle"
+    );
+    for fd in fds {
+        let connection = &connections[fd];
+        let peer = match connection.peer {
+            Some(peer) => peer.to_string(),
+            None => "unknown".to_string(),
+        };
+        let _ = writeln!(
+            table,
+            "{:<6} {:<40} {:>12} {:>12} {:>7.1}s",
+            fd,
+            peer,
+            connection.bytes_in,
+            connection.bytes_out,
+            connection.idle().as_secs_f64()
+        );
+    }
+    let _ = writeln!(table, "{} connections", connections.len());
+
+    table
+}
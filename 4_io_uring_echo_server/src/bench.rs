@@ -0,0 +1,390 @@
+/// Benchmarks
+///
+/// Small benchmarks for comparing different ways of doing the same I/O through
+/// the ring. Most run over a local socket pair, so what's being measured is the
+/// overhead of the ring and the copies rather than the network. The NAPI one is
+/// the exception, since busy polling is all about the network, and so is the
+/// receive one, which runs the echo server itself.
+///
+use crate::config::Config;
+use crate::echo_server::{EchoServer, SIGNAL_USER_DATA};
+use crate::iouring::IoUring;
+use crate::log::{self, Level};
+use crate::metrics::Metrics;
+use crate::signal::SIGINT;
+use std::io::{self, IoSliceMut, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BENCH_BUFFER_SIZE: usize = 64 * 1024;
+const BENCH_TOTAL_BYTES: usize = 1024 * 1024 * 1024;
+
+const PING_SIZE: usize = 64;
+const PING_ROUNDS: usize = 10_000;
+const PING_WARMUP: usize = 100;
+const NAPI_BUSY_POLL: Duration = Duration::from_micros(50);
+
+const ECHO_CONNECTIONS: usize = 8;
+const ECHO_ROUNDS: usize = 10_000;
+
+const WRITE: u64 = 0;
+const READ: u64 = 1;
+
+/// How the data is moved
+///
+/// Regular uses the same send/recv entries as the echo server while Fixed
+/// uses write_fixed/read_fixed against registered buffers.
+///
+#[derive(Clone, Copy)]
+enum Mode {
+    Regular,
+    Fixed,
+}
+
+/// Compare regular and fixed buffers
+///
+/// Pushes the same amount of data through a socket pair with each mode and
+/// prints the throughput of both.
+///
+pub fn fixed_buffers() -> io::Result<()> {
+    println!(
+        "Transferring {} MiB in {} KiB chunks",
+        BENCH_TOTAL_BYTES / (1024 * 1024),
+        BENCH_BUFFER_SIZE / 1024
+    );
+
+    for (name, mode) in [("send/recv", Mode::Regular), ("fixed", Mode::Fixed)] {
+        let elapsed = transfer(mode)?;
+        let mib = BENCH_TOTAL_BYTES as f64 / (1024.0 * 1024.0);
+
+        println!(
+            "{:<10} {:>8.3} s {:>10.1} MiB/s",
+            name,
+            elapsed.as_secs_f64(),
+            mib / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// Transfer data
+///
+/// Keeps one write and one read in flight at all times until everything has
+/// been read on the other end.
+///
+fn transfer(mode: Mode) -> io::Result<Duration> {
+    let (writer, reader) = UnixStream::pair()?;
+    let mut ring = IoUring::builder(8).build()?;
+
+    let mut write_buf = vec![0xABu8; BENCH_BUFFER_SIZE];
+    let mut read_buf = vec![0u8; BENCH_BUFFER_SIZE];
+
+    if let Mode::Fixed = mode {
+        ring.register_buffers(&[
+            IoSliceMut::new(&mut write_buf),
+            IoSliceMut::new(&mut read_buf),
+        ])?;
+    }
+
+    let (mut written, mut read) = (0, 0);
+    let (mut writing, mut reading) = (false, false);
+    let start = Instant::now();
+
+    while read < BENCH_TOTAL_BYTES {
+        let mut entry = ring.create_entry();
+
+        if !writing && written < BENCH_TOTAL_BYTES {
+            match mode {
+                Mode::Regular => entry.set_send(
+                    writer.as_raw_fd(),
+                    write_buf.as_ptr(),
+                    BENCH_BUFFER_SIZE,
+                    0,
+                    WRITE,
+                ),
+                Mode::Fixed => entry.set_write_fixed(
+                    writer.as_raw_fd(),
+                    write_buf.as_ptr(),
+                    BENCH_BUFFER_SIZE as u32,
+                    0,
+                    0,
+                    WRITE,
+                ),
+            }
+            writing = true;
+        }
+
+        if !reading {
+            match mode {
+                Mode::Regular => entry.set_receive(
+                    reader.as_raw_fd(),
+                    read_buf.as_mut_ptr(),
+                    BENCH_BUFFER_SIZE,
+                    0,
+                    READ,
+                ),
+                Mode::Fixed => entry.set_read_fixed(
+                    reader.as_raw_fd(),
+                    read_buf.as_mut_ptr(),
+                    BENCH_BUFFER_SIZE as u32,
+                    0,
+                    1,
+                    READ,
+                ),
+            }
+            reading = true;
+        }
+
+        ring.submit()?;
+
+        let cqe = ring.wait_completion()?;
+        if cqe.res < 0 {
+            return Err(io::Error::from_raw_os_error(-cqe.res));
+        }
+
+        if cqe.user_data == WRITE {
+            written += cqe.res as usize;
+            writing = false;
+        } else {
+            read += cqe.res as usize;
+            reading = false;
+        }
+    }
+
+    Ok(start.elapsed())
+}
+
+/// Compare round trip latency with and without NAPI busy polling
+///
+/// Sends small messages to an echo server one at a time and prints the
+/// latency percentiles with interrupts and with busy polling. Busy polling
+/// only does anything for sockets on a real network device, so this should
+/// be pointed at an echo server on another machine. Without an address it
+/// starts a plain echo server on loopback, which is only good as a baseline.
+///
+pub fn napi(addr: Option<&str>) -> io::Result<()> {
+    let addr = match addr {
+        Some(addr) => addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Address didn't resolve"))?,
+        None => spawn_echo_peer()?,
+    };
+
+    println!(
+        "{} round trips of {} bytes to {}",
+        PING_ROUNDS, PING_SIZE, addr
+    );
+
+    for (name, busy_poll) in [("interrupts", None), ("napi", Some(NAPI_BUSY_POLL))] {
+        let mut builder = IoUring::builder(8);
+        if let Some(busy_poll) = busy_poll {
+            builder = builder.napi(busy_poll, true);
+        }
+
+        // NAPI needs 6.9+, so carry on with just the baseline if it's missing
+        let mut ring = match builder.build() {
+            Ok(ring) => ring,
+            Err(err) if busy_poll.is_some() => {
+                println!("{:<10} unavailable: {}", name, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut latencies = ping_pong(&mut ring, addr)?;
+        latencies.sort();
+        let percentile =
+            |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize].as_micros();
+
+        println!(
+            "{:<10} p50 {:>6} us  p99 {:>6} us  p99.9 {:>6} us",
+            name,
+            percentile(0.5),
+            percentile(0.99),
+            percentile(0.999)
+        );
+    }
+
+    Ok(())
+}
+
+/// Compare one-shot and multishot receives in the echo server
+///
+/// Runs the echo server in each mode with its default timeouts and has a few
+/// connections ping pong small messages through it, then prints how many
+/// submission queue entries it took per echo. Accepts and closes are in
+/// there too, but with this many rounds they hardly count.
+///
+pub fn receive_modes() -> io::Result<()> {
+    log::set_level(Level::Warn);
+    println!(
+        "{} connections each echoing {} messages of {} bytes",
+        ECHO_CONNECTIONS, ECHO_ROUNDS, PING_SIZE
+    );
+
+    for (name, multishot) in [("one-shot", false), ("multishot", true)] {
+        let start = Instant::now();
+        let metrics = run_echo_server(multishot)?;
+        let elapsed = start.elapsed();
+        let echoes = (ECHO_CONNECTIONS * ECHO_ROUNDS) as f64;
+
+        println!(
+            "{:<10} {:>8} SQEs, {:.2} per echo, {:.0} echoes/s",
+            name,
+            metrics.submitted,
+            metrics.submitted as f64 / echoes,
+            echoes / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs an echo server until the connections are done with it
+///
+/// The server runs as a worker on its own thread, so it can be stopped the
+/// way workers are, by posting a signal to its ring.
+///
+fn run_echo_server(multishot: bool) -> io::Result<Metrics> {
+    let config = Config {
+        address: Ipv4Addr::LOCALHOST.into(),
+        port: 0,
+        multishot,
+        ..Config::default()
+    };
+    let (ready, listening) = mpsc::channel();
+
+    let server = thread::spawn(move || -> io::Result<Metrics> {
+        let mut server = EchoServer::worker(&config, 0)?;
+        let _ = ready.send((server.local_addr()?, server.ring_fd()));
+        server.run()?;
+        Ok(server.metrics())
+    });
+
+    let (addr, ring_fd): (SocketAddr, RawFd) = match listening.recv() {
+        Ok(listening) => listening,
+        Err(_) => {
+            return match server.join() {
+                Ok(Err(err)) => Err(err),
+                _ => Err(io::Error::other("echo server didn't start")),
+            }
+        }
+    };
+
+    let clients: Vec<_> = (0..ECHO_CONNECTIONS)
+        .map(|_| thread::spawn(move || echo_rounds(addr)))
+        .collect();
+    for client in clients {
+        client
+            .join()
+            .map_err(|_| io::Error::other("client panicked"))??;
+    }
+
+    let mut ring = IoUring::builder(2).build()?;
+    ring.create_entry()
+        .set_msg_ring(ring_fd, SIGINT as u32, SIGNAL_USER_DATA, true, 0);
+    ring.submit()?;
+
+    server
+        .join()
+        .map_err(|_| io::Error::other("echo server panicked"))?
+}
+
+/// Sends messages one at a time and waits for each echo
+fn echo_rounds(addr: SocketAddr) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+
+    let ping = [0xABu8; PING_SIZE];
+    let mut pong = [0u8; PING_SIZE];
+    for _ in 0..ECHO_ROUNDS {
+        stream.write_all(&ping)?;
+        stream.read_exact(&mut pong)?;
+    }
+
+    Ok(())
+}
+
+/// Ping pong
+///
+/// Each round queues the send and the receive together and waits for both,
+/// then keeps receiving until the whole message has come back.
+///
+fn ping_pong(ring: &mut IoUring, addr: SocketAddr) -> io::Result<Vec<Duration>> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let fd = stream.as_raw_fd();
+
+    let ping = [0xABu8; PING_SIZE];
+    let mut pong = [0u8; PING_SIZE];
+    let mut latencies = Vec::with_capacity(PING_ROUNDS);
+
+    for round in 0..PING_WARMUP + PING_ROUNDS {
+        let start = Instant::now();
+
+        ring.create_entry()
+            .set_send(fd, ping.as_ptr(), PING_SIZE, 0, WRITE);
+        ring.create_entry()
+            .set_receive(fd, pong.as_mut_ptr(), PING_SIZE, 0, READ);
+        ring.submit()?;
+
+        let (mut sent, mut received) = (false, 0);
+        while !sent || received < PING_SIZE {
+            let cqe = ring.wait_completion()?;
+            if cqe.res < 0 {
+                return Err(io::Error::from_raw_os_error(-cqe.res));
+            }
+            if cqe.res == 0 && cqe.user_data == READ {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            if cqe.user_data == WRITE {
+                sent = true;
+            } else {
+                received += cqe.res as usize;
+                if received < PING_SIZE {
+                    let rest = &mut pong[received..];
+                    ring.create_entry()
+                        .set_receive(fd, rest.as_mut_ptr(), rest.len(), 0, READ);
+                    ring.submit()?;
+                }
+            }
+        }
+
+        if round >= PING_WARMUP {
+            latencies.push(start.elapsed());
+        }
+    }
+
+    Ok(latencies)
+}
+
+/// Starts an echo server on loopback
+///
+/// A plain blocking one on its own threads, so that it doesn't share
+/// anything with the ring being measured.
+///
+fn spawn_echo_peer() -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let _ = stream.set_nodelay(true);
+                if let Ok(mut reader) = stream.try_clone() {
+                    let mut writer = stream;
+                    let _ = io::copy(&mut reader, &mut writer);
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
@@ -0,0 +1,330 @@
+/// Benchmark comparison
+///
+/// Puts the io_uring echo server and the thread-per-connection baseline (see
+/// threads_baseline) through the same loads, and prints what loadgen got out
+/// of each side by side:
+///
+///     cargo build --release --features raw-uring --bins
+///     ./target/release/bench_compare -c 1,10,100 -t 5
+///
+/// The servers and loadgen are run from the directory this binary is in, so
+/// all three come from the same build. Every load gets freshly started
+/// servers on a free port, which are stopped with SIGINT once loadgen is
+/// done, giving the io_uring one its graceful shutdown.
+///
+use std::env;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: bench_compare [options]
+
+Runs loadgen against io_uring_tcp and threads_baseline and compares them.
+Build all the binaries first, e.g. with
+
+    cargo build --release --features raw-uring --bins
+
+Options:
+  -c, --connections <list> Connection counts to try, comma separated
+                           (default 1,10,100)
+  -s, --size <n>           Bytes per message (default 64)
+  -t, --time <s>           Seconds to run each load for (default 5)
+  -h, --help               Print this message
+";
+
+const SIGINT: c_int = 2;
+
+/// How long a server gets to start listening
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+extern "C" {
+    fn kill(pid: c_int, sig: c_int) -> c_int;
+}
+
+/// A server to compare, and how to run it quietly
+struct Server {
+    name: &'static str,
+    binary: &'static str,
+    args: &'static [&'static str],
+}
+
+/// The baseline comes first, so the io_uring server can be compared to it
+const SERVERS: [Server; 2] = [
+    Server {
+        name: "threads",
+        binary: "threads_baseline",
+        args: &[],
+    },
+    Server {
+        name: "io_uring",
+        binary: "io_uring_tcp",
+        args: &["-q"],
+    },
+];
+
+#[derive(Debug, Clone)]
+struct Options {
+    connections: Vec<usize>,
+    size: usize,
+    time: u64,
+}
+
+impl Options {
+    fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut options = Options {
+            connections: vec![1, 10, 100],
+            size: 64,
+            time: 5,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+            match arg.as_str() {
+                "-c" | "--connections" => {
+                    let list = value()?;
+                    options.connections = list
+                        .split(',')
+                        .map(|count| parse(&arg, count.to_string()))
+                        .collect::<Result<_, _>>()?;
+                }
+                "-s" | "--size" => options.size = parse(&arg, value()?)?,
+                "-t" | "--time" => options.time = parse(&arg, value()?)?,
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        if options.connections.contains(&0) || options.size == 0 || options.time == 0 {
+            return Err("Connections, size and time must be non-zero".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+/// What loadgen reported for one run
+#[derive(Debug, Default)]
+struct Measurement {
+    rate: f64,
+    p50: u64,
+    p99: u64,
+    p999: u64,
+    /// Anything that went wrong, like mismatched or lost echoes, or failed
+    /// connections
+    problems: Vec<String>,
+}
+
+impl Measurement {
+    /// Picks the numbers out of loadgen's report
+    fn parse(report: &str) -> Self {
+        let mut measurement = Measurement::default();
+
+        for line in report.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let after = |label: &str| {
+                let index = words.iter().position(|word| *word == label)?;
+                words.get(index + 1)?.parse().ok()
+            };
+
+            if line.contains(" msg/s") {
+                measurement.rate = words
+                    .get(2)
+                    .and_then(|rate| rate.parse().ok())
+                    .unwrap_or(0.0);
+            } else if line.starts_with("latency") {
+                measurement.p50 = after("p50").unwrap_or(0);
+                measurement.p99 = after("p99").unwrap_or(0);
+                measurement.p999 = after("p99.9").unwrap_or(0);
+            } else if line.ends_with("echoes didn't match") {
+                if words.first() != Some(&"0") {
+                    measurement.problems.push(line.to_string());
+                }
+            } else if line.contains("connections failed") || line.ends_with("never came back") {
+                measurement.problems.push(line.to_string());
+            }
+        }
+
+        measurement
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    let options = match Options::from_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = compare(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs every load against every server, printing each row as it's done
+fn compare(options: &Options) -> io::Result<()> {
+    let dir = binary_dir()?;
+
+    println!(
+        "{} byte messages, {}s per run\n",
+        options.size, options.time
+    );
+    println!(
+        "{:>11}  {:<9} {:>10} {:>9} {:>9} {:>9} {:>11}",
+        "connections", "server", "msg/s", "p50 us", "p99 us", "p99.9 us", "vs threads"
+    );
+
+    let mut problems = Vec::new();
+    for &connections in &options.connections {
+        let mut baseline = None;
+
+        for server in &SERVERS {
+            let measurement = measure(&dir, server, connections, options)?;
+            let relative = match baseline {
+                Some(rate) if rate > 0.0 => format!("{:.2}x", measurement.rate / rate),
+                _ => String::new(),
+            };
+            baseline.get_or_insert(measurement.rate);
+
+            println!(
+                "{:>11}  {:<9} {:>10.0} {:>9} {:>9} {:>9} {:>11}",
+                connections,
+                server.name,
+                measurement.rate,
+                measurement.p50,
+                measurement.p99,
+                measurement.p999,
+                relative
+            );
+            for problem in measurement.problems {
+                problems.push(format!(
+                    "{} with {} connections: {}",
+                    server.name, connections, problem
+                ));
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        println!();
+        for problem in problems {
+            println!("{}", problem);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the servers and loadgen are, which is next to this binary
+fn binary_dir() -> io::Result<PathBuf> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    for binary in SERVERS
+        .iter()
+        .map(|server| server.binary)
+        .chain(["loadgen"])
+    {
+        if !dir.join(binary).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} isn't in {}, build it with: cargo build --release --features raw-uring --bins",
+                    binary,
+                    dir.display()
+                ),
+            ));
+        }
+    }
+
+    Ok(dir)
+}
+
+/// Starts the server, runs loadgen against it and stops it again
+fn measure(
+    dir: &Path,
+    server: &Server,
+    connections: usize,
+    options: &Options,
+) -> io::Result<Measurement> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, free_port()?));
+    let mut child = Command::new(dir.join(server.binary))
+        .args(server.args)
+        .args(["-a", "127.0.0.1", "-p", &addr.port().to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let result = wait_until_listening(&mut child, addr).and_then(|()| {
+        Command::new(dir.join("loadgen"))
+            .args(["-c", &connections.to_string()])
+            .args(["-s", &options.size.to_string()])
+            .args(["-t", &options.time.to_string()])
+            .arg(addr.to_string())
+            .stderr(Stdio::inherit())
+            .output()
+    });
+
+    unsafe { kill(child.id() as c_int, SIGINT) };
+    child.wait()?;
+
+    let output = result?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "loadgen failed against {}: {}",
+            server.name, output.status
+        )));
+    }
+    Ok(Measurement::parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// A port nothing is listening on right now
+///
+/// Something else could take it before the server gets to it, but on a
+/// machine that's quiet enough to benchmark on that's unlikely.
+///
+fn free_port() -> io::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Waits for the server to take connections, or to give up trying
+fn wait_until_listening(child: &mut Child, addr: SocketAddr) -> io::Result<()> {
+    let start = Instant::now();
+
+    loop {
+        if TcpStream::connect(addr).is_ok() {
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!("Server exited with {}", status)));
+        }
+        if start.elapsed() > START_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Server didn't start listening on {}", addr),
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,295 @@
+/// Load generator
+///
+/// Puts an echo server under load and reports what it managed. Every
+/// connection gets its own thread that sends a message, waits for the whole
+/// echo to come back and checks it, over and over until the time is up:
+///
+///     cargo run --release --bin loadgen -- -c 50 -s 64 -r 1000 -t 10
+///
+/// Blocking sockets on plain threads keep the client simple and independent
+/// of the ring it's measuring, at the cost of a thread per connection.
+///
+/// With a rate, each connection sends on a fixed schedule and the latency is
+/// measured from when a message was due rather than when it went out. A
+/// server that falls behind then shows up in the latencies instead of just
+/// quietly slowing the client down.
+///
+/// An echo that hasn't come back a second after the time is up never will,
+/// e.g. because the server dropped it. That round trip counts as failed, and
+/// its connection stops there, since whatever it reads next would be out of
+/// step.
+///
+use std::env;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long after the end of the run echoes still get to come back
+const GRACE: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "\
+Usage: loadgen [options] [addr]
+
+Sends messages to the echo server at addr (default 127.0.0.1:8080) and
+reports throughput and round trip latencies.
+
+Options:
+  -c, --connections <n>    Connections to open at once (default 10)
+  -s, --size <n>           Bytes per message (default 64)
+  -r, --rate <n>           Messages per second per connection, 0 for as
+                           fast as each echo comes back (default 0)
+  -t, --time <s>           Seconds to run for (default 10)
+      --lines              End every message with a newline, for a server
+                           echoing complete lines (its -l); the size has
+                           to fit in the server's buffers
+      --no-verify          Don't check that echoes match, e.g. against a
+                           server with a transform
+  -h, --help               Print this message
+";
+
+#[derive(Debug, Clone)]
+struct Options {
+    addr: SocketAddr,
+    connections: usize,
+    size: usize,
+    rate: u32,
+    time: Duration,
+    lines: bool,
+    verify: bool,
+}
+
+impl Options {
+    fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut addr = "127.0.0.1:8080".to_string();
+        let mut options = Options {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            connections: 10,
+            size: 64,
+            rate: 0,
+            time: Duration::from_secs(10),
+            lines: false,
+            verify: true,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+            match arg.as_str() {
+                "-c" | "--connections" => options.connections = parse(&arg, value()?)?,
+                "-s" | "--size" => options.size = parse(&arg, value()?)?,
+                "-r" | "--rate" => options.rate = parse(&arg, value()?)?,
+                "-t" | "--time" => options.time = Duration::from_secs(parse(&arg, value()?)?),
+                "--lines" => options.lines = true,
+                "--no-verify" => options.verify = false,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+                _ => addr = arg,
+            }
+        }
+
+        if options.connections == 0 || options.size == 0 {
+            return Err("Connections and size must be non-zero".to_string());
+        }
+        options.addr = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or(format!("Invalid address: {}", addr))?;
+
+        Ok(options)
+    }
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+/// What one connection got done
+#[derive(Debug, Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    mismatches: u64,
+    timeouts: u64,
+    error: Option<String>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    let options = match Options::from_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    println!(
+        "{} connections sending {} byte messages to {} for {}s",
+        options.connections,
+        options.size,
+        options.addr,
+        options.time.as_secs()
+    );
+
+    // Everyone starts together, once all the threads are up
+    let start = Instant::now() + Duration::from_millis(100);
+    let handles: Vec<_> = (0..options.connections)
+        .map(|id| {
+            let options = options.clone();
+            thread::spawn(move || run_connection(&options, id, start))
+        })
+        .collect();
+
+    let results: Vec<Results> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap_or_default())
+        .collect();
+
+    report(&options, &results);
+}
+
+/// Runs one connection until the time is up or something goes wrong
+fn run_connection(options: &Options, id: usize, start: Instant) -> Results {
+    let mut results = Results::default();
+
+    if let Err(err) = round_trips(options, id, start, &mut results) {
+        results.error = Some(err.to_string());
+    }
+    results
+}
+
+fn round_trips(
+    options: &Options,
+    id: usize,
+    start: Instant,
+    results: &mut Results,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect(options.addr)?;
+    stream.set_nodelay(true)?;
+
+    let interval = match options.rate {
+        0 => None,
+        rate => Some(Duration::from_secs(1) / rate),
+    };
+    let mut message = vec![0u8; options.size];
+    let mut echo = vec![0u8; options.size];
+
+    thread::sleep(start.saturating_duration_since(Instant::now()));
+    let end = start + options.time;
+    let mut due = start;
+
+    for round in 0u64.. {
+        let sent_at = match interval {
+            Some(interval) => {
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+                let at = due;
+                due += interval;
+                at
+            }
+            None => Instant::now(),
+        };
+        if sent_at >= end {
+            break;
+        }
+
+        fill(&mut message, id, round, options.lines);
+        stream.write_all(&message)?;
+
+        let left = (end + GRACE).saturating_duration_since(Instant::now());
+        stream.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        match stream.read_exact(&mut echo) {
+            Ok(()) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                results.timeouts += 1;
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+        results.latencies.push(sent_at.elapsed());
+
+        if options.verify && echo != message {
+            results.mismatches += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills a message with bytes that differ between connections and rounds,
+/// so an echo that went to the wrong connection or came back stale is caught
+///
+/// As a line, the message has its only newline at the end.
+///
+fn fill(message: &mut [u8], id: usize, round: u64, line: bool) {
+    let seed = (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ round;
+    for (i, byte) in message.iter_mut().enumerate() {
+        *byte = (seed.wrapping_add(i as u64).wrapping_mul(31) >> 3) as u8;
+        if line && *byte == b'\n' {
+            *byte = b' ';
+        }
+    }
+
+    if line {
+        if let Some(last) = message.last_mut() {
+            *last = b'\n';
+        }
+    }
+}
+
+fn report(options: &Options, results: &[Results]) {
+    let mut latencies: Vec<Duration> = results
+        .iter()
+        .flat_map(|result| result.latencies.iter().copied())
+        .collect();
+    latencies.sort();
+
+    let failed: Vec<&String> = results.iter().filter_map(|r| r.error.as_ref()).collect();
+    let mismatches: u64 = results.iter().map(|result| result.mismatches).sum();
+    let timeouts: u64 = results.iter().map(|result| result.timeouts).sum();
+    let messages = latencies.len() as f64;
+    let secs = options.time.as_secs_f64();
+
+    println!(
+        "{} messages, {:.0} msg/s, {:.1} MiB/s each way",
+        latencies.len(),
+        messages / secs,
+        messages * options.size as f64 / secs / (1024.0 * 1024.0)
+    );
+
+    if !latencies.is_empty() {
+        let percentile =
+            |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize].as_micros();
+        println!(
+            "latency p50 {} us  p90 {} us  p99 {} us  p99.9 {} us  max {} us",
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(0.999),
+            percentile(1.0)
+        );
+    }
+
+    if options.verify {
+        println!("{} echoes didn't match", mismatches);
+    }
+    if timeouts > 0 {
+        println!("{} echoes never came back", timeouts);
+    }
+    if !failed.is_empty() {
+        println!(
+            "{} of {} connections failed, e.g. {}",
+            failed.len(),
+            results.len(),
+            failed[0]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+/// Threads baseline
+///
+/// The classic way to write an echo server, to measure the io_uring one
+/// against: blocking sockets and a thread for every connection, each reading
+/// into its own buffer and writing back whatever it got.
+///
+///     cargo run --release --bin threads_baseline -- -p 9000
+///
+/// It sets TCP_NODELAY and uses the same buffer size as io_uring_tcp by
+/// default, so the two see the same traffic, but has none of its limits or
+/// timeouts. It runs until it's killed. See bench_compare for putting the two
+/// side by side.
+///
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::thread;
+
+const USAGE: &str = "\
+Usage: threads_baseline [options]
+
+Echo server with a thread per connection, for comparing against
+io_uring_tcp.
+
+Options:
+  -a, --address <ip>       Address to bind to (default 0.0.0.0)
+  -p, --port <port>        Port to listen on (default 8080)
+  -b, --buffer-size <n>    Bytes per connection buffer (default 1024)
+  -h, --help               Print this message
+";
+
+#[derive(Debug, Clone)]
+struct Options {
+    addr: SocketAddr,
+    buffer_size: usize,
+}
+
+impl Options {
+    fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut address = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        let mut port = 8080;
+        let mut buffer_size = 1024;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+            match arg.as_str() {
+                "-a" | "--address" => address = parse(&arg, value()?)?,
+                "-p" | "--port" => port = parse(&arg, value()?)?,
+                "-b" | "--buffer-size" => buffer_size = parse(&arg, value()?)?,
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        if buffer_size == 0 {
+            return Err("Buffer size must be non-zero".to_string());
+        }
+
+        Ok(Options {
+            addr: SocketAddr::new(address, port),
+            buffer_size,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+
+    let options = match Options::from_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let listener = TcpListener::bind(options.addr)?;
+    eprintln!("Echo server listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let buffer_size = options.buffer_size;
+                thread::spawn(move || {
+                    if let Err(err) = echo(stream, buffer_size) {
+                        eprintln!("Connection failed: {}", err);
+                    }
+                });
+            }
+            // Out of fds or the like, which the next accept may not be
+            Err(err) => eprintln!("Accept failed: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Echoes everything back until the peer closes
+///
+/// Then shuts down the write side, the same as the io_uring server does. A
+/// reset is just another way for the peer to go.
+///
+fn echo(mut stream: TcpStream, buffer_size: usize) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut buffer = vec![0u8; buffer_size];
+
+    loop {
+        let len = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if is_disconnect(&err) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        match stream.write_all(&buffer[..len]) {
+            Ok(()) => {}
+            Err(err) if is_disconnect(&err) => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let _ = stream.shutdown(Shutdown::Write);
+    Ok(())
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+    )
+}
@@ -0,0 +1,229 @@
+/// Buffer pool
+///
+/// Fixed-size buffers carved out of a single allocation, checked out for an
+/// operation and checked back in once it's done. Unlike a buffer ring the
+/// kernel doesn't know about the pool; we pick the buffer when preparing the
+/// entry, so it works with any operation and any kernel. It replaces
+/// allocating a fresh Vec for every connection or read.
+///
+/// A checked out PooledBuffer is a handle to one of the buffers. Handles can't
+/// be copied, so a buffer can't be given to two operations at once, and
+/// dropping one checks its buffer back in. That way a buffer goes back
+/// whichever way the operation holding it ends, without every error path
+/// having to remember to return it. The memory is shared by the pool and
+/// its handles, and freed once the last of them is dropped, so a handle
+/// that outlives its pool still points at live memory. The kernel isn't
+/// counted though: the ring has to be done with a buffer before it goes.
+///
+/// The buffers can optionally be aligned, e.g. to 512 or 4096 bytes for files
+/// opened with O_DIRECT, and can be registered as fixed buffers in one go,
+/// with each buffer's index in the pool as its fixed buffer index.
+///
+use crate::iouring::IoUring;
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::cell::RefCell;
+use std::io::{self, IoSliceMut};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+/// The allocation, shared by the pool and its handles
+///
+/// free holds the indexes of the buffers not checked out, so handles can put
+/// themselves back.
+///
+struct Memory {
+    ptr: *mut u8,
+    layout: Layout,
+    free: RefCell<Vec<u32>>,
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+pub struct BufferPool {
+    memory: Rc<Memory>,
+    buffer_size: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool of count buffers of buffer_size bytes
+    pub fn new(count: u32, buffer_size: usize) -> io::Result<Self> {
+        Self::with_alignment(count, buffer_size, 1)
+    }
+
+    /// Creates a pool whose buffers all start on an align boundary
+    ///
+    /// The alignment has to be a power of two, and the buffer size a multiple
+    /// of it so that every buffer after the first is aligned too.
+    ///
+    pub fn with_alignment(count: u32, buffer_size: usize, align: usize) -> io::Result<Self> {
+        if !align.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Alignment must be a power of two",
+            ));
+        }
+        if count == 0 || buffer_size == 0 || !buffer_size.is_multiple_of(align) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Buffer size must be a non-zero multiple of the alignment",
+            ));
+        }
+
+        let layout = (count as usize)
+            .checked_mul(buffer_size)
+            .and_then(|size| Layout::from_size_align(size, align).ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Buffer pool is too large")
+            })?;
+
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "Failed to allocate buffer pool",
+            ));
+        }
+
+        Ok(Self {
+            memory: Rc::new(Memory {
+                ptr,
+                layout,
+                // Reversed so buffers are handed out from index 0 up
+                free: RefCell::new((0..count).rev().collect()),
+            }),
+            buffer_size,
+        })
+    }
+
+    /// Checks out a buffer
+    ///
+    /// Returns None if every buffer is in use. The buffer starts out empty
+    /// with room for buffer_size bytes.
+    ///
+    pub fn checkout(&mut self) -> Option<PooledBuffer> {
+        let index = self.memory.free.borrow_mut().pop()?;
+
+        Some(PooledBuffer {
+            ptr: unsafe { self.memory.ptr.add(index as usize * self.buffer_size) },
+            index,
+            len: 0,
+            capacity: self.buffer_size,
+            memory: Rc::clone(&self.memory),
+        })
+    }
+
+    /// Registers every buffer in the pool as a fixed buffer
+    ///
+    /// A buffer's index in the pool is then its buf_index for read_fixed and
+    /// write_fixed.
+    ///
+    #[allow(dead_code)]
+    pub fn register(&mut self, ring: &mut IoUring) -> io::Result<()> {
+        let slices: Vec<IoSliceMut> = (0..self.count())
+            .map(|index| unsafe {
+                let ptr = self.memory.ptr.add(index as usize * self.buffer_size);
+                IoSliceMut::new(slice::from_raw_parts_mut(ptr, self.buffer_size))
+            })
+            .collect();
+
+        ring.register_buffers(&slices)
+    }
+
+    /// Total number of buffers
+    pub fn count(&self) -> u32 {
+        (self.memory.layout.size() / self.buffer_size) as u32
+    }
+
+    /// Number of buffers available to check out
+    #[allow(dead_code)]
+    pub fn available(&self) -> usize {
+        self.memory.free.borrow().len()
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+}
+
+/// A buffer checked out of a BufferPool
+///
+/// Holds how many bytes of it are in use, which is what as_slice returns. The
+/// pointers are what go into an entry: as_mut_ptr with capacity for a
+/// receive, as_ptr with len for a send. So it has to be kept until the
+/// operation completes, since dropping it hands the buffer to the next
+/// checkout.
+///
+pub struct PooledBuffer {
+    ptr: *mut u8,
+    index: u32,
+    len: usize,
+    capacity: usize,
+    memory: Rc<Memory>,
+}
+
+impl PooledBuffer {
+    /// The buffer's index in its pool, and its fixed buffer index if the pool
+    /// is registered
+    #[allow(dead_code)]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets how many bytes are in use, e.g. after a receive completes
+    ///
+    /// Clamped to the capacity.
+    ///
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.capacity);
+    }
+
+    /// Appends as much of data as fits and returns how much that was
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(self.capacity - self.len);
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(self.len), count);
+        }
+        self.len += count;
+        count
+    }
+
+    #[allow(dead_code)]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.memory.free.borrow_mut().push(self.index);
+    }
+}
@@ -0,0 +1,134 @@
+/// Buffer ring
+///
+/// A pool of fixed-size buffers that is handed to the kernel up front. Instead
+/// of allocating a buffer for every receive, the receive is submitted without
+/// one and the kernel picks a free buffer from the ring when data actually
+/// arrives. The completion tells us which buffer it used (see Cqe::buffer_id),
+/// and once we're done with the data we give that buffer back with recycle.
+///
+/// This requires a 5.19+ kernel and liburing 2.4+.
+///
+use crate::bindings::*;
+use std::io;
+
+pub struct BufferRing {
+    ring: *mut io_uring_buf_ring,
+    storage: Vec<u8>,
+    group_id: u16,
+    count: u16,
+    buffer_size: usize,
+}
+
+impl BufferRing {
+    /// Registers a new buffer ring
+    ///
+    /// The count has to be a power of two since the kernel indexes the ring
+    /// with a mask. All of the buffers are carved out of a single allocation
+    /// and added to the ring straight away.
+    ///
+    pub fn new(
+        ring: &mut io_uring,
+        group_id: u16,
+        count: u16,
+        buffer_size: usize,
+    ) -> io::Result<Self> {
+        if !count.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Buffer count must be a power of two",
+            ));
+        }
+
+        let mut ret = 0;
+        let buf_ring =
+            unsafe { io_uring_setup_buf_ring(ring, count as u32, group_id as i32, 0, &mut ret) };
+
+        if buf_ring.is_null() {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+
+        let mut buffers = Self {
+            ring: buf_ring,
+            storage: vec![0u8; count as usize * buffer_size],
+            group_id,
+            count,
+            buffer_size,
+        };
+
+        for id in 0..count {
+            buffers.add(id, id as i32);
+        }
+        unsafe { io_uring_buf_ring_advance(buffers.ring, count as i32) };
+
+        Ok(buffers)
+    }
+
+    /// The group id to pass to a receive that selects its own buffer
+    pub fn group_id(&self) -> u16 {
+        self.group_id
+    }
+
+    /// The size of each buffer in the ring
+    #[allow(dead_code)]
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Reads a buffer
+    ///
+    /// Returns the first len bytes of the buffer with the given id, where len
+    /// is usually the res of the completion that used it.
+    ///
+    pub fn get(&self, id: u16, len: usize) -> &[u8] {
+        let start = id as usize * self.buffer_size;
+        &self.storage[start..start + len.min(self.buffer_size)]
+    }
+
+    /// Gives a buffer back to the kernel
+    ///
+    /// Until this is called the kernel won't pick the buffer again. If every
+    /// buffer is checked out, receives waiting on this group fail with
+    /// -ENOBUFS.
+    ///
+    pub fn recycle(&mut self, id: u16) {
+        self.add(id, 0);
+        unsafe { io_uring_buf_ring_advance(self.ring, 1) };
+    }
+
+    /// Unregisters the buffer ring
+    ///
+    /// This has to be done before the io_uring instance it was registered
+    /// with goes away.
+    ///
+    pub fn free(self, ring: &mut io_uring) -> io::Result<()> {
+        let ret = unsafe {
+            io_uring_free_buf_ring(ring, self.ring, self.count as u32, self.group_id as i32)
+        };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Adds a buffer to the ring
+    ///
+    /// The buffer is placed offset slots past the current tail and isn't
+    /// visible to the kernel until the ring is advanced past it.
+    ///
+    fn add(&mut self, id: u16, offset: i32) {
+        let start = id as usize * self.buffer_size;
+        let addr = self.storage[start..].as_mut_ptr();
+
+        unsafe {
+            io_uring_buf_ring_add(
+                self.ring,
+                addr as *mut _,
+                self.buffer_size as u32,
+                id,
+                io_uring_buf_ring_mask(self.count as u32),
+                offset,
+            );
+        }
+    }
+}
@@ -0,0 +1,384 @@
+/// Config
+///
+/// The echo server's settings, read from the command line. The parsing is done
+/// by hand since there are only a handful of options; each one takes its value
+/// either as the next argument or after an equals sign:
+///
+///     io_uring_tcp --port 9000 --buffer-size=4096 -v
+///
+/// The more lasting ones can also go in a TOML file (see Config::from_file),
+/// which is read first so the command line can override it:
+///
+///     io_uring_tcp --config echo.toml --port 9001
+///
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::fault::FaultConfig;
+use crate::log::Level;
+use crate::socket::SocketOptions;
+use crate::toml;
+use crate::transform::Pipeline;
+
+pub const USAGE: &str = "\
+Usage: io_uring_tcp [options]
+       io_uring_tcp bench-fixed
+       io_uring_tcp bench-napi [addr]
+       io_uring_tcp bench-recv
+
+Options:
+      --config <path>      Read settings from a TOML file, which the
+                           options given here override
+  -a, --address <ip>       Address to bind to, :: or [::] for IPv6 and
+                           IPv4 both (default 0.0.0.0)
+      --ipv6-only          Don't take IPv4 connections on an IPv6
+                           address
+  -p, --port <port>        Port to listen on (default 8080)
+  -d, --queue-depth <n>    Submission queue entries (default 256)
+  -b, --buffer-size <n>    Bytes per connection buffer (default 1024)
+  -i, --idle-timeout <s>   Close connections idle this many seconds,
+                           0 to never close them (default 60)
+      --send-timeout <s>   Close connections that don't read an echo
+                           within this many seconds, 0 to wait forever
+                           (default 30)
+  -m, --max-connections <n>
+                           Reject connections beyond this many, per
+                           worker with --workers (default no limit)
+  -s, --stats <s>          Print a stats line every this many seconds
+  -k, --keepalive <s>      Probe connections idle this many seconds, and
+                           every this many seconds after (default off)
+      --nagle              Leave Nagle's algorithm on (TCP_NODELAY is
+                           set by default)
+  -l, --lines              Echo complete lines only, holding on to the
+                           start of a line until its newline arrives
+  -t, --transform <list>   Transform the echo: identity, upper, reverse or
+                           delay:<ms>, comma separated to chain them
+      --oneshot            Queue a receive for every message instead of
+                           keeping a multishot receive armed
+  -w, --workers <n>        Threads to run, each with its own ring and a
+                           listener sharing the port (default 1)
+      --fault-delay <ms>   Stall up to this long before handling each
+                           completion
+      --fault-drop <pct>   Drop this percentage of sends, losing their
+                           data but keeping their connections open
+      --fault-close <pct>  Close connections on this percentage of
+                           receives instead of echoing
+      --admin <path>       Take admin commands (stats, kick <fd>) on a
+                           Unix socket at path, with .<n> added for
+                           each worker
+      --access-log <path>  Append a line to this file for every connection
+                           opened, closed or rejected
+  -v, --verbose            Also log every read and send
+  -q, --quiet              Only log warnings and errors
+  -h, --help               Print this message
+";
+
+/// How the echo is framed
+///
+/// Raw sends back whatever each receive got, as it arrived. Lines holds on to
+/// bytes until a newline arrives and sends back complete lines only, so a line
+/// that comes in over several receives goes back in one piece. A line can be
+/// at most the buffer size long.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Raw,
+    Lines,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub address: IpAddr,
+    pub v6_only: bool,
+    pub port: u16,
+    pub queue_depth: u32,
+    pub buffer_size: usize,
+    pub idle_timeout: Option<Duration>,
+    pub send_timeout: Option<Duration>,
+    pub max_connections: Option<usize>,
+    pub socket_options: SocketOptions,
+    pub stats_interval: Option<Duration>,
+    pub framing: Framing,
+    pub transform: Pipeline,
+    pub multishot: bool,
+    pub workers: usize,
+    pub faults: FaultConfig,
+    pub admin_socket: Option<PathBuf>,
+    pub access_log: Option<PathBuf>,
+    pub log_level: Level,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            v6_only: false,
+            port: 8080,
+            queue_depth: 256,
+            buffer_size: 1024,
+            idle_timeout: Some(Duration::from_secs(60)),
+            send_timeout: Some(Duration::from_secs(30)),
+            max_connections: None,
+            socket_options: SocketOptions::default(),
+            stats_interval: None,
+            framing: Framing::Raw,
+            transform: Pipeline::default(),
+            multishot: true,
+            workers: 1,
+            faults: FaultConfig::default(),
+            admin_socket: None,
+            access_log: None,
+            log_level: Level::Info,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the arguments, not including the program name
+    ///
+    /// Anything not given keeps its default, or its value in the --config
+    /// file when there is one. The sizes have to be non-zero, anything else
+    /// about them is left for the ring and the pool to check.
+    ///
+    pub fn from_args<I>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = match config_file(&args)? {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Split --name=value, otherwise the value is the next argument
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let value = || inline.or_else(|| args.next());
+
+            match name.as_str() {
+                // Already read, before any of the other options
+                "--config" => {
+                    value();
+                }
+                "-a" | "--address" => config.address = parse_address(&name, value())?,
+                "--ipv6-only" => config.v6_only = true,
+                "-p" | "--port" => config.port = parse(&name, value())?,
+                "-d" | "--queue-depth" => config.queue_depth = parse_non_zero(&name, value())?,
+                "-b" | "--buffer-size" => config.buffer_size = parse_non_zero(&name, value())?,
+                "-i" | "--idle-timeout" => config.idle_timeout = parse_timeout(&name, value())?,
+                "--send-timeout" => config.send_timeout = parse_timeout(&name, value())?,
+                "-m" | "--max-connections" => {
+                    config.max_connections = Some(parse_non_zero(&name, value())?);
+                }
+                "-s" | "--stats" => {
+                    let secs: u64 = parse_non_zero(&name, value())?;
+                    config.stats_interval = Some(Duration::from_secs(secs));
+                }
+                "-k" | "--keepalive" => {
+                    let secs: u64 = parse_non_zero(&name, value())?;
+                    config.socket_options.keepalive = Some(Duration::from_secs(secs));
+                }
+                "--nagle" => config.socket_options.nodelay = false,
+                "-l" | "--lines" => config.framing = Framing::Lines,
+                "-t" | "--transform" => config.transform = parse(&name, value())?,
+                "--oneshot" => config.multishot = false,
+                "-w" | "--workers" => config.workers = parse_non_zero(&name, value())?,
+                "--fault-delay" => {
+                    let ms: u64 = parse_non_zero(&name, value())?;
+                    config.faults.delay = Some(Duration::from_millis(ms));
+                }
+                "--fault-drop" => config.faults.drop_percent = parse_percent(&name, value())?,
+                "--fault-close" => config.faults.close_percent = parse_percent(&name, value())?,
+                "--admin" => config.admin_socket = Some(parse(&name, value())?),
+                "--access-log" => config.access_log = Some(parse(&name, value())?),
+                "-v" | "--verbose" => config.log_level = Level::Debug,
+                "-q" | "--quiet" => config.log_level = Level::Warn,
+                _ => return Err(ConfigError::UnknownOption(name)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads settings from a TOML file
+    ///
+    /// The file has a table for each group of settings. Every key is
+    /// optional, and takes the same values as the option it stands for:
+    ///
+    ///     [listen]
+    ///     address = "::"          # --address
+    ///     port = 9000             # --port
+    ///     ipv6-only = false       # --ipv6-only
+    ///
+    ///     [buffers]
+    ///     size = 4096             # --buffer-size
+    ///     queue-depth = 512       # --queue-depth
+    ///
+    ///     [limits]
+    ///     max-connections = 1000  # --max-connections
+    ///     workers = 4             # --workers
+    ///
+    ///     [timeouts]
+    ///     idle = 60               # --idle-timeout
+    ///     send = 30               # --send-timeout
+    ///
+    ///     [log]
+    ///     level = "info"          # error, warn, info or debug
+    ///     stats = 10              # --stats
+    ///     access-log = "echo.log" # --access-log
+    ///
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let file_error = |message: String| ConfigError::File {
+            path: path.to_path_buf(),
+            message,
+        };
+        let text = fs::read_to_string(path).map_err(|err| file_error(err.to_string()))?;
+        let entries = toml::parse(&text).map_err(|err| file_error(err.to_string()))?;
+
+        let mut config = Self::default();
+        for entry in entries {
+            config
+                .set(&entry.key, entry.value.to_string())
+                .map_err(|err| file_error(format!("line {}: {}", entry.line, err)))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Sets the setting for a key in the config file
+    fn set(&mut self, key: &str, value: String) -> Result<(), ConfigError> {
+        let value = Some(value);
+
+        match key {
+            "listen.address" => self.address = parse_address(key, value)?,
+            "listen.port" => self.port = parse(key, value)?,
+            "listen.ipv6-only" => self.v6_only = parse(key, value)?,
+            "buffers.size" => self.buffer_size = parse_non_zero(key, value)?,
+            "buffers.queue-depth" => self.queue_depth = parse_non_zero(key, value)?,
+            "limits.max-connections" => self.max_connections = Some(parse_non_zero(key, value)?),
+            "limits.workers" => self.workers = parse_non_zero(key, value)?,
+            "timeouts.idle" => self.idle_timeout = parse_timeout(key, value)?,
+            "timeouts.send" => self.send_timeout = parse_timeout(key, value)?,
+            "log.level" => self.log_level = parse(key, value)?,
+            "log.stats" => {
+                let secs: u64 = parse_non_zero(key, value)?;
+                self.stats_interval = Some(Duration::from_secs(secs));
+            }
+            "log.access-log" => self.access_log = Some(parse(key, value)?),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String },
+    UnknownKey(String),
+    File { path: PathBuf, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownOption(option) => write!(f, "Unknown option: {}", option),
+            ConfigError::MissingValue(option) => write!(f, "Missing value for {}", option),
+            ConfigError::InvalidValue { option, value } => {
+                write!(f, "Invalid value for {}: {}", option, value)
+            }
+            ConfigError::UnknownKey(key) => write!(f, "Unknown key: {}", key),
+            ConfigError::File { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Finds the --config file, so it can be read before the other options
+///
+/// When there's more than one, the last one counts.
+///
+fn config_file(args: &[String]) -> Result<Option<PathBuf>, ConfigError> {
+    let mut path = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            path = Some(parse(arg, args.next().cloned())?);
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(parse("--config", Some(value.to_string()))?);
+        }
+    }
+
+    Ok(path)
+}
+
+fn parse<T: FromStr>(option: &str, value: Option<String>) -> Result<T, ConfigError> {
+    let value = value.ok_or_else(|| ConfigError::MissingValue(option.to_string()))?;
+
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        option: option.to_string(),
+        value,
+    })
+}
+
+/// Parses an IP address, which may be in brackets like [::1]
+fn parse_address(option: &str, value: Option<String>) -> Result<IpAddr, ConfigError> {
+    let value = value.ok_or_else(|| ConfigError::MissingValue(option.to_string()))?;
+    let unbracketed = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(&value);
+
+    unbracketed.parse().map_err(|_| ConfigError::InvalidValue {
+        option: option.to_string(),
+        value: value.clone(),
+    })
+}
+
+fn parse_non_zero<T>(option: &str, value: Option<String>) -> Result<T, ConfigError>
+where
+    T: FromStr + Default + PartialEq,
+{
+    let parsed: T = parse(option, value.clone())?;
+
+    if parsed == T::default() {
+        return Err(ConfigError::InvalidValue {
+            option: option.to_string(),
+            value: value.unwrap_or_default(),
+        });
+    }
+    Ok(parsed)
+}
+
+/// Parses a number of seconds, where 0 means no timeout
+fn parse_timeout(option: &str, value: Option<String>) -> Result<Option<Duration>, ConfigError> {
+    let secs: u64 = parse(option, value)?;
+
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
+fn parse_percent(option: &str, value: Option<String>) -> Result<u8, ConfigError> {
+    let percent: u8 = parse(option, value.clone())?;
+
+    if percent > 100 {
+        return Err(ConfigError::InvalidValue {
+            option: option.to_string(),
+            value: value.unwrap_or_default(),
+        });
+    }
+    Ok(percent)
+}
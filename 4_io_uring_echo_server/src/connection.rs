@@ -0,0 +1,140 @@
+/// Connection
+///
+/// What the echo server knows about an accepted connection. One is created
+/// when the accept completes and kept, keyed by fd, until the close for it
+/// completes; only then can the kernel hand the same fd out again.
+///
+/// The connection owns its socket until a close is queued for it (see
+/// take_fd). One that's dropped before then, like when the server stops on
+/// an error, closes the socket itself rather than leaking it.
+///
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::io::{IntoRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Connection {
+    socket: Option<OwnedFd>,
+    pub peer: Option<SocketAddr>,
+    pub opened: Instant,
+    pub last_active: Instant,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// The peer has shut down its side, so once the echo is out we're done
+    pub read_closed: bool,
+    /// Why we're closing it, once we are
+    pub close_reason: Option<CloseReason>,
+}
+
+impl Connection {
+    pub fn new(socket: OwnedFd, peer: Option<SocketAddr>) -> Self {
+        let now = Instant::now();
+        Self {
+            socket: Some(socket),
+            peer,
+            opened: now,
+            last_active: now,
+            bytes_in: 0,
+            bytes_out: 0,
+            read_closed: false,
+            close_reason: None,
+        }
+    }
+
+    /// Hands the socket over to a close on the ring
+    ///
+    /// Returns None once it's been handed over, so a connection is only ever
+    /// closed once.
+    ///
+    pub fn take_fd(&mut self) -> Option<RawFd> {
+        self.socket.take().map(IntoRawFd::into_raw_fd)
+    }
+
+    /// How long the connection has been open
+    pub fn age(&self) -> Duration {
+        self.opened.elapsed()
+    }
+
+    /// How long since anything was received or sent
+    pub fn idle(&self) -> Duration {
+        self.last_active.elapsed()
+    }
+
+    pub fn received(&mut self, len: u64) {
+        self.bytes_in += len;
+        self.last_active = Instant::now();
+    }
+
+    pub fn sent(&mut self, len: u64) {
+        self.bytes_out += len;
+        self.last_active = Instant::now();
+    }
+
+    /// Bytes received but not echoed yet
+    pub fn unsent(&self) -> u64 {
+        self.bytes_in - self.bytes_out
+    }
+}
+
+/// Connection Display implementation
+///
+/// A one line summary, meant for when the connection closes.
+///
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "{}", peer)?,
+            None => write!(f, "unknown peer")?,
+        }
+
+        write!(
+            f,
+            ", {} bytes in, {} bytes out over {:.1}s",
+            self.bytes_in,
+            self.bytes_out,
+            self.age().as_secs_f64()
+        )
+    }
+}
+
+/// Why a connection was closed
+///
+/// The first reason given is the one that sticks, since whatever follows is
+/// only a consequence of it (see EchoServer::add_close).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    PeerClosed,
+    Reset,
+    Idle,
+    SendTimeout,
+    Stalled,
+    LineTooLong,
+    NoBuffer,
+    Rejected,
+    Kicked,
+    Fault,
+    Shutdown,
+    Error,
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CloseReason::PeerClosed => "peer-closed",
+            CloseReason::Reset => "reset",
+            CloseReason::Idle => "idle",
+            CloseReason::SendTimeout => "send-timeout",
+            CloseReason::Stalled => "stalled",
+            CloseReason::LineTooLong => "line-too-long",
+            CloseReason::NoBuffer => "no-buffer",
+            CloseReason::Rejected => "rejected",
+            CloseReason::Kicked => "kicked",
+            CloseReason::Fault => "fault",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::Error => "error",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,57 @@
+/// Cqe
+///
+/// A completion queue entry copied out of the ring. The raw io_uring_cqe lives
+/// in memory shared with the kernel and gets reused once it's marked as seen,
+/// so everything the wrapper returns is one of these instead.
+///
+use crate::bindings::*;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cqe {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+impl Cqe {
+    /// Copies the fields we care about out of a raw entry
+    pub fn from_raw(cqe: &io_uring_cqe) -> Self {
+        Self {
+            user_data: cqe.user_data,
+            res: cqe.res,
+            flags: cqe.flags,
+        }
+    }
+
+    /// Checks if more completions will follow
+    ///
+    /// Multishot operations set IORING_CQE_F_MORE on every completion except
+    /// the last one.
+    ///
+    pub fn has_more(&self) -> bool {
+        self.flags & IORING_CQE_F_MORE != 0
+    }
+
+    /// Gets the provided buffer used by a completion
+    ///
+    /// When the kernel picked the buffer itself (buffer select), it sets
+    /// IORING_CQE_F_BUFFER and stores the buffer id in the upper 16 bits of
+    /// the flags.
+    ///
+    pub fn buffer_id(&self) -> Option<u16> {
+        if self.flags & IORING_CQE_F_BUFFER != 0 {
+            Some((self.flags >> IORING_CQE_BUFFER_SHIFT) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Checks if a completion is a zero-copy notification
+    ///
+    /// These follow a zero-copy send and signal that the kernel no longer
+    /// needs the buffer. Their res is always 0.
+    ///
+    pub fn is_notification(&self) -> bool {
+        self.flags & IORING_CQE_F_NOTIF != 0
+    }
+}
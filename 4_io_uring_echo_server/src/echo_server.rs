@@ -3,28 +3,159 @@
 /// This echo server is based on on bindings to the Linux liburing library (see
 /// build.rs). It will only work if the liburing library has been installed.
 ///
-use crate::bindings::*;
-use crate::iouring::IoUring;
+use crate::access_log::AccessLog;
+use crate::addr::AcceptSlot;
+use crate::admin::{self, AdminRequest, AdminSocket, Command};
+use crate::bindings::__kernel_timespec;
+use crate::buffer_pool::{BufferPool, PooledBuffer};
+use crate::buffer_ring::BufferRing;
+use crate::config::{Config, Framing};
+use crate::connection::{CloseReason, Connection};
+use crate::cqe::Cqe;
+use crate::entry::timespec;
+use crate::error::{Op, UringError};
+use crate::fault::Faults;
+use crate::iouring::{CqOverflow, IoUring};
+use crate::log::{self, debug, error, info, warning, Level};
+use crate::metrics::{Metrics, StatsReporter};
+use crate::signal::{signal_name, signal_number, SignalFd, SIGINFO_SIZE, SIGINT, SIGTERM};
+use crate::slab::Slab;
+use crate::socket::{self, SocketOptions};
+use crate::transform::Pipeline;
+use multishot::{Stream, BUFFER_GROUP};
 use std::collections::HashMap;
 use std::io;
-use std::net::TcpListener;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::ptr;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
-const QUEUE_DEPTH: u32 = 256;
-const BUFFER_SIZE: usize = 1024;
+mod multishot;
+
+const BUFFER_COUNT: u32 = 1024;
+const BATCH_SIZE: usize = 64;
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The slab never hands out this key, so completions of our cancel entries are
+// simply ignored.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+/// What a connection over the limit is told before it's closed
+const REJECT_MESSAGE: &[u8] = b"Too many connections\n";
+
+/// The user_data of a signal forwarded to a worker
+///
+/// Workers don't read the signalfd themselves. The main thread posts each
+/// signal to every worker's ring with this as the user_data and the signal
+/// number as the result (see workers.rs). The slab never hands it out either.
+///
+pub const SIGNAL_USER_DATA: u64 = u64::MAX - 1;
 
 /// Operation types
 ///
 /// This defines the operation types we'll be using. This setup leaves it open
-/// to easily adding more. Note, both Receive and Send the location of the
-/// operation's associated buffer.
+/// to easily adding more. Each one holds on to whatever the kernel needs to
+/// stay put until the operation completes.
 ///
 enum Operation {
-    Accept,
-    Receive(*mut u8),
-    Send(*mut u8),
+    /// Holds the slot the kernel writes the peer's address into
+    Accept(Box<AcceptSlot>),
+    Receive(PooledBuffer),
+    /// Stays armed for as long as the connection is open, and carries
+    /// nothing since the kernel picks the buffers (see multishot.rs)
+    ReceiveMultishot,
+    // Only held so the timespec stays put until the entry is submitted
+    /// Linked to a Receive
+    IdleTimeout(#[allow(dead_code)] Box<__kernel_timespec>),
+    /// Linked to a Send
+    SendTimeout(#[allow(dead_code)] Box<__kernel_timespec>),
+    /// Sends the buffer from offset up to end
+    ///
+    /// end is only short of the buffer's length when the rest is an
+    /// unfinished line (see Framing). offset moves up with every short send
+    /// (see handle_send), and dropped is set when fault injection swapped the
+    /// send for a NOP.
+    ///
+    Send {
+        buffer: PooledBuffer,
+        offset: usize,
+        end: usize,
+        dropped: bool,
+    },
+    /// Shuts down the write side ahead of a Close
+    Shutdown,
+    Close,
+    /// Sends REJECT_MESSAGE to a connection we're about to close
+    Reject,
+    /// Holds on to a received buffer while a transform holds the echo back
+    /// (see transform.rs)
+    Delay {
+        buffer: PooledBuffer,
+        end: usize,
+        // Only held so the timespec stays put until the entry is submitted
+        #[allow(dead_code)]
+        ts: Box<__kernel_timespec>,
+    },
+    /// A read of the signalfd
+    Signal(Box<[u8; SIGINFO_SIZE]>),
+    /// Tells us when to print stats
+    StatsTimer(#[allow(dead_code)] Box<__kernel_timespec>),
+    /// Looks for idle connections when receives are multishot
+    IdleSweep(#[allow(dead_code)] Box<__kernel_timespec>),
+    /// This and the Admin operations after it are the same for connections
+    /// to the admin socket, which get their own request buffer and send back
+    /// a response (see admin.rs)
+    AdminAccept,
+    AdminReceive(AdminRequest),
+    AdminSend {
+        request: AdminRequest,
+        response: Vec<u8>,
+        offset: usize,
+    },
+    AdminClose,
+    /// Holds on to the access log lines being written, and how many of them
+    /// are already written (see access_log.rs)
+    LogWrite {
+        data: Vec<u8>,
+        offset: usize,
+    },
+}
+
+impl Operation {
+    /// Whether a connection with a multishot receive waits on this before
+    /// it can be closed
+    fn tracked(&self) -> bool {
+        matches!(
+            self,
+            Operation::ReceiveMultishot | Operation::Send { .. } | Operation::Delay { .. }
+        )
+    }
+
+    /// What to report in errors for this operation
+    fn kind(&self) -> Op {
+        match self {
+            Operation::Accept(_) => Op::Accept,
+            Operation::Receive(_) => Op::Receive,
+            Operation::ReceiveMultishot => Op::Receive,
+            Operation::IdleTimeout(_) => Op::Timeout,
+            Operation::SendTimeout(_) => Op::Timeout,
+            Operation::Send { .. } => Op::Send,
+            Operation::Shutdown => Op::Shutdown,
+            Operation::Close => Op::Close,
+            Operation::Reject => Op::Send,
+            Operation::Delay { .. } => Op::Timeout,
+            Operation::Signal(_) => Op::Read,
+            Operation::StatsTimer(_) => Op::Timeout,
+            Operation::IdleSweep(_) => Op::Timeout,
+            Operation::AdminAccept => Op::Accept,
+            Operation::AdminReceive(_) => Op::Receive,
+            Operation::AdminSend { .. } => Op::Send,
+            Operation::AdminClose => Op::Close,
+            Operation::LogWrite { .. } => Op::Write,
+        }
+    }
 }
 
 /// Operation data
@@ -37,92 +168,539 @@ struct OperationData {
     fd: RawFd,
 }
 
+/// Where we are in shutting down
+///
+/// The first signal starts draining: no more accepts or receives, but sends
+/// already in flight get to finish. Once nothing but the signal read is left,
+/// or the drain takes too long, or a second signal arrives, whatever is left
+/// gets cancelled. When the last completion is in we're done.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    Draining(Instant),
+    Cancelling,
+}
+
 /// Echo serer
 ///
 /// Holds the ring, the primary TcpListener (this could alternatively be
-/// represented by a file descriptor, but this makes it easier). Lastly, we have
-/// our operations look up which uses a unique u64 id for each queue entry that
-/// is match to our operation data.
+/// represented by a file descriptor, but this makes it easier), the open
+/// connections and everything we need to serve them. Each operation in flight
+/// has an entry in the operations slab, whose unique u64 key goes in the
+/// entry's user_data.
 ///
 pub struct EchoServer {
     ring: IoUring,
     listener: TcpListener,
-    operations: HashMap<u64, OperationData>,
-    next_id: u64,
+    operations: Slab<OperationData>,
+    /// The open connections, keyed by their fd
+    connections: HashMap<RawFd, Connection>,
+    /// What multishot receives need to know about each connection
+    streams: HashMap<RawFd, Stream>,
+    /// Where the connection buffers come from
+    pool: BufferPool,
+    /// The buffer ring multishot receives pick from, if we're using them
+    buffers: Option<BufferRing>,
+    /// Where we hear about shutdown from, which a worker has none of (see
+    /// SIGNAL_USER_DATA)
+    signals: Option<SignalFd>,
+    /// Which worker we are, if we're one
+    worker: Option<usize>,
+    state: State,
+    idle_timeout: Option<Duration>,
+    /// How long a connection may take to read an echo
+    send_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    socket_options: SocketOptions,
+    framing: Framing,
+    transform: Pipeline,
+    faults: Faults,
+    admin: Option<AdminSocket>,
+    access_log: Option<AccessLog>,
+    metrics: Metrics,
+    /// How often to report the metrics
+    stats_interval: Option<Duration>,
+    reporter: StatsReporter,
 }
 
 impl EchoServer {
     /// Create a new server instance
     ///
-    /// This will create a non-blocking TcpListener and the io-uring queue. The
-    /// fd_map will be used to track connections.
+    /// This will create a non-blocking TcpListener and the io-uring queue, both
+    /// set up from the config. The connections map will be used to track
+    /// connections.
+    /// If the kernel supports it the ring's fd is registered, which makes every
+    /// submit a little cheaper. SIGINT and SIGTERM are blocked from here on and
+    /// read from a signalfd instead (see signal.rs), so the server has to be
+    /// created before any other threads are started.
+    ///
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let addr = SocketAddr::new(config.address, config.port);
+        let listener = socket::listener(addr, false, config.v6_only)?;
+        let signals = SignalFd::new(&[SIGINT, SIGTERM])?;
+
+        Self::with_listener(config, listener, Some(signals), None)
+    }
+
+    /// Create one of several workers sharing a port
     ///
-    pub fn new(port: u16) -> io::Result<Self> {
-        let listener = TcpListener::bind(("0.0.0.0", port))?;
+    /// Each worker has its own ring and its own listener on the same address,
+    /// bound with SO_REUSEPORT so the kernel spreads connections across them
+    /// (see socket.rs). A worker hears about signals from the main thread
+    /// rather than reading them itself, which is why it should be created on
+    /// the thread that runs it.
+    ///
+    pub fn worker(config: &Config, id: usize) -> io::Result<Self> {
+        let addr = SocketAddr::new(config.address, config.port);
+        let listener = socket::listener(addr, true, config.v6_only)?;
+
+        Self::with_listener(config, listener, None, Some(id))
+    }
+
+    fn with_listener(
+        config: &Config,
+        listener: TcpListener,
+        signals: Option<SignalFd>,
+        worker: Option<usize>,
+    ) -> io::Result<Self> {
         listener.set_nonblocking(true)?;
-        let ring = IoUring::new(QUEUE_DEPTH)?;
+        let mut ring = IoUring::builder(config.queue_depth).build()?;
+
+        if ring.capabilities().registered_ring {
+            ring.register_ring_fd()?;
+        }
+
+        let capabilities = ring.capabilities();
+        let buffers = if !config.multishot {
+            None
+        } else if capabilities.multishot_recv && capabilities.buffer_rings {
+            Some(ring.register_buffer_ring(
+                BUFFER_GROUP,
+                BUFFER_COUNT as u16,
+                config.buffer_size,
+            )?)
+        } else {
+            warning!("Multishot receives aren't supported, falling back to one-shot");
+            None
+        };
+
+        // Workers each get their own, since each has its own connections
+        let admin = match (&config.admin_socket, worker) {
+            (Some(path), None) => Some(AdminSocket::bind(path)?),
+            (Some(path), Some(id)) => {
+                let mut path = path.clone().into_os_string();
+                path.push(format!(".{}", id));
+                Some(AdminSocket::bind(path.as_ref())?)
+            }
+            (None, _) => None,
+        };
+        let access_log = match &config.access_log {
+            Some(path) => Some(AccessLog::open(path)?),
+            None => None,
+        };
 
         Ok(Self {
             ring,
             listener,
-            operations: HashMap::new(),
-            next_id: 0,
+            operations: Slab::new(),
+            connections: HashMap::new(),
+            streams: HashMap::new(),
+            pool: BufferPool::new(BUFFER_COUNT, config.buffer_size)?,
+            buffers,
+            signals,
+            worker,
+            state: State::Running,
+            idle_timeout: config.idle_timeout,
+            send_timeout: config.send_timeout,
+            max_connections: config.max_connections,
+            socket_options: config.socket_options,
+            framing: config.framing,
+            transform: config.transform.clone(),
+            faults: Faults::new(config.faults),
+            admin,
+            access_log,
+            metrics: Metrics::default(),
+            stats_interval: config.stats_interval,
+            reporter: StatsReporter::new(),
         })
     }
 
+    /// The address we ended up listening on
+    ///
+    /// Differs from the config when it asked for port 0.
+    ///
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// What we've done so far
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// The ring's fd, for forwarding signals to a worker
+    pub fn ring_fd(&self) -> RawFd {
+        self.ring.ring_fd()
+    }
+
+    /// Post a completion to another ring
+    ///
+    /// Lets a worker tell the main thread it's done once run returns. Nothing
+    /// waits on our side of it.
+    ///
+    pub fn notify(&mut self, ring_fd: RawFd, len: u32, data: u64) -> io::Result<()> {
+        self.ring
+            .create_entry()
+            .set_msg_ring(ring_fd, len, data, true, CANCEL_USER_DATA);
+        self.ring.submit()?;
+        Ok(())
+    }
+
     /// Run the server
     ///
-    /// When run, we first add the listener to the shared memory space, then we
-    /// submit it to the queue, after which we start looping.  The queue is
-    /// peeked for completions which are then handled.
+    /// When run, we report what the kernel supports and the size of the ring we
+    /// got (unless we're told to be quiet), and then add the listener and the
+    /// signalfd to the shared memory space, then we submit them to the queue,
+    /// after which we start looping. The queue is drained of completions in
+    /// batches which are then handled.
     ///
-    /// The sleep is to keep us from hammering too hard.
+    /// Once the queue is empty we submit whatever the handlers queued up and
+    /// block in the kernel until something completes (see wait). We return
+    /// once a signal has shut us down and every operation has completed, at
+    /// which point nothing refers to our buffers anymore and the ring can be
+    /// dropped.
     ///
     pub fn run(&mut self) -> io::Result<()> {
+        // Workers all share the one kernel, so only the first reports on it
+        if log::enabled(Level::Info) && self.worker.unwrap_or(0) == 0 {
+            let report = format!("{}\n{}", self.ring.capabilities(), self.ring.params());
+            for line in report.lines() {
+                info!("{}", line);
+            }
+        }
+        if self.faults.is_enabled() && self.worker.unwrap_or(0) == 0 {
+            warning!("Injecting faults: {:?}", self.faults.config());
+        }
+
+        if let Some(admin) = &self.admin {
+            info!("Admin socket listening on {}", admin.path().display());
+        }
+
         self.add_accept()?;
-        self.ring.submit()?;
+        self.add_admin_accept()?;
+        self.add_signal_read(Box::new([0; SIGINFO_SIZE]))?;
+        self.add_stats_timer()?;
+        self.add_idle_sweep()?;
+        self.submit()?;
+
+        let mut cqes = [Cqe::default(); BATCH_SIZE];
+        let mut overflow = CqOverflow::default();
 
         loop {
-            match self.ring.peek_completion() {
-                Some(cqe) => self.handle_completion(cqe)?,
-                None => {
-                    self.ring.submit()?;
-                    std::thread::sleep(Duration::from_millis(1));
+            let count = self.ring.peek_batch(&mut cqes);
+
+            if count == 0 {
+                if self.check_shutdown() {
+                    if let Some(buffers) = self.buffers.take() {
+                        self.ring.unregister_buffer_ring(buffers)?;
+                    }
+                    match self.worker {
+                        Some(id) => info!("Worker {} shut down cleanly", id),
+                        None => info!("Shut down cleanly"),
+                    }
+                    return Ok(());
                 }
+
+                self.check_overflow(&mut overflow)?;
+                self.wait()?;
+                continue;
+            }
+
+            for cqe in &cqes[..count] {
+                self.handle_completion(*cqe)?;
             }
         }
     }
 
+    /// Wait for completions
+    ///
+    /// Submits and sleeps in the kernel until at least one completion is
+    /// ready, which costs nothing while idle and wakes us as soon as there's
+    /// work. While draining we only wait until the drain runs out of time, so
+    /// that check_shutdown gets to see it. A completion the timed wait hands
+    /// back is handled right away; the rest are picked up by the next batch.
+    ///
+    fn wait(&mut self) -> io::Result<()> {
+        let result = match self.state {
+            State::Draining(started) => {
+                let left = SHUTDOWN_TIMEOUT.saturating_sub(started.elapsed());
+                self.submit()?;
+                match self.ring.wait_completion_timeout(left) {
+                    Ok(Some(cqe)) => self.handle_completion(cqe),
+                    Ok(None) => Ok(()),
+                    Err(err) => Err(err),
+                }
+            }
+            _ => self.ring.submit_and_wait(1).map(|submitted| {
+                self.metrics.submitted += submitted as u64;
+            }),
+        };
+
+        // The signals we care about come in through the signalfd, but others
+        // (like a SIGSTOP and SIGCONT) can still interrupt the wait
+        match result {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Submit, counting the entries that went in
+    fn submit(&mut self) -> io::Result<()> {
+        let submitted = self.ring.submit()?;
+        self.metrics.submitted += submitted as u64;
+        Ok(())
+    }
+
+    /// Check for completion queue overflow
+    ///
+    /// Warns whenever the overflow state changes and flushes any completions
+    /// the kernel is holding on to so that no connection is left waiting on
+    /// one.
+    ///
+    fn check_overflow(&mut self, last: &mut CqOverflow) -> io::Result<()> {
+        let overflow = self.ring.cq_overflow();
+
+        if overflow.pending {
+            self.ring.flush_cq_overflow()?;
+        }
+
+        if overflow != *last {
+            warning!(
+                "Completion queue overflow: pending {}, dropped {}, invalid submissions {}",
+                overflow.pending,
+                overflow.dropped,
+                overflow.invalid_sqes
+            );
+            *last = overflow;
+        }
+
+        Ok(())
+    }
+
+    /// Check how shutting down is going
+    ///
+    /// Moves on to cancelling once the drain is done or out of time, and
+    /// returns true once there's nothing left in flight.
+    ///
+    fn check_shutdown(&mut self) -> bool {
+        let started = match self.state {
+            State::Running => return false,
+            State::Draining(started) => started,
+            State::Cancelling => return self.operations.is_empty(),
+        };
+
+        // A worker has no signal read, so for it the drain is done once
+        // nothing at all is left
+        let signal_only = self
+            .operations
+            .iter()
+            .all(|(_, data)| matches!(data.op, Operation::Signal(_)));
+
+        if signal_only || started.elapsed() >= SHUTDOWN_TIMEOUT {
+            self.cancel_all();
+        }
+        self.operations.is_empty()
+    }
+
+    /// Start shutting down
+    ///
+    /// Stops accepting and cancels every receive, leaving the sends to finish.
+    /// The stats timer and idle sweep are cancelled too, since they would
+    /// otherwise keep the drain going until it runs out of time, and so is
+    /// everything waiting on the admin socket.
+    ///
+    fn start_shutdown(&mut self) {
+        self.state = State::Draining(Instant::now());
+
+        let targets: Vec<u64> = self
+            .operations
+            .iter()
+            .filter(|(_, data)| {
+                matches!(
+                    data.op,
+                    Operation::Accept(_)
+                        | Operation::Receive(_)
+                        | Operation::ReceiveMultishot
+                        | Operation::StatsTimer(_)
+                        | Operation::IdleSweep(_)
+                        | Operation::AdminAccept
+                        | Operation::AdminReceive(_)
+                )
+            })
+            .map(|(key, _)| key)
+            .collect();
+
+        for target in targets {
+            self.ring
+                .create_entry()
+                .set_cancel(target, CANCEL_USER_DATA);
+        }
+    }
+
+    /// Cancel everything that's left
+    ///
+    /// Closes, and the shutdowns ahead of them, are left alone since they're
+    /// about to finish anyway, and so are access log writes, which are to a
+    /// file and don't wait on anyone.
+    ///
+    fn cancel_all(&mut self) {
+        self.state = State::Cancelling;
+
+        let targets: Vec<u64> = self
+            .operations
+            .iter()
+            .filter(|(_, data)| {
+                !matches!(
+                    data.op,
+                    Operation::Shutdown
+                        | Operation::Close
+                        | Operation::AdminClose
+                        | Operation::LogWrite { .. }
+                )
+            })
+            .map(|(key, _)| key)
+            .collect();
+
+        for target in targets {
+            self.ring
+                .create_entry()
+                .set_cancel(target, CANCEL_USER_DATA);
+        }
+    }
+
     /// Accept connections
     ///
-    /// We create an accept empty accept entry and then add the listener's file
-    /// descriptor. We set the parameters for addr and addrlen to null since we
-    /// don't care about the IP address for now. Later, we'll want to grab these.
+    /// We create an accept entry for the listener's file descriptor, along with
+    /// a slot for the kernel to write the peer's address into. The slot lives in
+    /// the operation data until the accept completes.
     ///
     fn add_accept(&mut self) -> io::Result<()> {
-        let user_data = self.generate_entry_id(Operation::Accept, self.listener.as_raw_fd());
-        self.ring.create_entry().set_accept(
-            self.listener.as_raw_fd(),
-            ptr::null_mut(),
-            ptr::null_mut(),
-            user_data,
+        let fd = self.listener.as_raw_fd();
+        let mut slot = AcceptSlot::new();
+        let (addr, addrlen) = slot.as_mut_ptrs();
+
+        let user_data = self.generate_entry_id(Operation::Accept(slot), fd);
+        self.ring
+            .create_entry()
+            .set_accept(fd, addr, addrlen, user_data);
+        Ok(())
+    }
+
+    /// Accept admin connections
+    ///
+    /// Like add_accept, but on the admin socket, if there is one. Who
+    /// connected to a Unix socket isn't worth knowing, so there's no slot.
+    ///
+    fn add_admin_accept(&mut self) -> io::Result<()> {
+        let fd = match &self.admin {
+            Some(admin) => admin.listener().as_raw_fd(),
+            None => return Ok(()),
+        };
+
+        let user_data = self.generate_entry_id(Operation::AdminAccept, fd);
+        self.ring
+            .create_entry()
+            .set_accept(fd, ptr::null_mut(), ptr::null_mut(), user_data);
+        Ok(())
+    }
+
+    /// Receive an admin command
+    ///
+    /// Receives onto whatever the request already holds, which is only ever
+    /// the start of a command still missing its newline.
+    ///
+    fn add_admin_receive(&mut self, fd: RawFd, mut request: AdminRequest) -> io::Result<()> {
+        let (ptr, len) = request.spare();
+        let user_data = self.generate_entry_id(Operation::AdminReceive(request), fd);
+
+        self.ring
+            .create_entry()
+            .set_receive(fd, ptr, len, 0, user_data);
+        Ok(())
+    }
+
+    /// Send the response to admin commands, from offset on
+    fn add_admin_send(
+        &mut self,
+        fd: RawFd,
+        request: AdminRequest,
+        response: Vec<u8>,
+        offset: usize,
+    ) -> io::Result<()> {
+        let remaining = &response[offset..];
+        let (ptr, len) = (remaining.as_ptr(), remaining.len());
+        let user_data = self.generate_entry_id(
+            Operation::AdminSend {
+                request,
+                response,
+                offset,
+            },
+            fd,
         );
+
+        self.ring
+            .create_entry()
+            .set_send(fd, ptr, len, 0, user_data);
+        Ok(())
+    }
+
+    /// Close an admin connection
+    ///
+    /// Kept apart from add_close, since there's no Connection to forget.
+    ///
+    fn add_admin_close(&mut self, fd: RawFd) -> io::Result<()> {
+        let user_data = self.generate_entry_id(Operation::AdminClose, fd);
+        self.ring.create_entry().set_close(fd, user_data);
         Ok(())
     }
 
     /// Receive information
     ///
-    /// We create a buffer to store the incoming information and a recv entry
-    /// and then use the unique memory address of that buffer as the key in our
-    /// hash with the value as the fd.
+    /// We hand the ring the connection's buffer to store the incoming
+    /// information in, after whatever it already holds, which is only ever the
+    /// start of a line when framing by lines. The operation data holds on to
+    /// it until the receive completes.
+    ///
+    /// With an idle timeout the receive is linked to a timeout, so if nothing
+    /// arrives in time the receive is cancelled and the connection closed.
     ///
-    fn add_receive(&mut self, fd: RawFd) -> io::Result<()> {
-        let buffer = Box::into_raw(Box::new([0u8; BUFFER_SIZE])) as *mut u8;
+    fn add_receive(&mut self, fd: RawFd, mut buffer: PooledBuffer) -> io::Result<()> {
+        let ptr = unsafe { buffer.as_mut_ptr().add(buffer.len()) };
+        let capacity = buffer.capacity() - buffer.len();
         let user_data = self.generate_entry_id(Operation::Receive(buffer), fd);
 
+        let timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => {
+                self.ring
+                    .create_entry()
+                    .set_receive(fd, ptr, capacity, 0, user_data);
+                return Ok(());
+            }
+        };
+
+        let mut ts = Box::new(timespec(timeout));
+        let ts_ptr = &mut *ts as *mut __kernel_timespec;
+        let timeout_data = self.generate_entry_id(Operation::IdleTimeout(ts), fd);
+
         self.ring
             .create_entry()
-            .set_receive(fd, buffer as *mut u8, BUFFER_SIZE, 0, user_data);
+            .link()
+            .set_receive(fd, ptr, capacity, 0, user_data);
+        self.ring
+            .create_entry()
+            .set_link_timeout(ts_ptr, 0, timeout_data);
 
         Ok(())
     }
@@ -132,18 +710,263 @@ impl EchoServer {
     /// When sending we create a unique id, which we'll store in the user_data
     /// portion of the iouring submission queue entry. That entry is created in
     /// the shared memory of the queue that exists between user and kernel
-    /// space.
+    /// space. Everything in the buffer from offset up to end is sent.
+    ///
+    /// With a send timeout the send is linked to a timeout, so a peer that
+    /// stops reading can't hold on to the connection and its buffer forever.
+    ///
+    /// A send dropped by fault injection is a NOP instead, whose completion
+    /// is taken as everything having gone out, so the peer loses the data
+    /// but the connection carries on.
+    ///
+    fn add_send(
+        &mut self,
+        fd: RawFd,
+        buffer: PooledBuffer,
+        offset: usize,
+        end: usize,
+    ) -> io::Result<()> {
+        let remaining = &buffer.as_slice()[offset..end];
+        let (ptr, len) = (remaining.as_ptr(), remaining.len());
+        let dropped = self.faults.drop_send();
+        let user_data = self.generate_entry_id(
+            Operation::Send {
+                buffer,
+                offset,
+                end,
+                dropped,
+            },
+            fd,
+        );
+
+        if dropped {
+            debug!(conn: fd, "Dropping send (fault injection)");
+            self.ring.create_entry().set_nop(user_data);
+            return Ok(());
+        }
+
+        let timeout = match self.send_timeout {
+            Some(timeout) => timeout,
+            None => {
+                self.ring
+                    .create_entry()
+                    .set_send(fd, ptr, len, 0, user_data);
+                return Ok(());
+            }
+        };
+
+        let mut ts = Box::new(timespec(timeout));
+        let ts_ptr = &mut *ts as *mut __kernel_timespec;
+        let timeout_data = self.generate_entry_id(Operation::SendTimeout(ts), fd);
+
+        self.ring
+            .create_entry()
+            .link()
+            .set_send(fd, ptr, len, 0, user_data);
+        self.ring
+            .create_entry()
+            .set_link_timeout(ts_ptr, 0, timeout_data);
+
+        Ok(())
+    }
+
+    /// Read a signal
+    ///
+    /// Completes once SIGINT or SIGTERM arrives, with the signal's details
+    /// read into the buffer. Does nothing for a worker, which has no signalfd.
+    ///
+    fn add_signal_read(&mut self, mut info: Box<[u8; SIGINFO_SIZE]>) -> io::Result<()> {
+        let fd = match &self.signals {
+            Some(signals) => signals.as_raw_fd(),
+            None => return Ok(()),
+        };
+        let ptr = info.as_mut_ptr();
+        let user_data = self.generate_entry_id(Operation::Signal(info), fd);
+
+        self.ring
+            .create_entry()
+            .set_read(fd, ptr, SIGINFO_SIZE as u32, u64::MAX, user_data);
+
+        Ok(())
+    }
+
+    /// Set the stats timer
+    ///
+    /// Completes once the stats interval has passed. Does nothing if stats
+    /// weren't asked for.
+    ///
+    fn add_stats_timer(&mut self) -> io::Result<()> {
+        let interval = match self.stats_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+
+        let mut ts = Box::new(timespec(interval));
+        let ts_ptr = &mut *ts as *mut __kernel_timespec;
+        let user_data = self.generate_entry_id(Operation::StatsTimer(ts), -1);
+
+        self.ring
+            .create_entry()
+            .set_timeout(ts_ptr, 0, 0, user_data);
+
+        Ok(())
+    }
+
+    /// Delay a send
+    ///
+    /// Holds the buffer until the delay has passed, and then sends up to end
+    /// (see handle_delay).
     ///
-    fn add_send(&mut self, fd: RawFd, buffer: *mut u8, len: usize) -> io::Result<()> {
-        let user_data = self.generate_entry_id(Operation::Send(buffer), fd);
+    fn add_delay(
+        &mut self,
+        fd: RawFd,
+        buffer: PooledBuffer,
+        end: usize,
+        delay: Duration,
+    ) -> io::Result<()> {
+        let mut ts = Box::new(timespec(delay));
+        let ts_ptr = &mut *ts as *mut __kernel_timespec;
+        let user_data = self.generate_entry_id(Operation::Delay { buffer, end, ts }, fd);
 
         self.ring
             .create_entry()
-            .set_send(fd, buffer as *const u8, len, 0, user_data);
+            .set_timeout(ts_ptr, 0, 0, user_data);
+
+        Ok(())
+    }
+
+    /// Reject a connection
+    ///
+    /// Tells the peer why before closing, which happens once the send
+    /// completes whether or not it worked. The message is static, so there's
+    /// no buffer to hold on to.
+    ///
+    fn add_reject(&mut self, fd: RawFd) -> io::Result<()> {
+        let user_data = self.generate_entry_id(Operation::Reject, fd);
+        self.ring.create_entry().set_send(
+            fd,
+            REJECT_MESSAGE.as_ptr(),
+            REJECT_MESSAGE.len(),
+            0,
+            user_data,
+        );
+        Ok(())
+    }
+
+    /// Close a connection
+    ///
+    /// Queues a close for the socket. Without this the fd would stay open
+    /// until the process exits. The reason is kept with the connection for
+    /// when the close completes, unless it already has one. With a multishot
+    /// receive the close may have to wait (see EchoServer::close_stream).
+    ///
+    fn add_close(&mut self, fd: RawFd, reason: CloseReason) -> io::Result<()> {
+        if let Some(connection) = self.connections.get_mut(&fd) {
+            connection.close_reason.get_or_insert(reason);
+        }
+        if self.streams.contains_key(&fd) {
+            return self.close_stream(fd, false);
+        }
+
+        self.queue_close(fd);
+        Ok(())
+    }
+
+    fn queue_close(&mut self, fd: RawFd) {
+        if !self.release_fd(fd) {
+            return;
+        }
+
+        let user_data = self.generate_entry_id(Operation::Close, fd);
+        self.ring.create_entry().set_close(fd, user_data);
+    }
+
+    /// Finish a connection
+    ///
+    /// For when everything there was to echo has gone out. The write side is
+    /// shut down first, so the peer gets a FIN behind the last of the echo
+    /// rather than a reset if it still had data on its way to us. The close
+    /// is hard linked behind the shutdown, so it happens even if the
+    /// shutdown fails.
+    ///
+    fn add_shutdown_close(&mut self, fd: RawFd, reason: CloseReason) -> io::Result<()> {
+        if let Some(connection) = self.connections.get_mut(&fd) {
+            connection.close_reason.get_or_insert(reason);
+        }
+        if self.streams.contains_key(&fd) {
+            return self.close_stream(fd, true);
+        }
 
+        self.queue_shutdown_close(fd);
         Ok(())
     }
 
+    fn queue_shutdown_close(&mut self, fd: RawFd) {
+        if !self.release_fd(fd) {
+            return;
+        }
+
+        let shutdown_data = self.generate_entry_id(Operation::Shutdown, fd);
+        let close_data = self.generate_entry_id(Operation::Close, fd);
+
+        self.ring
+            .create_entry()
+            .hard_link()
+            .set_shutdown(fd, Shutdown::Write, shutdown_data);
+        self.ring.create_entry().set_close(fd, close_data);
+    }
+
+    /// Take a connection's fd for a close to be queued
+    ///
+    /// False if its close is already queued. An fd without a connection,
+    /// like a rejected one, only ever gets the one close anyway.
+    ///
+    fn release_fd(&mut self, fd: RawFd) -> bool {
+        match self.connections.get_mut(&fd) {
+            Some(connection) => connection.take_fd().is_some(),
+            None => true,
+        }
+    }
+
+    /// Write to the access log
+    ///
+    /// Writes whatever lines are waiting, if there's a log and it isn't
+    /// already being written to (see AccessLog::take_pending). The offset
+    /// is ignored, since the file is opened for appending.
+    ///
+    fn flush_access_log(&mut self) {
+        let (fd, data) = match &mut self.access_log {
+            Some(log) => match log.take_pending() {
+                Some(data) => (log.fd(), data),
+                None => return,
+            },
+            None => return,
+        };
+        self.add_log_write(fd, data, 0);
+    }
+
+    fn add_log_write(&mut self, fd: RawFd, data: Vec<u8>, offset: usize) {
+        let remaining = &data[offset..];
+        let (ptr, len) = (remaining.as_ptr(), remaining.len() as u32);
+        let user_data = self.generate_entry_id(Operation::LogWrite { data, offset }, fd);
+
+        self.ring
+            .create_entry()
+            .set_write(fd, ptr, len, u64::MAX, user_data);
+    }
+
+    /// Why a connection's operation was cancelled
+    ///
+    /// Either we're shutting down, or the timeout linked to it expired. A
+    /// kick records its reason before cancelling, so that one sticks.
+    ///
+    fn cancelled(&self, timeout: CloseReason) -> CloseReason {
+        match self.state {
+            State::Running => timeout,
+            _ => CloseReason::Shutdown,
+        }
+    }
+
     /// Creates entry id
     ///
     /// This is needed because when we create an entry, say for reading from a
@@ -151,29 +974,114 @@ impl EchoServer {
     /// an answer to. It's a way to match submission and completition queue
     /// entries with the given file descriptor.
     ///
+    /// A connection with a multishot receive counts what it has in flight
+    /// (see Operation::tracked), so that it's only closed once that's none.
+    ///
     fn generate_entry_id(&mut self, op: Operation, fd: RawFd) -> u64 {
-        let user_data = self.next_id;
-        self.next_id = self.next_id.wrapping_add(1);
-        self.operations.insert(user_data, OperationData { op, fd });
-        user_data
+        if op.tracked() {
+            if let Some(stream) = self.streams.get_mut(&fd) {
+                stream.started();
+            }
+        }
+        self.operations.insert(OperationData { op, fd })
     }
 
     /// Handles completed queue entries
     ///
     /// Grade the user_data from our completion queue entry (cqe) and then remove it
-    /// from our operations hashmap. Each operation has a variant and associated file
-    /// description AND possibly buffer (Receive/Send). We then pass those along to the
-    /// respective handler.
+    /// from our operations slab. Each operation has a variant and associated file
+    /// description AND possibly buffer (Receive/Send). The result is checked
+    /// against the operation it came from (see UringError) and passed along to
+    /// the respective handler.
+    ///
+    /// A multishot receive that will complete again (see Cqe::has_more) is
+    /// left in the slab, since it's still armed.
     ///
-    fn handle_completion(&mut self, cqe: io_uring_cqe) -> io::Result<()> {
+    fn handle_completion(&mut self, cqe: Cqe) -> io::Result<()> {
         let user_data = cqe.user_data;
-        let res = cqe.res; // This indicates the succces or failure or the operation.
+        self.metrics.completions += 1;
+
+        if let Some(delay) = self.faults.delay() {
+            thread::sleep(delay);
+        }
+
+        if user_data == SIGNAL_USER_DATA {
+            self.shut_down_on(cqe.res);
+            return Ok(());
+        }
+
+        let op_data = match self.operations.get(user_data) {
+            Some(data) if cqe.has_more() && matches!(data.op, Operation::ReceiveMultishot) => {
+                Some(OperationData {
+                    op: Operation::ReceiveMultishot,
+                    fd: data.fd,
+                })
+            }
+            _ => self.operations.remove(user_data),
+        };
+
+        if let Some(op_data) = op_data {
+            let fd = op_data.fd;
+            let result = UringError::check(op_data.op.kind(), fd, cqe.res);
+            let finished = op_data.op.tracked() && !cqe.has_more();
+            if finished {
+                if let Some(stream) = self.streams.get_mut(&fd) {
+                    stream.finished();
+                }
+            }
 
-        if let Some(op_data) = self.operations.remove(&user_data) {
             match op_data.op {
-                Operation::Accept => self.handle_accept(res)?,
-                Operation::Receive(buffer) => self.handle_receive(res, buffer, op_data.fd)?,
-                Operation::Send(buffer) => self.handle_send(res, buffer, op_data.fd)?,
+                Operation::Accept(slot) => self.handle_accept(result, &slot)?,
+                Operation::Receive(buffer) => self.handle_receive(result, buffer, fd)?,
+                Operation::ReceiveMultishot => {
+                    self.handle_receive_multishot(result, &cqe, fd, user_data)?
+                }
+                Operation::IdleTimeout(_) => self.handle_idle_timeout(result, fd),
+                Operation::SendTimeout(_) => self.handle_send_timeout(result, fd),
+                Operation::Send {
+                    buffer,
+                    offset,
+                    end,
+                    dropped,
+                } => {
+                    // The NOP standing in for a dropped send "sent" the lot
+                    let result = if dropped {
+                        result.map(|_| (end - offset) as u32)
+                    } else {
+                        result
+                    };
+                    self.handle_send(result, buffer, offset, end, fd)?
+                }
+                Operation::Shutdown => self.handle_shutdown(result, fd),
+                Operation::Close => self.handle_close(result, fd),
+                Operation::Reject => self.add_close(fd, CloseReason::Rejected)?,
+                Operation::Delay { buffer, end, .. } => {
+                    self.handle_delay(result, buffer, end, fd)?
+                }
+                Operation::Signal(info) => self.handle_signal(result, info)?,
+                Operation::StatsTimer(_) => self.handle_stats_timer(result)?,
+                Operation::IdleSweep(_) => self.handle_idle_sweep(result)?,
+                Operation::AdminAccept => self.handle_admin_accept(result)?,
+                Operation::AdminReceive(request) => {
+                    self.handle_admin_receive(result, request, fd)?
+                }
+                Operation::AdminSend {
+                    request,
+                    response,
+                    offset,
+                } => self.handle_admin_send(result, request, response, offset, fd)?,
+                Operation::AdminClose => {
+                    if let Err(err) = result {
+                        error!("{}", err);
+                    }
+                }
+                Operation::LogWrite { data, offset } => {
+                    self.handle_log_write(result, data, offset, fd)
+                }
+            }
+
+            if finished {
+                self.finish_stream(fd)?;
             }
         }
 
@@ -182,73 +1090,601 @@ impl EchoServer {
 
     /// Handle Accept
     ///
-    /// We check the result to see if a connection is being made, if so we queue
-    /// of a receive. If result is negative, then queue may be full. No matter
-    /// what happens we queue up another accept, which keeps us listening for
-    /// more connections.
-    ///
-    fn handle_accept(&mut self, res: i32) -> io::Result<()> {
-        if res >= 0 {
-            println!("Accepted new connection: {}", res);
-            self.add_receive(res)?;
-        } else if res == -(EAGAIN as i32) {
-            println!("No new connection available");
-        } else {
-            eprintln!("Accept failed with error: {}", -res);
+    /// We check the result to see if a connection is being made, if so we set
+    /// its socket options and queue up a receive, or arm a multishot one. If it
+    /// would have blocked, then queue may be full. No matter what happens we
+    /// queue up another accept, which keeps us listening for more connections,
+    /// unless we're shutting down. A connection that got in just as we started
+    /// shutting down is closed straight away, and one that would take us over
+    /// the connection limit is rejected.
+    ///
+    fn handle_accept(
+        &mut self,
+        result: Result<u32, UringError>,
+        slot: &AcceptSlot,
+    ) -> io::Result<()> {
+        match result {
+            Ok(fd) if self.state != State::Running => {
+                self.add_close(fd as RawFd, CloseReason::Shutdown)?
+            }
+            Ok(fd)
+                if self
+                    .max_connections
+                    .is_some_and(|max| self.connections.len() >= max) =>
+            {
+                let fd = fd as RawFd;
+                info!(conn: fd, "Too many connections, rejecting");
+                self.metrics.rejected += 1;
+                if let Some(log) = &mut self.access_log {
+                    log.rejected(fd, slot.peer());
+                    self.flush_access_log();
+                }
+                self.add_reject(fd)?;
+            }
+            Ok(fd) => {
+                let fd = fd as RawFd;
+                let peer = slot.peer();
+                match peer {
+                    Some(peer) => info!(conn: fd, "Accepted new connection from {}", peer),
+                    None => info!(conn: fd, "Accepted new connection"),
+                }
+                let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+                let connection = Connection::new(socket, peer);
+                if let Some(log) = &mut self.access_log {
+                    log.opened(fd, &connection);
+                    self.flush_access_log();
+                }
+                self.connections.insert(fd, connection);
+                self.metrics.accepted += 1;
+
+                if let Err(err) = self.socket_options.apply(fd) {
+                    warning!(conn: fd, "Setting socket options failed: {}", err);
+                }
+
+                match self.pool.checkout() {
+                    Some(buffer) if self.buffers.is_some() => {
+                        self.streams.insert(fd, Stream::new(buffer));
+                        self.arm_receive(fd)?;
+                    }
+                    Some(buffer) => self.add_receive(fd, buffer)?,
+                    None => {
+                        warning!(conn: fd, "Buffer pool exhausted, closing connection");
+                        self.add_close(fd, CloseReason::NoBuffer)?;
+                    }
+                }
+            }
+            Err(UringError::WouldBlock { .. }) => debug!("No new connection available"),
+            Err(UringError::Canceled { .. }) => {}
+            Err(err) => error!("{}", err),
         }
 
+        if self.state != State::Running {
+            return Ok(());
+        }
         self.add_accept()
     }
 
     /// Handle receive
     ///
-    /// If we get a successful receive we convert the buffer to a readable string,
-    /// otherwise if we get 0 the connection is closed and we release the
-    /// buffer.
+    /// If we get a successful receive we convert the buffer to a readable string
+    /// and send the same buffer back, otherwise if we get 0 the peer has shut
+    /// down its side. Whatever is still in the buffer is echoed before we shut
+    /// down ours and close (see add_shutdown_close). On failure the socket is
+    /// closed, and the buffer goes back to the pool as it's dropped. A reset
+    /// from the peer is an ordinary way for a connection to end, so it isn't
+    /// reported as an error, and neither is a receive cancelled by shutting
+    /// down.
     ///
-    /// Releasing the buffer is a bit odd. We take it, wrap it in a box so that
-    /// Rust will be able to clean it up after it does out of scope. We do this
-    /// on connection closed or failure.
+    /// When framing by lines only the complete lines are sent back, and the
+    /// start of the next one stays put in the buffer while we receive the
+    /// rest of it. Until it has its newline nothing is sent, and a line that
+    /// doesn't fit in the buffer closes the connection. A line still missing
+    /// its newline when the peer closes is echoed as it is.
     ///
-    fn handle_receive(&mut self, res: i32, buffer: *mut u8, fd: RawFd) -> io::Result<()> {
-        if res > 0 {
-            let slice = unsafe { std::slice::from_raw_parts(buffer, res as usize) };
-            let text = String::from_utf8_lossy(slice);
-            println!("Read {} bytes: {}", res, text);
+    fn handle_receive(
+        &mut self,
+        result: Result<u32, UringError>,
+        mut buffer: PooledBuffer,
+        fd: RawFd,
+    ) -> io::Result<()> {
+        match result {
+            Ok(0) if buffer.is_empty() => {
+                info!(conn: fd, "Connection closed");
+                self.add_shutdown_close(fd, CloseReason::PeerClosed)?;
+            }
+            Ok(0) => {
+                info!(conn: fd, "Connection closed, echoing the rest");
+                if let Some(connection) = self.connections.get_mut(&fd) {
+                    connection.read_closed = true;
+                }
 
-            self.add_send(fd, buffer, res as usize)?;
-        } else if res == 0 {
-            println!("Connection closed");
-            unsafe {
-                let _ = Box::from_raw(buffer);
+                let end = buffer.len();
+                self.transform.apply(&mut buffer.as_mut_slice()[..end]);
+                match self.transform.delay() {
+                    Duration::ZERO => self.add_send(fd, buffer, 0, end)?,
+                    delay => self.add_delay(fd, buffer, end, delay)?,
+                }
             }
-        } else {
-            eprintln!("Read failed with error: {}", -res);
-            unsafe {
-                let _ = Box::from_raw(buffer);
+            Ok(len) => {
+                let start = buffer.len();
+                buffer.set_len(start + len as usize);
+                if let Some(connection) = self.connections.get_mut(&fd) {
+                    connection.received(len as u64);
+                }
+                self.metrics.bytes_read += len as u64;
+                if log::enabled(Level::Debug) {
+                    let text = String::from_utf8_lossy(&buffer.as_slice()[start..]);
+                    debug!(conn: fd, "Read {} bytes: {}", len, text);
+                }
+
+                if self.faults.close() {
+                    warning!(conn: fd, "Closing connection (fault injection)");
+                    return self.add_close(fd, CloseReason::Fault);
+                }
+
+                let end = match self.framing {
+                    Framing::Raw => buffer.len(),
+                    Framing::Lines => match buffer.as_slice().iter().rposition(|&b| b == b'\n') {
+                        Some(newline) => newline + 1,
+                        None if buffer.len() < buffer.capacity() => {
+                            return self.add_receive(fd, buffer);
+                        }
+                        None => {
+                            warning!(conn: fd, "Line longer than the buffer, closing connection");
+                            return self.add_close(fd, CloseReason::LineTooLong);
+                        }
+                    },
+                };
+
+                self.transform.apply(&mut buffer.as_mut_slice()[..end]);
+                match self.transform.delay() {
+                    Duration::ZERO => self.add_send(fd, buffer, 0, end)?,
+                    delay => self.add_delay(fd, buffer, end, delay)?,
+                }
+            }
+            Err(UringError::Canceled { .. }) => {
+                self.add_close(fd, self.cancelled(CloseReason::Idle))?;
+            }
+            Err(err) if err.is_disconnect() => {
+                info!(conn: fd, "Connection reset");
+                self.add_close(fd, CloseReason::Reset)?;
+            }
+            Err(err) => {
+                error!("{}", err);
+                self.add_close(fd, CloseReason::Error)?;
             }
         }
 
         Ok(())
     }
 
+    /// Handle delay
+    ///
+    /// Sends the echo once the delay is up. Only shutting down cancels a
+    /// delay, in which case the connection is closed without it.
+    ///
+    fn handle_delay(
+        &mut self,
+        result: Result<u32, UringError>,
+        buffer: PooledBuffer,
+        end: usize,
+        fd: RawFd,
+    ) -> io::Result<()> {
+        match result {
+            Ok(_) => self.add_send(fd, buffer, 0, end),
+            Err(UringError::Canceled { .. }) => self.add_close(fd, CloseReason::Shutdown),
+            Err(err) => {
+                error!("{}", err);
+                self.add_close(fd, CloseReason::Error)
+            }
+        }
+    }
+
+    /// Handle idle timeout
+    ///
+    /// The timeout expiring means the receive it was linked to has been
+    /// cancelled, which closes the connection, so there's only something to
+    /// report. If the receive finished first the timeout is cancelled.
+    ///
+    fn handle_idle_timeout(&mut self, result: Result<u32, UringError>, fd: RawFd) {
+        match result {
+            Ok(_) => info!(conn: fd, "Connection idle, closing"),
+            Err(UringError::Canceled { .. }) => {}
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    /// Handle send timeout
+    ///
+    /// Like the idle timeout, except that it's the peer not reading what we
+    /// send that closes the connection.
+    ///
+    fn handle_send_timeout(&mut self, result: Result<u32, UringError>, fd: RawFd) {
+        match result {
+            Ok(_) => {
+                let unsent = self.connections.get(&fd).map_or(0, Connection::unsent);
+                warning!(conn: fd, "Peer stopped reading with {} bytes unsent, closing", unsent);
+            }
+            Err(UringError::Canceled { .. }) => {}
+            Err(err) => error!("{}", err),
+        }
+    }
+
     /// Handle send
     ///
-    /// The information is sent and another receive is queued up. In all cases
-    /// we release the buffer pointer.
+    /// The information is sent and another receive is queued up, reusing the
+    /// buffer. If the send failed the buffer goes back to the pool and the
+    /// connection is closed instead. The same goes once the last of the
+    /// connection's data is out and either we're shutting down or the peer
+    /// has closed its side, except that our side is shut down before the
+    /// close.
     ///
-    fn handle_send(&mut self, res: i32, buffer: *mut u8, fd: RawFd) -> io::Result<()> {
-        if res >= 0 {
-            println!("Send completed: {} bytes", res);
-            self.add_receive(fd)?;
-        } else {
-            eprintln!("Write failed with error: {}", -res);
+    /// A send can go out short when the socket's send buffer fills up, in
+    /// which case we send the rest before receiving anything more. A send of
+    /// nothing at all means the socket is stuck, so that connection is closed.
+    ///
+    /// That's also what keeps a peer that reads slowly in check: while its
+    /// echo is waiting to go out no receive is queued, so we stop reading from
+    /// it and at most a buffer's worth is ever pending. If it doesn't make
+    /// room before the send timeout the connection is closed.
+    ///
+    /// Once everything up to end is out, whatever comes after it (the start
+    /// of a line) is moved to the front of the buffer to be received onto.
+    ///
+    fn handle_send(
+        &mut self,
+        result: Result<u32, UringError>,
+        mut buffer: PooledBuffer,
+        offset: usize,
+        end: usize,
+        fd: RawFd,
+    ) -> io::Result<()> {
+        match result {
+            Ok(0) => {
+                warning!(conn: fd, "Send made no progress, closing connection");
+                self.add_close(fd, CloseReason::Stalled)?;
+            }
+            Ok(len) => {
+                if let Some(connection) = self.connections.get_mut(&fd) {
+                    connection.sent(len as u64);
+                }
+                self.metrics.bytes_written += len as u64;
+
+                let offset = offset + len as usize;
+                if offset < end {
+                    debug!(conn: fd, "Short send: {} of {} bytes", offset, end);
+                    return self.add_send(fd, buffer, offset, end);
+                }
+
+                debug!(conn: fd, "Send completed: {} bytes", end);
+                let rest = buffer.len() - end;
+                buffer.as_mut_slice().copy_within(end.., 0);
+                buffer.set_len(rest);
+
+                if self.streams.contains_key(&fd) {
+                    return self.sent(fd, buffer);
+                }
+                let read_closed = self
+                    .connections
+                    .get(&fd)
+                    .is_some_and(|connection| connection.read_closed);
+                if self.state == State::Running && !read_closed {
+                    self.add_receive(fd, buffer)?;
+                } else {
+                    let reason = if read_closed {
+                        CloseReason::PeerClosed
+                    } else {
+                        CloseReason::Shutdown
+                    };
+                    self.add_shutdown_close(fd, reason)?;
+                }
+            }
+            Err(UringError::Canceled { .. }) => {
+                self.add_close(fd, self.cancelled(CloseReason::SendTimeout))?;
+            }
+            Err(err) if err.is_disconnect() => {
+                info!(conn: fd, "Connection reset");
+                self.add_close(fd, CloseReason::Reset)?;
+            }
+            Err(err) => {
+                error!("{}", err);
+                self.add_close(fd, CloseReason::Error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle shutdown
+    ///
+    /// The close is on its way either way, so there's only something to
+    /// report, and a peer that's already gone isn't worth that.
+    ///
+    fn handle_shutdown(&mut self, result: Result<u32, UringError>, fd: RawFd) {
+        match result {
+            Ok(_) => debug!(conn: fd, "Shut down the write side"),
+            Err(err) if err.is_disconnect() => {}
+            Err(UringError::Canceled { .. }) => {}
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    /// Handle close
+    ///
+    /// The connection is forgotten whether or not the close worked, since the
+    /// fd is no use to us either way. All that's left is to report it, to the
+    /// access log as well if we have one.
+    ///
+    fn handle_close(&mut self, result: Result<u32, UringError>, fd: RawFd) {
+        let connection = self.connections.remove(&fd);
+        self.streams.remove(&fd);
+
+        if let (Some(log), Some(connection)) = (&mut self.access_log, &connection) {
+            let reason = connection.close_reason.unwrap_or(CloseReason::Error);
+            log.closed(fd, connection, reason);
+            self.flush_access_log();
+        }
+
+        match result {
+            Ok(_) => match connection {
+                Some(connection) => info!(conn: fd, "Closed connection ({})", connection),
+                None => info!(conn: fd, "Closed connection"),
+            },
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    /// Handle access log write
+    ///
+    /// Writes the rest if the write came up short, and otherwise whatever
+    /// lines came in while it was in flight. Lines that fail to be written
+    /// are dropped, since there's nothing better to do with them.
+    ///
+    fn handle_log_write(
+        &mut self,
+        result: Result<u32, UringError>,
+        data: Vec<u8>,
+        offset: usize,
+        fd: RawFd,
+    ) {
+        match result {
+            Ok(len) if len > 0 && offset + (len as usize) < data.len() => {
+                return self.add_log_write(fd, data, offset + len as usize);
+            }
+            Ok(0) => error!("Access log write made no progress, dropping lines"),
+            Ok(_) => {}
+            Err(err) => error!("{}", err),
+        }
+
+        if let Some(log) = &mut self.access_log {
+            log.written();
+        }
+        self.flush_access_log();
+    }
+
+    /// Handle signal
+    ///
+    /// The first signal starts a graceful shutdown and the read is queued up
+    /// again, so that a second one can cut the drain short.
+    ///
+    fn handle_signal(
+        &mut self,
+        result: Result<u32, UringError>,
+        info: Box<[u8; SIGINFO_SIZE]>,
+    ) -> io::Result<()> {
+        match result {
+            Ok(_) => {}
+            Err(UringError::Canceled { .. }) => return Ok(()),
+            Err(err) => {
+                error!("{}", err);
+                return Ok(());
+            }
+        }
+
+        let running = self.state == State::Running;
+        self.shut_down_on(signal_number(&info));
+        if running {
+            self.add_signal_read(info)?;
+        }
+
+        Ok(())
+    }
+
+    /// Act on a signal, whether read or forwarded
+    fn shut_down_on(&mut self, signal: c_int) {
+        let name = signal_name(signal);
+        match self.state {
+            State::Running => {
+                info!("Got {}, shutting down", name);
+                self.start_shutdown();
+            }
+            State::Draining(_) => {
+                info!("Got {} while draining, cancelling everything", name);
+                self.cancel_all();
+            }
+            State::Cancelling => {}
+        }
+    }
+
+    /// Handle stats timer
+    ///
+    /// Prints the stats line and sets the timer again. It goes to stdout rather
+    /// than the log, no matter the log level, since asking for stats is asking
+    /// for this line.
+    ///
+    fn handle_stats_timer(&mut self, result: Result<u32, UringError>) -> io::Result<()> {
+        match result {
+            Ok(_) => {}
+            Err(UringError::Canceled { .. }) => return Ok(()),
+            Err(err) => error!("{}", err),
         }
 
-        unsafe {
-            let _ = Box::from_raw(buffer);
+        let sq_full = self.ring.sq_stats().full;
+        let report = self
+            .reporter
+            .report(&self.metrics, self.connections.len(), sq_full);
+        match self.worker {
+            Some(id) => println!("{} worker {}: {}", log::timestamp(), id, report),
+            None => println!("{} {}", log::timestamp(), report),
         }
 
+        if self.state == State::Running {
+            self.add_stats_timer()?;
+        }
         Ok(())
     }
+
+    /// Handle admin accept
+    ///
+    /// Starts receiving commands on the new connection and accepts the next
+    /// one, unless we're shutting down.
+    ///
+    fn handle_admin_accept(&mut self, result: Result<u32, UringError>) -> io::Result<()> {
+        match result {
+            Ok(fd) if self.state != State::Running => self.add_admin_close(fd as RawFd)?,
+            Ok(fd) => {
+                debug!(conn: fd as RawFd, "Accepted admin connection");
+                self.add_admin_receive(fd as RawFd, AdminRequest::new())?;
+            }
+            Err(UringError::WouldBlock { .. } | UringError::Canceled { .. }) => {}
+            Err(err) => error!("{}", err),
+        }
+
+        if self.state != State::Running {
+            return Ok(());
+        }
+        self.add_admin_accept()
+    }
+
+    /// Handle admin receive
+    ///
+    /// Runs every complete command that has come in and sends back what they
+    /// had to say. A command that doesn't fit in the request closes the
+    /// connection, as does the peer closing it.
+    ///
+    fn handle_admin_receive(
+        &mut self,
+        result: Result<u32, UringError>,
+        mut request: AdminRequest,
+        fd: RawFd,
+    ) -> io::Result<()> {
+        let len = match result {
+            Ok(0) => return self.add_admin_close(fd),
+            Ok(len) => len,
+            Err(err) => {
+                if !matches!(err, UringError::Canceled { .. }) && !err.is_disconnect() {
+                    error!("{}", err);
+                }
+                return self.add_admin_close(fd);
+            }
+        };
+        request.filled(len as usize);
+
+        let mut response = String::new();
+        while let Some(line) = request.take_line() {
+            if !line.is_empty() {
+                self.run_admin_command(&line, &mut response);
+            }
+        }
+
+        if !response.is_empty() {
+            return self.add_admin_send(fd, request, response.into_bytes(), 0);
+        }
+        if request.is_full() {
+            warning!(conn: fd, "Admin command too long, closing connection");
+            return self.add_admin_close(fd);
+        }
+        self.add_admin_receive(fd, request)
+    }
+
+    /// Run an admin command, adding its answer to the response
+    fn run_admin_command(&mut self, line: &str, response: &mut String) {
+        debug!("Admin command: {}", line);
+
+        match line.parse() {
+            Ok(Command::Stats) => response.push_str(&admin::stats_table(&self.connections)),
+            Ok(Command::Kick(fd)) if self.kick(fd) => {
+                response.push_str(&format!("Kicked {}\n", fd));
+            }
+            Ok(Command::Kick(fd)) => response.push_str(&format!("No connection on fd {}\n", fd)),
+            Err(err) => {
+                response.push_str(&err);
+                response.push('\n');
+            }
+        }
+    }
+
+    /// Force a connection closed
+    ///
+    /// Cancels whatever the connection is waiting on, which closes it the
+    /// same way a shutdown would. Returns false if there's no such
+    /// connection.
+    ///
+    fn kick(&mut self, fd: RawFd) -> bool {
+        match self.connections.get_mut(&fd) {
+            Some(connection) => {
+                connection.close_reason.get_or_insert(CloseReason::Kicked);
+            }
+            None => return false,
+        }
+        info!(conn: fd, "Kicked by admin, closing");
+
+        let targets: Vec<u64> = self
+            .operations
+            .iter()
+            .filter(|(_, data)| {
+                data.fd == fd
+                    && matches!(
+                        data.op,
+                        Operation::Receive(_)
+                            | Operation::ReceiveMultishot
+                            | Operation::Send { .. }
+                            | Operation::Delay { .. }
+                    )
+            })
+            .map(|(key, _)| key)
+            .collect();
+
+        for target in targets {
+            self.ring
+                .create_entry()
+                .set_cancel(target, CANCEL_USER_DATA);
+        }
+        true
+    }
+
+    /// Handle admin send
+    ///
+    /// Sends whatever is left of the response, and then goes back to
+    /// receiving commands. Once we're shutting down the connection is closed
+    /// instead.
+    ///
+    fn handle_admin_send(
+        &mut self,
+        result: Result<u32, UringError>,
+        request: AdminRequest,
+        response: Vec<u8>,
+        offset: usize,
+        fd: RawFd,
+    ) -> io::Result<()> {
+        let offset = match result {
+            Ok(0) => return self.add_admin_close(fd),
+            Ok(len) => offset + len as usize,
+            Err(err) => {
+                if !matches!(err, UringError::Canceled { .. }) && !err.is_disconnect() {
+                    error!("{}", err);
+                }
+                return self.add_admin_close(fd);
+            }
+        };
+
+        if offset < response.len() {
+            return self.add_admin_send(fd, request, response, offset);
+        }
+        if self.state == State::Running {
+            self.add_admin_receive(fd, request)
+        } else {
+            self.add_admin_close(fd)
+        }
+    }
 }
@@ -0,0 +1,432 @@
+/// Multishot receives
+///
+/// The default way of receiving (--oneshot goes back to the old one).
+/// Instead of queueing a receive for every message, each connection gets a
+/// single multishot receive that stays armed and completes whenever data
+/// arrives. It doesn't take a buffer of its own: the kernel picks one from
+/// the buffer ring (see buffer_ring.rs) and the completion says which. The
+/// data is copied out onto the connection's backlog and the buffer goes
+/// straight back, so the ring's buffers are only ever held for as long as
+/// the copy takes.
+///
+/// From the backlog the data goes through the connection's pooled buffer
+/// and out the same way as with one-shot receives: framed, transformed and
+/// sent, one send at a time. The difference is that receiving carries on
+/// while the send is out, so for a steady stream of messages only the sends
+/// cost a submission (compare the two with bench-recv):
+///
+///     one-shot:  receive + idle timeout, send + send timeout, per message
+///     multishot: send + send timeout, per message
+///
+/// With the receive always armed, two operations can be in flight for the
+/// same connection, which makes closing more involved. The receive is
+/// cancelled and the close only goes out once neither is left, since
+/// otherwise the fd could be handed to a new connection while a completion
+/// for the old one is still on its way. A peer that sends faster than it
+/// reads grows the backlog, and past MAX_BACKLOG_BUFFERS worth of it the
+/// receive is cancelled until the backlog has drained. Idle connections are
+/// found by a sweep every so often, since a timeout can't be linked to a
+/// multishot receive.
+///
+use super::{EchoServer, Operation, State, CANCEL_USER_DATA};
+use crate::bindings::{__kernel_timespec, ENOBUFS};
+use crate::buffer_pool::PooledBuffer;
+use crate::config::Framing;
+use crate::connection::CloseReason;
+use crate::cqe::Cqe;
+use crate::entry::timespec;
+use crate::error::UringError;
+use crate::log::{self, debug, error, info, warning, Level};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/// The buffer group multishot receives pick from
+pub const BUFFER_GROUP: u16 = 0;
+
+/// How many buffers' worth a backlog may grow to before receiving pauses
+const MAX_BACKLOG_BUFFERS: usize = 4;
+
+/// How often to look for idle connections, unless the idle timeout is shorter
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a connection needs on top of Connection with a multishot receive
+pub struct Stream {
+    /// Held here between sends, and by the send while one is out
+    buffer: Option<PooledBuffer>,
+    /// Received, but not in the buffer yet
+    backlog: Vec<u8>,
+    /// The armed receive's user_data
+    receive: Option<u64>,
+    /// Receives, sends and delays not completed yet
+    in_flight: u32,
+    sending: bool,
+    paused: bool,
+    /// Set once we're closing, to whether to shut down the write side first
+    closing: Option<bool>,
+    close_queued: bool,
+}
+
+impl Stream {
+    pub fn new(buffer: PooledBuffer) -> Self {
+        Self {
+            buffer: Some(buffer),
+            backlog: Vec::new(),
+            receive: None,
+            in_flight: 0,
+            sending: false,
+            paused: false,
+            closing: None,
+            close_queued: false,
+        }
+    }
+
+    pub fn started(&mut self) {
+        self.in_flight += 1;
+    }
+
+    pub fn finished(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+}
+
+impl EchoServer {
+    /// Arm a multishot receive
+    pub(super) fn arm_receive(&mut self, fd: RawFd) -> io::Result<()> {
+        let Some(group) = self.buffers.as_ref().map(|buffers| buffers.group_id()) else {
+            return Ok(());
+        };
+
+        let user_data = self.generate_entry_id(Operation::ReceiveMultishot, fd);
+        if let Some(stream) = self.streams.get_mut(&fd) {
+            stream.receive = Some(user_data);
+        }
+
+        self.ring
+            .create_entry()
+            .set_receive_multishot(fd, group, 0, user_data);
+        Ok(())
+    }
+
+    /// Handle multishot receive
+    ///
+    /// Whatever arrived goes onto the backlog and the kernel's buffer goes
+    /// back to the ring, and then the backlog is pumped along. A receive the
+    /// kernel ended without a reason to close, like running out of buffers,
+    /// is armed again. Getting 0 means the peer has shut down its side, and
+    /// we follow once everything has been echoed.
+    ///
+    pub(super) fn handle_receive_multishot(
+        &mut self,
+        result: Result<u32, UringError>,
+        cqe: &Cqe,
+        fd: RawFd,
+        user_data: u64,
+    ) -> io::Result<()> {
+        let stream = self.streams.get_mut(&fd);
+
+        if let (Ok(len), Some(id), Some(buffers)) = (&result, cqe.buffer_id(), &mut self.buffers) {
+            let data = buffers.get(id, *len as usize);
+            if log::enabled(Level::Debug) {
+                debug!(conn: fd, "Read {} bytes: {}", len, String::from_utf8_lossy(data));
+            }
+            if let Some(stream) = stream.filter(|stream| stream.closing.is_none()) {
+                stream.backlog.extend_from_slice(data);
+            }
+            buffers.recycle(id);
+        }
+
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(());
+        };
+        if !cqe.has_more() && stream.receive == Some(user_data) {
+            stream.receive = None;
+        }
+        if stream.closing.is_some() {
+            return Ok(());
+        }
+        let rearm = !cqe.has_more() && !stream.paused;
+
+        match result {
+            Ok(0) => {
+                info!(conn: fd, "Connection closed");
+                if let Some(connection) = self.connections.get_mut(&fd) {
+                    connection.read_closed = true;
+                }
+                self.pump(fd)
+            }
+            Ok(len) => {
+                if let Some(connection) = self.connections.get_mut(&fd) {
+                    connection.received(len as u64);
+                }
+                self.metrics.bytes_read += len as u64;
+
+                if self.faults.close() {
+                    warning!(conn: fd, "Closing connection (fault injection)");
+                    return self.add_close(fd, CloseReason::Fault);
+                }
+
+                self.check_backlog(fd);
+                let paused = self.streams.get(&fd).is_some_and(|stream| stream.paused);
+                if !cqe.has_more() && !paused {
+                    self.arm_receive(fd)?;
+                }
+                self.pump(fd)
+            }
+            Err(err) if err.errno() == ENOBUFS as i32 => {
+                debug!(conn: fd, "Out of receive buffers, rearming");
+                if rearm {
+                    self.arm_receive(fd)?;
+                }
+                Ok(())
+            }
+            Err(UringError::Canceled { .. })
+                if stream.paused
+                    && self.state == State::Running
+                    && self
+                        .connections
+                        .get(&fd)
+                        .is_some_and(|connection| connection.close_reason.is_none()) =>
+            {
+                self.pump(fd)
+            }
+            Err(UringError::Canceled { .. }) => {
+                self.add_close(fd, self.cancelled(CloseReason::Kicked))
+            }
+            Err(err) if err.is_disconnect() => {
+                info!(conn: fd, "Connection reset");
+                self.add_close(fd, CloseReason::Reset)
+            }
+            Err(err) => {
+                error!("{}", err);
+                self.add_close(fd, CloseReason::Error)
+            }
+        }
+    }
+
+    /// Pause receiving if the backlog has grown too far
+    ///
+    /// The peer keeps sending while we wait on it to read, and the receive
+    /// would otherwise keep taking it all in.
+    ///
+    fn check_backlog(&mut self, fd: RawFd) {
+        let limit = self.pool.buffer_size() * MAX_BACKLOG_BUFFERS;
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return;
+        };
+
+        if stream.paused || stream.backlog.len() < limit {
+            return;
+        }
+
+        debug!(conn: fd, "Backlog of {} bytes, pausing receives", stream.backlog.len());
+        stream.paused = true;
+        if let Some(receive) = stream.receive {
+            self.ring
+                .create_entry()
+                .set_cancel(receive, CANCEL_USER_DATA);
+        }
+    }
+
+    /// Move the backlog along
+    ///
+    /// Unless a send is already out, tops up the buffer from the backlog and
+    /// sends whatever in it is ready to go, which when framing by lines is
+    /// up to the last newline. Once the peer has closed its side and
+    /// everything has gone out, our side is shut down too. Receiving resumes
+    /// once a paused backlog has drained.
+    ///
+    pub(super) fn pump(&mut self, fd: RawFd) -> io::Result<()> {
+        let limit = self.pool.buffer_size() * MAX_BACKLOG_BUFFERS;
+        let read_closed = self
+            .connections
+            .get(&fd)
+            .is_some_and(|connection| connection.read_closed);
+        let running = self.state == State::Running;
+
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(());
+        };
+        if stream.sending || stream.closing.is_some() {
+            return Ok(());
+        }
+        let Some(mut buffer) = stream.buffer.take() else {
+            return Ok(());
+        };
+
+        let moved = buffer.extend_from_slice(&stream.backlog);
+        stream.backlog.drain(..moved);
+        let eof = read_closed && stream.backlog.is_empty();
+
+        let resume = stream.paused
+            && stream.receive.is_none()
+            && stream.backlog.len() < limit
+            && running
+            && !read_closed;
+        if resume {
+            debug!(conn: fd, "Backlog drained, resuming receives");
+            stream.paused = false;
+        }
+
+        let end = match self.framing {
+            Framing::Raw => buffer.len(),
+            Framing::Lines => match buffer.as_slice().iter().rposition(|&b| b == b'\n') {
+                Some(newline) => newline + 1,
+                None if eof => buffer.len(),
+                None if buffer.len() < buffer.capacity() => 0,
+                None => {
+                    warning!(conn: fd, "Line longer than the buffer, closing connection");
+                    return self.add_close(fd, CloseReason::LineTooLong);
+                }
+            },
+        };
+
+        if end == 0 {
+            stream.buffer = Some(buffer);
+            if resume {
+                self.arm_receive(fd)?;
+            }
+            if eof {
+                return self.add_shutdown_close(fd, CloseReason::PeerClosed);
+            }
+            return Ok(());
+        }
+
+        stream.sending = true;
+        if resume {
+            self.arm_receive(fd)?;
+        }
+
+        self.transform.apply(&mut buffer.as_mut_slice()[..end]);
+        match self.transform.delay() {
+            Duration::ZERO => self.add_send(fd, buffer, 0, end),
+            delay => self.add_delay(fd, buffer, end, delay),
+        }
+    }
+
+    /// Take the buffer back once a send is done, and pump again
+    ///
+    /// While shutting down there's no waiting for the rest of the backlog,
+    /// the same as with one-shot receives.
+    ///
+    pub(super) fn sent(&mut self, fd: RawFd, buffer: PooledBuffer) -> io::Result<()> {
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(());
+        };
+
+        stream.sending = false;
+        if stream.closing.is_some() {
+            return Ok(());
+        }
+        stream.buffer = Some(buffer);
+
+        if self.state != State::Running {
+            return self.add_shutdown_close(fd, CloseReason::Shutdown);
+        }
+        self.pump(fd)
+    }
+
+    /// Start closing a connection with a multishot receive
+    ///
+    /// Cancels the receive and closes once nothing is in flight anymore
+    /// (see finish_stream). Only the first call counts.
+    ///
+    pub(super) fn close_stream(&mut self, fd: RawFd, graceful: bool) -> io::Result<()> {
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(());
+        };
+
+        stream.closing.get_or_insert(graceful);
+        if let Some(receive) = stream.receive.take() {
+            self.ring
+                .create_entry()
+                .set_cancel(receive, CANCEL_USER_DATA);
+        }
+        self.finish_stream(fd)
+    }
+
+    /// Close a connection that's closing, once nothing is in flight
+    ///
+    /// Called whenever one of its operations has finished.
+    ///
+    pub(super) fn finish_stream(&mut self, fd: RawFd) -> io::Result<()> {
+        let Some(stream) = self.streams.get_mut(&fd) else {
+            return Ok(());
+        };
+        let graceful = match stream.closing {
+            Some(graceful) if stream.in_flight == 0 && !stream.close_queued => graceful,
+            _ => return Ok(()),
+        };
+
+        stream.close_queued = true;
+        // Back to the pool now rather than once the close completes
+        stream.buffer = None;
+
+        if graceful {
+            self.queue_shutdown_close(fd);
+        } else {
+            self.queue_close(fd);
+        }
+        Ok(())
+    }
+
+    /// Set the idle sweep
+    ///
+    /// Only needed with multishot receives and an idle timeout.
+    ///
+    pub(super) fn add_idle_sweep(&mut self) -> io::Result<()> {
+        let interval = match (self.idle_timeout, &self.buffers) {
+            (Some(timeout), Some(_)) => timeout.min(SWEEP_INTERVAL),
+            _ => return Ok(()),
+        };
+
+        let mut ts = Box::new(timespec(interval));
+        let ts_ptr = &mut *ts as *mut __kernel_timespec;
+        let user_data = self.generate_entry_id(Operation::IdleSweep(ts), -1);
+
+        self.ring
+            .create_entry()
+            .set_timeout(ts_ptr, 0, 0, user_data);
+
+        Ok(())
+    }
+
+    /// Handle idle sweep
+    ///
+    /// Closes every connection that hasn't received or sent anything for the
+    /// idle timeout, and sets the sweep again.
+    ///
+    pub(super) fn handle_idle_sweep(&mut self, result: Result<u32, UringError>) -> io::Result<()> {
+        match result {
+            Ok(_) => {}
+            Err(UringError::Canceled { .. }) => return Ok(()),
+            Err(err) => error!("{}", err),
+        }
+        let Some(timeout) = self.idle_timeout else {
+            return Ok(());
+        };
+
+        let idle: Vec<RawFd> = self
+            .connections
+            .iter()
+            .filter(|(fd, connection)| {
+                connection.idle() >= timeout
+                    && self
+                        .streams
+                        .get(fd)
+                        .is_some_and(|stream| stream.closing.is_none())
+            })
+            .map(|(fd, _)| *fd)
+            .collect();
+
+        for fd in idle {
+            info!(conn: fd, "Connection idle, closing");
+            self.add_close(fd, CloseReason::Idle)?;
+        }
+
+        if self.state == State::Running {
+            self.add_idle_sweep()?;
+        }
+        Ok(())
+    }
+}
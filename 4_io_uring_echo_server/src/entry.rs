@@ -2,19 +2,227 @@
 ///
 /// This defines iouring entries for the echo server
 use crate::bindings::*;
+use crate::observer::Tracer;
+use crate::overflow::{Overflow, SqFullPolicy};
+use std::io;
+use std::mem::zeroed;
+use std::net::Shutdown;
+use std::os::raw::c_char;
 use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// Submission flags
+///
+/// The IOSQE_* flags in the kernel header are defined by shifting an enum value
+/// (e.g. `1U << IOSQE_IO_LINK_BIT`), which bindgen can't evaluate, so we spell
+/// them out here.
+///
+const IOSQE_FIXED_FILE: u8 = 1 << 0;
+const IOSQE_IO_DRAIN: u8 = 1 << 1;
+const IOSQE_IO_LINK: u8 = 1 << 2;
+const IOSQE_IO_HARDLINK: u8 = 1 << 3;
+const IOSQE_BUFFER_SELECT: u8 = 1 << 5;
+const IOSQE_CQE_SKIP_SUCCESS: u8 = 1 << 6;
+
+/// Either kind of link, which ties an entry to the one after it
+pub const LINK_FLAGS: u8 = IOSQE_IO_LINK | IOSQE_IO_HARDLINK;
+
+/// Poll events
+///
+/// The readiness events used with set_poll_add, from poll.h.
+///
+#[allow(dead_code)]
+pub const POLL_IN: u32 = 0x001;
+#[allow(dead_code)]
+pub const POLL_OUT: u32 = 0x004;
 
 pub struct Entry<'a> {
     ring: &'a mut io_uring,
+    overflow: &'a mut Overflow,
+    tracer: Option<&'a mut Tracer>,
+    flags: u8,
 }
 
 impl<'a> Entry<'a> {
     /// Create initial Entry
     ///
-    /// We create an Entry with a reference to the io_uring instance.
+    /// We create an Entry with a reference to the io_uring instance, the
+    /// overflow list used when its submission queue is full and the tracer,
+    /// if an observer is attached.
+    ///
+    pub fn new(
+        ring: &'a mut io_uring,
+        overflow: &'a mut Overflow,
+        tracer: Option<&'a mut Tracer>,
+    ) -> Self {
+        Entry {
+            ring,
+            overflow,
+            tracer,
+            flags: 0,
+        }
+    }
+
+    /// Link the next entry
+    ///
+    /// The next operation set on this Entry will be linked to the one after
+    /// it, meaning the second won't start until the first completes. This is
+    /// how a timeout gets attached to a receive (see set_link_timeout).
+    ///
+    pub fn link(&mut self) -> &mut Self {
+        self.flags |= IOSQE_IO_LINK;
+        self
+    }
+
+    /// Hard link the next entry
+    ///
+    /// Like link, except the entry after it still runs if this one fails. A
+    /// plain link cancels the rest of the chain on failure, which is right
+    /// for a receive and its timeout but not for a close that has to happen
+    /// no matter what came before it.
+    ///
+    pub fn hard_link(&mut self) -> &mut Self {
+        self.flags |= IOSQE_IO_HARDLINK;
+        self
+    }
+
+    /// Use a fixed file for the next entry
+    ///
+    /// The fd given to the next operation set on this Entry is taken as an
+    /// index into the ring's fixed file table (see IoUring::register_files)
+    /// rather than a normal fd. This is how direct descriptors from
+    /// set_accept_direct are used, since they have no fd at all.
+    ///
+    #[allow(dead_code)]
+    pub fn fixed_file(&mut self) -> &mut Self {
+        self.flags |= IOSQE_FIXED_FILE;
+        self
+    }
+
+    /// Drain before the next entry
+    ///
+    /// The next operation set on this Entry won't start until everything
+    /// submitted before it has completed, and nothing submitted after it will
+    /// start until it has. This is a barrier for the whole ring, so it's for
+    /// things like closing a socket only once every send queued on it has
+    /// gone out, not for ordering a single pair of operations (use link for
+    /// that).
+    ///
+    /// Note that a multishot operation never completes while it's armed, so a
+    /// drain behind one waits until it's cancelled.
+    ///
+    #[allow(dead_code)]
+    pub fn drain(&mut self) -> &mut Self {
+        self.flags |= IOSQE_IO_DRAIN;
+        self
+    }
+
+    /// Prepare a submission queue entry
     ///
-    pub fn new(ring: &'a mut io_uring) -> Self {
-        Entry { ring }
+    /// Grabs an SQE from the ring, lets the caller fill it in and then sets the
+    /// user_data and any pending flags. The prep functions in liburing clear
+    /// the flags, so they have to be applied afterwards. Flags only ever apply
+    /// to a single SQE.
+    ///
+    /// If the queue is full the overflow policy decides what happens (see
+    /// SqFullPolicy). Once anything is on the overflow list, new entries go
+    /// there too so they're submitted in the order they were made.
+    ///
+    /// An entry that's linked, and the ones after it up to the end of its
+    /// chain, are held back and placed together once the chain is complete,
+    /// so that the queue filling up can't split it (see overflow.rs).
+    ///
+    fn prepare<F>(&mut self, user_data: u64, prep: F)
+    where
+        F: FnOnce(*mut io_uring_sqe),
+    {
+        let linked = self.flags & LINK_FLAGS != 0;
+
+        if linked || !self.overflow.chain.is_empty() {
+            let mut local: io_uring_sqe = unsafe { zeroed() };
+            self.fill(&mut local, user_data, prep);
+            self.overflow.chain.push(local);
+            if !linked {
+                self.place_chain();
+            }
+        } else if self.make_room(1) {
+            let sqe = unsafe { io_uring_get_sqe(self.ring) };
+            self.fill(sqe, user_data, prep);
+        } else {
+            let mut local: io_uring_sqe = unsafe { zeroed() };
+            self.fill(&mut local, user_data, prep);
+            self.overflow.entries.push_back(local);
+            self.overflow.stats.queued += 1;
+        }
+
+        self.flags = 0;
+    }
+
+    /// Places a complete link chain, all in the queue or all on the overflow
+    /// list
+    fn place_chain(&mut self) {
+        let chain = std::mem::take(&mut self.overflow.chain);
+
+        if self.make_room(chain.len() as u32) {
+            for entry in chain {
+                unsafe {
+                    let sqe = io_uring_get_sqe(self.ring);
+                    ptr::write(sqe, entry);
+                }
+            }
+        } else {
+            self.overflow.stats.queued += chain.len() as u64;
+            self.overflow.entries.extend(chain);
+        }
+    }
+
+    /// Checks that the queue has room for this many entries
+    ///
+    /// With the Submit policy, submits to make room if there isn't any. A
+    /// submit that fails is kept for the next call to IoUring::submit to
+    /// return. There's never room while the overflow list isn't empty, since
+    /// whatever is on it has to go in first.
+    ///
+    fn make_room(&mut self, needed: u32) -> bool {
+        if !self.overflow.entries.is_empty() {
+            return false;
+        }
+        if unsafe { io_uring_sq_space_left(self.ring) } >= needed {
+            return true;
+        }
+
+        self.overflow.stats.full += 1;
+        if self.overflow.policy != SqFullPolicy::Submit {
+            return false;
+        }
+
+        self.overflow.stats.auto_submits += 1;
+        let ret = unsafe { io_uring_submit(self.ring) };
+        if ret < 0 {
+            self.overflow
+                .error
+                .get_or_insert(io::Error::from_raw_os_error(-ret));
+            return false;
+        }
+        unsafe { io_uring_sq_space_left(self.ring) >= needed }
+    }
+
+    /// Fills in an SQE, wherever it lives
+    fn fill<F>(&mut self, sqe: *mut io_uring_sqe, user_data: u64, prep: F)
+    where
+        F: FnOnce(*mut io_uring_sqe),
+    {
+        prep(sqe);
+        unsafe {
+            (*sqe).user_data = user_data;
+            (*sqe).flags |= self.flags;
+        }
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.prepared(unsafe { (*sqe).opcode }, user_data);
+        }
     }
 
     pub fn set_accept(
@@ -24,32 +232,503 @@ impl<'a> Entry<'a> {
         addrlen: *mut u32,
         user_data: u64,
     ) {
-        let sqe = unsafe { io_uring_get_sqe(self.ring) };
-        if !sqe.is_null() {
-            unsafe {
-                io_uring_prep_accept(sqe, fd, addr, addrlen, 0);
-                (*sqe).user_data = user_data;
-            }
-        }
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_accept(sqe, fd, addr, addrlen, 0);
+        });
+    }
+
+    /// Set an accept into the fixed file table
+    ///
+    /// Instead of a normal fd the new connection becomes a direct descriptor,
+    /// which only exists inside the ring, so use it with fixed_file and close
+    /// it with set_close_direct. It goes in the given slot, or with None the
+    /// kernel picks a free one and returns it as the result. Needs a file
+    /// table to have been registered (see IoUring::register_files_sparse).
+    ///
+    #[allow(dead_code)]
+    pub fn set_accept_direct(
+        &mut self,
+        fd: RawFd,
+        addr: *mut sockaddr,
+        addrlen: *mut u32,
+        file_index: Option<u32>,
+        user_data: u64,
+    ) {
+        let file_index = file_index.unwrap_or(IORING_FILE_INDEX_ALLOC as u32);
+
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_accept_direct(sqe, fd, addr, addrlen, 0, file_index);
+        });
     }
 
     pub fn set_receive(&mut self, fd: RawFd, buf: *mut u8, len: usize, flags: i32, user_data: u64) {
-        let sqe = unsafe { io_uring_get_sqe(self.ring) };
-        if !sqe.is_null() {
-            unsafe {
-                io_uring_prep_recv(sqe, fd, buf as *mut _, len, flags);
-                (*sqe).user_data = user_data;
-            }
-        }
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_recv(sqe, fd, buf as *mut _, len, flags);
+        });
+    }
+
+    /// Set a multishot receive
+    ///
+    /// A single entry that keeps posting a completion every time data arrives
+    /// on the socket. Since we don't know ahead of time how many reads there
+    /// will be, the kernel picks a buffer for each one from the provided
+    /// buffer group and reports which one it used in the completion flags
+    /// (see Cqe::buffer_id). As long as IORING_CQE_F_MORE is set on the
+    /// completions the receive is still armed; once it isn't, it needs to be
+    /// resubmitted.
+    ///
+    pub fn set_receive_multishot(&mut self, fd: RawFd, buf_group: u16, flags: i32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_recv_multishot(sqe, fd, ptr::null_mut(), 0, flags);
+            (*sqe).flags |= IOSQE_BUFFER_SELECT;
+            (*sqe).__bindgen_anon_4.buf_group = buf_group;
+        });
     }
 
     pub fn set_send(&mut self, fd: RawFd, buf: *const u8, len: usize, flags: i32, user_data: u64) {
-        let sqe = unsafe { io_uring_get_sqe(self.ring) };
-        if !sqe.is_null() {
-            unsafe {
-                io_uring_prep_send(sqe, fd, buf as *mut _, len, flags);
-                (*sqe).user_data = user_data;
-            }
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_send(sqe, fd, buf as *mut _, len, flags);
+        });
+    }
+
+    /// Set a zero-copy send
+    ///
+    /// The kernel sends straight from our buffer instead of copying it first.
+    /// This posts two completions with the same user_data: the usual one with
+    /// the number of bytes sent (with IORING_CQE_F_MORE set), followed later
+    /// by a notification (IORING_CQE_F_NOTIF) once the kernel is done with the
+    /// buffer. The buffer can't be touched until that second one arrives. See
+    /// ZeroCopySender for something that keeps track of that. Requires 6.0+.
+    ///
+    pub fn set_send_zc(
+        &mut self,
+        fd: RawFd,
+        buf: *const u8,
+        len: usize,
+        flags: i32,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_send_zc(sqe, fd, buf as *const _, len, flags, 0);
+        });
+    }
+
+    /// Read into a registered buffer
+    ///
+    /// Works like a regular read, except buf has to point inside the buffer
+    /// registered at buf_index (see IoUring::register_buffers). Since the
+    /// kernel already has those pages pinned, it can skip mapping them on
+    /// every operation. For sockets the offset should be 0.
+    ///
+    pub fn set_read_fixed(
+        &mut self,
+        fd: RawFd,
+        buf: *mut u8,
+        len: u32,
+        offset: u64,
+        buf_index: u16,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_read_fixed(sqe, fd, buf as *mut _, len, offset, buf_index as i32);
+        });
+    }
+
+    /// Write from a registered buffer
+    ///
+    /// The write counterpart to set_read_fixed.
+    ///
+    pub fn set_write_fixed(
+        &mut self,
+        fd: RawFd,
+        buf: *const u8,
+        len: u32,
+        offset: u64,
+        buf_index: u16,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_write_fixed(sqe, fd, buf as *const _, len, offset, buf_index as i32);
+        });
+    }
+
+    /// Send a message
+    ///
+    /// Sends the data described by msg, to the address in msg_name if there is
+    /// one. The msghdr and everything it points to have to stay alive until
+    /// the operation completes.
+    ///
+    pub fn set_sendmsg(&mut self, fd: RawFd, msg: *const msghdr, flags: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_sendmsg(sqe, fd, msg, flags);
+        });
+    }
+
+    /// Receive a message
+    ///
+    /// On completion the kernel has filled in the data, the sender's address
+    /// and any control data, and updated their lengths in msg.
+    ///
+    pub fn set_recvmsg(&mut self, fd: RawFd, msg: *mut msghdr, flags: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_recvmsg(sqe, fd, msg, flags);
+        });
+    }
+
+    /// Open a file
+    ///
+    /// The path is resolved relative to dfd, or the current directory if dfd
+    /// is AT_FDCWD, and has to stay alive until the entry is submitted. On
+    /// success the completion's result is the new file descriptor.
+    ///
+    pub fn set_openat(
+        &mut self,
+        dfd: RawFd,
+        path: *const c_char,
+        flags: i32,
+        mode: u32,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_openat(sqe, dfd, path, flags, mode);
+        });
+    }
+
+    /// Get a file's status
+    ///
+    /// Fills in statxbuf for the file at path, resolved like set_openat. The
+    /// mask picks the fields we care about (STATX_SIZE | STATX_MTIME is enough
+    /// for caching headers) and the kernel sets stx_mask to the ones it
+    /// actually filled in. Both the path and statxbuf have to stay alive until
+    /// the operation completes, not just until it's submitted.
+    ///
+    #[allow(dead_code)]
+    pub fn set_statx(
+        &mut self,
+        dfd: RawFd,
+        path: *const c_char,
+        flags: i32,
+        mask: u32,
+        statxbuf: *mut statx,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_statx(sqe, dfd, path, flags, mask, statxbuf);
+        });
+    }
+
+    /// Read from a file
+    ///
+    /// Reads up to len bytes at offset. An offset of u64::MAX reads from the
+    /// file's current position instead, like read(2).
+    ///
+    pub fn set_read(&mut self, fd: RawFd, buf: *mut u8, len: u32, offset: u64, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_read(sqe, fd, buf as *mut _, len, offset);
+        });
+    }
+
+    /// Write to a file
+    ///
+    /// Same as set_read, including the u64::MAX offset for appending at the
+    /// current position.
+    ///
+    pub fn set_write(&mut self, fd: RawFd, buf: *const u8, len: u32, offset: u64, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_write(sqe, fd, buf as *const _, len, offset);
+        });
+    }
+
+    /// Flush a file to disk
+    ///
+    /// With datasync set only the data is flushed and not the metadata, like
+    /// fdatasync(2). Link it after a write to make sure it runs afterwards, as
+    /// entries are otherwise free to run in any order.
+    ///
+    #[allow(dead_code)]
+    pub fn set_fsync(&mut self, fd: RawFd, datasync: bool, user_data: u64) {
+        let flags = if datasync { IORING_FSYNC_DATASYNC } else { 0 };
+
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_fsync(sqe, fd, flags);
+        });
+    }
+
+    /// Move data between two file descriptors
+    ///
+    /// One side has to be a pipe. Data is moved in the kernel without being
+    /// copied through userspace, so sending a file over a socket is two
+    /// linked splices: file to pipe, then pipe to socket. An offset of -1
+    /// means the fd's current position, and has to be used for pipes.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    #[allow(dead_code)]
+    pub fn set_splice(
+        &mut self,
+        fd_in: RawFd,
+        off_in: i64,
+        fd_out: RawFd,
+        off_out: i64,
+        len: u32,
+        flags: u32,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_splice(sqe, fd_in, off_in, fd_out, off_out, len, flags);
+        });
+    }
+
+    /// Duplicate data from one pipe into another
+    ///
+    /// Like set_splice between two pipes, except the data isn't consumed from
+    /// fd_in, so it can still be read from there afterwards.
+    ///
+    #[allow(dead_code)]
+    pub fn set_tee(&mut self, fd_in: RawFd, fd_out: RawFd, len: u32, flags: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_tee(sqe, fd_in, fd_out, len, flags);
+        });
+    }
+
+    /// Post a completion to another ring
+    ///
+    /// The ring behind ring_fd (see IoUring::ring_fd) gets a completion with
+    /// data as its user_data and len as its result, without us touching its
+    /// queues, which makes it a way for one thread to wake up or hand work to
+    /// another. Threads share a file descriptor table, so an accepted fd can
+    /// be handed over as len. Requires 5.18+.
+    ///
+    /// With skip_local set we don't get a completion of our own for the
+    /// message unless it fails.
+    ///
+    pub fn set_msg_ring(
+        &mut self,
+        ring_fd: RawFd,
+        len: u32,
+        data: u64,
+        skip_local: bool,
+        user_data: u64,
+    ) {
+        if skip_local {
+            self.flags |= IOSQE_CQE_SKIP_SUCCESS;
         }
+
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_msg_ring(sqe, ring_fd, len, data, 0);
+        });
+    }
+
+    /// Wait on a futex
+    ///
+    /// Parks until another thread wakes the futex, completing with 0. The
+    /// kernel first checks that the futex still holds expected and completes
+    /// right away with -EAGAIN if it doesn't, so a wake that lands between
+    /// reading the value and submitting the wait isn't lost. A thread can
+    /// park this way while still waiting on its sockets, and the wait can be
+    /// cancelled like any other operation. Requires 6.7+.
+    ///
+    /// The futex is private to the process, and has to stay alive until the
+    /// wait completes.
+    ///
+    #[allow(dead_code)]
+    pub fn set_futex_wait(&mut self, futex: *const AtomicU32, expected: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_futex_wait(
+                sqe,
+                futex as *mut u32,
+                expected as u64,
+                FUTEX_BITSET_MATCH_ANY as u64,
+                FUTEX2_SIZE_U32 | FUTEX2_PRIVATE,
+                0,
+            );
+        });
+    }
+
+    /// Wake threads waiting on a futex
+    ///
+    /// Wakes up to count waiters, whether they're parked through a ring or
+    /// with a plain futex call, and completes with the number woken. Requires
+    /// 6.7+.
+    ///
+    #[allow(dead_code)]
+    pub fn set_futex_wake(&mut self, futex: *const AtomicU32, count: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_futex_wake(
+                sqe,
+                futex as *mut u32,
+                count as u64,
+                FUTEX_BITSET_MATCH_ANY as u64,
+                FUTEX2_SIZE_U32 | FUTEX2_PRIVATE,
+                0,
+            );
+        });
+    }
+
+    /// Shut down part of a connection
+    ///
+    /// Shutting down the write side sends a FIN once everything already
+    /// queued has gone out, while leaving the read side open so whatever the
+    /// peer still sends can be drained before the socket is closed. Requires
+    /// 5.11+.
+    ///
+    pub fn set_shutdown(&mut self, fd: RawFd, how: Shutdown, user_data: u64) {
+        let how = match how {
+            Shutdown::Read => SHUT_RD,
+            Shutdown::Write => SHUT_WR,
+            Shutdown::Both => SHUT_RDWR,
+        };
+
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_shutdown(sqe, fd, how as i32);
+        });
+    }
+
+    /// Do nothing
+    ///
+    /// Completes with 0 without touching anything, which is a way to get a
+    /// completion for some user_data, e.g. to stand in for an operation that
+    /// was skipped.
+    ///
+    pub fn set_nop(&mut self, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_nop(sqe);
+        });
+    }
+
+    /// Close a file descriptor
+    ///
+    /// The close happens in the kernel like any other operation, so the fd
+    /// shouldn't be reused until the completion arrives.
+    ///
+    pub fn set_close(&mut self, fd: RawFd, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_close(sqe, fd);
+        });
+    }
+
+    /// Close a direct descriptor
+    ///
+    /// Empties the slot in the fixed file table.
+    ///
+    #[allow(dead_code)]
+    pub fn set_close_direct(&mut self, file_index: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_close_direct(sqe, file_index);
+        });
+    }
+
+    /// Wait for a file descriptor to become ready
+    ///
+    /// Completes once any of the events in poll_mask (e.g. POLL_IN) are
+    /// ready, with res set to the events that fired. Nothing is read or
+    /// written; this is for code that does its own non-blocking I/O and just
+    /// needs to know when to try again.
+    ///
+    #[allow(dead_code)]
+    pub fn set_poll_add(&mut self, fd: RawFd, poll_mask: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_poll_add(sqe, fd, poll_mask);
+        });
+    }
+
+    /// Wait for a file descriptor to become ready, repeatedly
+    ///
+    /// Like set_poll_add, except it stays armed and posts a completion each
+    /// time the fd becomes ready. As with the other multishot operations
+    /// IORING_CQE_F_MORE is set while it's still armed.
+    ///
+    #[allow(dead_code)]
+    pub fn set_poll_multishot(&mut self, fd: RawFd, poll_mask: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_poll_multishot(sqe, fd, poll_mask);
+        });
+    }
+
+    /// Remove a pending poll
+    ///
+    /// The target is the user_data the poll was submitted with. The removed
+    /// poll completes with -ECANCELED.
+    ///
+    #[allow(dead_code)]
+    pub fn set_poll_remove(&mut self, target: u64, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_poll_remove(sqe, target);
+        });
+    }
+
+    /// Cancel a pending operation
+    ///
+    /// Finds the in-flight operation that was submitted with target as its
+    /// user_data and cancels it. The cancelled operation still posts its own
+    /// completion with -ECANCELED, so whatever state it refers to has to be
+    /// kept alive until then. The cancel itself completes with 0 on success,
+    /// -ENOENT if nothing matched or -EALREADY if it was too late to stop.
+    ///
+    pub fn set_cancel(&mut self, target: u64, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_cancel64(sqe, target, 0);
+        });
+    }
+
+    /// Cancel every pending operation on a file descriptor
+    ///
+    /// Useful when disconnecting a client since we don't need to know which
+    /// operations happen to be in flight. The result is the number of
+    /// operations that were cancelled. Requires 5.19+.
+    ///
+    #[allow(dead_code)]
+    pub fn set_cancel_fd(&mut self, fd: RawFd, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_cancel_fd(sqe, fd, IORING_ASYNC_CANCEL_ALL);
+        });
+    }
+
+    /// Set a timeout
+    ///
+    /// Completes with -ETIME once the timespec has elapsed, or with 0 once
+    /// count other completions have been posted (a count of 0 means only the
+    /// time matters). The flags are the IORING_TIMEOUT_* values, e.g.
+    /// IORING_TIMEOUT_ABS.
+    ///
+    /// The kernel reads the timespec when the entry is submitted, so it has to
+    /// stay alive until then.
+    ///
+    pub fn set_timeout(
+        &mut self,
+        ts: *mut __kernel_timespec,
+        count: u32,
+        flags: u32,
+        user_data: u64,
+    ) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_timeout(sqe, ts, count, flags);
+        });
+    }
+
+    /// Set a linked timeout
+    ///
+    /// This must directly follow an entry that was set with link(). If that
+    /// operation hasn't finished by the time the timeout expires it is
+    /// cancelled and completes with -ECANCELED, while the timeout completes
+    /// with -ETIME. If the operation finishes first the timeout completes with
+    /// -ECANCELED instead.
+    ///
+    /// As with set_timeout, the timespec must stay alive until submission.
+    ///
+    pub fn set_link_timeout(&mut self, ts: *mut __kernel_timespec, flags: u32, user_data: u64) {
+        self.prepare(user_data, |sqe| unsafe {
+            io_uring_prep_link_timeout(sqe, ts, flags);
+        });
+    }
+}
+
+/// Converts a Duration to a timespec
+///
+/// The timeout operations take a kernel timespec rather than a Duration.
+///
+pub fn timespec(duration: Duration) -> __kernel_timespec {
+    __kernel_timespec {
+        tv_sec: duration.as_secs() as i64,
+        tv_nsec: duration.subsec_nanos() as i64,
     }
 }
@@ -0,0 +1,170 @@
+/// Errors
+///
+/// A failed operation completes with a negative errno in res. On its own that
+/// number says nothing about which operation failed or on which socket, and
+/// some of them aren't really failures depending on the operation (a timeout
+/// completing with ETIME is a timeout that expired). UringError sorts the
+/// common ones into variants that can be matched on, and keeps the operation
+/// and fd alongside:
+///
+///     match UringError::check(Op::Receive, fd, cqe.res) {
+///         Ok(0) => { /* closed */ }
+///         Ok(n) => { /* got n bytes */ }
+///         Err(err) if err.is_disconnect() => { /* peer went away */ }
+///         Err(err) => eprintln!("{}", err),
+///     }
+///
+use crate::bindings::*;
+use std::fmt;
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// The kind of operation that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Accept,
+    Receive,
+    Send,
+    Close,
+    Shutdown,
+    Read,
+    Write,
+    Timeout,
+    Other,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Op::Accept => "accept",
+            Op::Receive => "receive",
+            Op::Send => "send",
+            Op::Close => "close",
+            Op::Shutdown => "shutdown",
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Timeout => "timeout",
+            Op::Other => "operation",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UringError {
+    /// EAGAIN: nothing was ready, try again later
+    WouldBlock { op: Op, fd: RawFd },
+    /// ECONNRESET: the peer reset the connection
+    ConnectionReset { op: Op, fd: RawFd },
+    /// EPIPE: the connection was already shut down for writing
+    BrokenPipe { op: Op, fd: RawFd },
+    /// ECANCELED: cancelled, either explicitly or by a failed link
+    Canceled { op: Op, fd: RawFd },
+    /// ETIME: a linked timeout expired before the operation finished
+    TimedOut { op: Op, fd: RawFd },
+    /// Anything else, with the errno as the kernel gave it
+    Os { op: Op, fd: RawFd, errno: i32 },
+}
+
+const ENOTCONN_ERRNO: i32 = ENOTCONN as i32;
+
+impl UringError {
+    /// Checks a completion's res
+    ///
+    /// Non-negative results are passed through. For Op::Timeout an ETIME
+    /// means the timeout simply expired, so it counts as success.
+    ///
+    pub fn check(op: Op, fd: RawFd, res: i32) -> Result<u32, UringError> {
+        if res >= 0 {
+            return Ok(res as u32);
+        }
+
+        let errno = -res;
+        if op == Op::Timeout && errno == ETIME as i32 {
+            return Ok(0);
+        }
+
+        Err(match errno as u32 {
+            EAGAIN => UringError::WouldBlock { op, fd },
+            ECONNRESET => UringError::ConnectionReset { op, fd },
+            EPIPE => UringError::BrokenPipe { op, fd },
+            ECANCELED => UringError::Canceled { op, fd },
+            ETIME => UringError::TimedOut { op, fd },
+            _ => UringError::Os { op, fd, errno },
+        })
+    }
+
+    /// The operation that failed
+    pub fn op(&self) -> Op {
+        match *self {
+            UringError::WouldBlock { op, .. }
+            | UringError::ConnectionReset { op, .. }
+            | UringError::BrokenPipe { op, .. }
+            | UringError::Canceled { op, .. }
+            | UringError::TimedOut { op, .. }
+            | UringError::Os { op, .. } => op,
+        }
+    }
+
+    /// The fd the operation was on
+    pub fn fd(&self) -> RawFd {
+        match *self {
+            UringError::WouldBlock { fd, .. }
+            | UringError::ConnectionReset { fd, .. }
+            | UringError::BrokenPipe { fd, .. }
+            | UringError::Canceled { fd, .. }
+            | UringError::TimedOut { fd, .. }
+            | UringError::Os { fd, .. } => fd,
+        }
+    }
+
+    /// The errno behind the error
+    pub fn errno(&self) -> i32 {
+        (match *self {
+            UringError::WouldBlock { .. } => EAGAIN,
+            UringError::ConnectionReset { .. } => ECONNRESET,
+            UringError::BrokenPipe { .. } => EPIPE,
+            UringError::Canceled { .. } => ECANCELED,
+            UringError::TimedOut { .. } => ETIME,
+            UringError::Os { errno, .. } => return errno,
+        }) as i32
+    }
+
+    /// Checks if the error means the peer is gone
+    ///
+    /// Nothing more can be sent or received on the socket, so it should just
+    /// be closed. This isn't worth more than a note in the logs. ENOTCONN,
+    /// which a shutdown gets once the peer has reset, counts too.
+    ///
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            UringError::ConnectionReset { .. }
+                | UringError::BrokenPipe { .. }
+                | UringError::Os {
+                    errno: ENOTCONN_ERRNO,
+                    ..
+                }
+        )
+    }
+}
+
+impl fmt::Display for UringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} failed: {}",
+            self.op(),
+            self.fd(),
+            io::Error::from_raw_os_error(self.errno())
+        )
+    }
+}
+
+impl std::error::Error for UringError {}
+
+impl From<UringError> for io::Error {
+    fn from(err: UringError) -> Self {
+        io::Error::from_raw_os_error(err.errno())
+    }
+}
@@ -0,0 +1,86 @@
+/// Fault injection
+///
+/// Makes things go wrong on purpose, so the paths the echo server takes when
+/// they go wrong for real can be exercised rather than just read:
+///
+///     delay:  sleeps up to this long before handling each completion, like a
+///             loaded machine would, so timeouts start firing
+///     drop:   this percentage of sends are swapped for a NOP and never go
+///             out, but complete as if they had, so the peer loses the data
+///             and the connection goes back to receiving
+///     close:  this percentage of receives close their connection instead of
+///             echoing, as if the server had given up on it
+///
+/// The numbers come from a small xorshift generator seeded from the clock, so
+/// every run is different. It's nowhere near good enough for anything but this.
+///
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What faults to inject, from the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultConfig {
+    pub delay: Option<Duration>,
+    pub drop_percent: u8,
+    pub close_percent: u8,
+}
+
+impl FaultConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.delay.is_some() || self.drop_percent > 0 || self.close_percent > 0
+    }
+}
+
+pub struct Faults {
+    config: FaultConfig,
+    state: u64,
+}
+
+impl Faults {
+    pub fn new(config: FaultConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+
+        Self {
+            config,
+            // xorshift gets stuck on zero
+            state: seed | 1,
+        }
+    }
+
+    pub fn config(&self) -> FaultConfig {
+        self.config
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    /// How long to stall before handling the next completion
+    pub fn delay(&mut self) -> Option<Duration> {
+        let max = self.config.delay?;
+        let nanos = self.next() % (max.as_nanos() as u64 + 1);
+        Some(Duration::from_nanos(nanos))
+    }
+
+    /// Whether to drop the next send
+    pub fn drop_send(&mut self) -> bool {
+        self.roll(self.config.drop_percent)
+    }
+
+    /// Whether to close the connection instead of echoing
+    pub fn close(&mut self) -> bool {
+        self.roll(self.config.close_percent)
+    }
+
+    fn roll(&mut self, percent: u8) -> bool {
+        percent > 0 && self.next() % 100 < percent as u64
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
@@ -5,41 +5,892 @@
 /// echo server running.
 ///
 use crate::bindings::*;
-use crate::entry::Entry;
-use std::io;
+use crate::buffer_ring::BufferRing;
+use crate::cqe::Cqe;
+use crate::entry::{timespec, Entry};
+use crate::message::Message;
+use crate::observer::{InFlight, RingObserver, Tracer};
+use crate::overflow::{Overflow, SqFullPolicy, SqStats};
+use crate::probe::{Capabilities, Probe};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::io::{self, IoSliceMut};
 use std::mem::zeroed;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
 use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+/// IoUring builder
+///
+/// Collects the setup flags and parameters for the ring before it's created.
+/// Everything is off by default, which gives the same ring as a plain
+/// io_uring_queue_init.
+///
+pub struct IoUringBuilder {
+    entries: u32,
+    params: io_uring_params,
+    sq_full_policy: SqFullPolicy,
+    napi: Option<(Duration, bool)>,
+}
+
+impl IoUringBuilder {
+    /// Starts a builder for a ring with the given submission queue size
+    pub fn new(entries: u32) -> Self {
+        Self {
+            entries,
+            params: unsafe { zeroed() },
+            sq_full_policy: SqFullPolicy::Submit,
+            napi: None,
+        }
+    }
+
+    /// Sets the completion queue size
+    ///
+    /// By default the kernel makes the completion queue twice the size of the
+    /// submission queue. That's not enough when a single entry can post many
+    /// completions, as multishot receives and accepts do, since then the
+    /// completion queue fills up long before the submission queue does.
+    ///
+    /// It has to be at least as large as the submission queue and at most
+    /// MAX_CQ_ENTRIES (unless clamped). Like the submission queue it's rounded
+    /// up to a power of two; the size actually granted is in IoUring::params.
+    ///
+    #[allow(dead_code)]
+    pub fn cq_entries(mut self, entries: u32) -> Self {
+        self.params.flags |= IORING_SETUP_CQSIZE;
+        self.params.cq_entries = entries;
+        self
+    }
+
+    /// Clamps the queue sizes to the kernel's maximum
+    ///
+    /// Without this asking for more entries than the kernel allows fails.
+    ///
+    pub fn clamp(mut self) -> Self {
+        self.params.flags |= IORING_SETUP_CLAMP;
+        self
+    }
+
+    /// Uses a kernel thread to poll the submission queue
+    ///
+    /// With SQPOLL the kernel starts a thread that watches the submission
+    /// queue, so submitting no longer needs a syscall while that thread is
+    /// awake. After idle_ms without any new entries the thread goes to sleep
+    /// and the next submit has to wake it, which liburing takes care of in
+    /// io_uring_submit. The thread can optionally be pinned to a cpu.
+    ///
+    /// The tradeoff is a kernel thread spinning on a core for as long as it
+    /// is awake. Before 5.11 this also requires root.
+    ///
+    #[allow(dead_code)]
+    pub fn sqpoll(mut self, idle_ms: u32, cpu: Option<u32>) -> Self {
+        self.params.flags |= IORING_SETUP_SQPOLL;
+        self.params.sq_thread_idle = idle_ms;
+
+        if let Some(cpu) = cpu {
+            self.params.flags |= IORING_SETUP_SQ_AFF;
+            self.params.sq_thread_cpu = cpu;
+        }
+        self
+    }
+
+    /// Promises that only a single thread will submit to the ring
+    ///
+    /// Lets the kernel skip some synchronization. Requires 6.0+.
+    ///
+    #[allow(dead_code)]
+    pub fn single_issuer(mut self) -> Self {
+        self.params.flags |= IORING_SETUP_SINGLE_ISSUER;
+        self
+    }
+
+    /// Stops the kernel from interrupting us to run completion work
+    ///
+    /// Completion work is instead run the next time we enter the kernel,
+    /// which is fine as long as we're regularly submitting or waiting.
+    /// Requires 5.19+.
+    ///
+    #[allow(dead_code)]
+    pub fn coop_taskrun(mut self) -> Self {
+        self.params.flags |= IORING_SETUP_COOP_TASKRUN;
+        self
+    }
+
+    /// Defers completion work until we wait for completions
+    ///
+    /// Goes a step further than coop_taskrun: work only runs when we ask for
+    /// completions. Requires single_issuer and 6.1+.
+    ///
+    #[allow(dead_code)]
+    pub fn defer_taskrun(mut self) -> Self {
+        self.params.flags |= IORING_SETUP_DEFER_TASKRUN;
+        self
+    }
+
+    /// Busy polls the network device when waiting on sockets
+    ///
+    /// See IoUring::register_napi, which this calls once the ring is created.
+    ///
+    pub fn napi(mut self, busy_poll: Duration, prefer_busy_poll: bool) -> Self {
+        self.napi = Some((busy_poll, prefer_busy_poll));
+        self
+    }
+
+    /// Sets what happens when the submission queue is full
+    ///
+    /// Defaults to SqFullPolicy::Submit.
+    ///
+    #[allow(dead_code)]
+    pub fn sq_full_policy(mut self, policy: SqFullPolicy) -> Self {
+        self.sq_full_policy = policy;
+        self
+    }
+
+    /// Creates the ring
+    ///
+    /// On success the kernel will have filled in the sizes it actually gave us
+    /// along with the features it supports, which can be read back with
+    /// IoUring::params.
+    ///
+    pub fn build(mut self) -> io::Result<IoUring> {
+        let flags = self.params.flags;
+        if flags & IORING_SETUP_DEFER_TASKRUN != 0 && flags & IORING_SETUP_SINGLE_ISSUER == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "defer_taskrun requires single_issuer",
+            ));
+        }
+
+        if flags & IORING_SETUP_CQSIZE != 0 {
+            self.check_cq_entries()?;
+        }
+
+        let mut ring: io_uring = unsafe { zeroed() };
+        let ret = unsafe { io_uring_queue_init_params(self.entries, &mut ring, &mut self.params) }; // This will return and -errno upon failure
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+
+        let mut ring = IoUring {
+            ring,
+            params: RingParams {
+                sq_entries: self.params.sq_entries,
+                cq_entries: self.params.cq_entries,
+                flags: self.params.flags,
+                features: self.params.features,
+            },
+            owned: HashMap::new(),
+            messages: HashMap::new(),
+            overflow: Overflow::new(self.sq_full_policy),
+            ring_fd_registered: false,
+            owner: None,
+            tracer: None,
+        };
+
+        if let Some((busy_poll, prefer_busy_poll)) = self.napi {
+            ring.register_napi(busy_poll, prefer_busy_poll)?;
+        }
+        Ok(ring)
+    }
+
+    /// Checks the completion queue size before the kernel sees it
+    ///
+    /// The kernel only says EINVAL, which doesn't tell you what was wrong.
+    ///
+    fn check_cq_entries(&self) -> io::Result<()> {
+        let cq_entries = self.params.cq_entries;
+
+        if cq_entries < self.entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cq_entries ({}) must be at least the submission queue size ({})",
+                    cq_entries, self.entries
+                ),
+            ));
+        }
+
+        if cq_entries > MAX_CQ_ENTRIES && self.params.flags & IORING_SETUP_CLAMP == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cq_entries ({}) is over the maximum of {}, use clamp to cap it",
+                    cq_entries, MAX_CQ_ENTRIES
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Ring parameters
+///
+/// What the kernel granted when the ring was created. The sizes may be larger
+/// than requested since they get rounded up to a power of two, and features
+/// holds the IORING_FEAT_* flags of the running kernel.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct RingParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    #[allow(dead_code)]
+    pub flags: u32,
+    pub features: u32,
+}
+
+#[allow(dead_code)]
+impl RingParams {
+    /// Checks for one of the IORING_FEAT_* flags
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.features & feature != 0
+    }
+}
+
+impl fmt::Display for RingParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Ring size: {} submission entries, {} completion entries",
+            self.sq_entries, self.cq_entries
+        )
+    }
+}
+
+/// The largest completion queue the kernel allows (IORING_MAX_CQ_ENTRIES)
+pub const MAX_CQ_ENTRIES: u32 = 65536;
+
+/// The most completions peek_batch will read in one call
+const PEEK_BATCH_SIZE: usize = 256;
+
+/// Owned buffer
+///
+/// A buffer the ring is holding on to for an in-flight operation. Receives
+/// remember that they were receives so the buffer can be trimmed down to what
+/// was actually read.
+///
+struct OwnedBuffer {
+    buffer: Vec<u8>,
+    receive: bool,
+}
 
 pub struct IoUring {
     ring: io_uring,
+    params: RingParams,
+    owned: HashMap<u64, OwnedBuffer>,
+    messages: HashMap<u64, Box<Message>>,
+    overflow: Overflow,
+    ring_fd_registered: bool,
+    /// The thread the ring is tied to, if it's tied to one (see check_thread)
+    owner: Option<ThreadId>,
+    tracer: Option<Tracer>,
 }
 
 impl IoUring {
-    /// Creates an io-uring instance
+    /// Starts building an io-uring instance
     ///
-    /// We create a default (zeroed) out queue. The size of this queue is
-    /// dependent on the version of the kernel you're using.
+    /// The entries are the size of the submission queue. Calling build right
+    /// away gives a default ring.
     ///
-    pub fn new(entries: u32) -> io::Result<Self> {
-        let mut ring: io_uring = unsafe { zeroed() };
-        let ret = unsafe { io_uring_queue_init(entries, &mut ring, 0) }; // This will return and -errno upon failure
+    pub fn builder(entries: u32) -> IoUringBuilder {
+        IoUringBuilder::new(entries)
+    }
+
+    /// The parameters the ring was created with
+    pub fn params(&self) -> RingParams {
+        self.params
+    }
+
+    /// Probes the kernel for supported operations
+    pub fn probe(&mut self) -> io::Result<Probe> {
+        self.check_thread()?;
+        Probe::new(&mut self.ring)
+    }
+
+    /// Works out which higher-level features the kernel supports
+    ///
+    /// Kernels too old to be probed support none of them.
+    ///
+    pub fn capabilities(&mut self) -> Capabilities {
+        self.probe()
+            .map(|probe| Capabilities::detect(&probe))
+            .unwrap_or_default()
+    }
+
+    /// The ring's file descriptor
+    ///
+    /// What another ring needs to send this one messages with set_msg_ring.
+    ///
+    pub fn ring_fd(&self) -> RawFd {
+        self.ring.ring_fd
+    }
+
+    /// Registers the ring's own fd
+    ///
+    /// Every submit and wait is an io_uring_enter call on the ring fd, and
+    /// each of those has the kernel look the fd up. Once it's registered we
+    /// pass an index into a small per-thread table instead, which skips the
+    /// lookup. It only helps when making a lot of syscalls, but it's cheap.
+    ///
+    /// The registration belongs to the thread that made it, so the ring is
+    /// tied to that thread from then on (see check_thread). Requires 5.18+
+    /// (see Capabilities::registered_ring).
+    ///
+    pub fn register_ring_fd(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_register_ring_fd(&mut self.ring) };
 
         if ret < 0 {
             return Err(io::Error::from_raw_os_error(-ret));
         }
-        Ok(Self { ring })
+        self.ring_fd_registered = true;
+        self.owner = Some(thread::current().id());
+        Ok(())
+    }
+
+    /// Unregisters the ring's fd
+    ///
+    /// Dropping the ring does this as well.
+    ///
+    #[allow(dead_code)]
+    pub fn unregister_ring_fd(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_unregister_ring_fd(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        self.ring_fd_registered = false;
+        if !self.is_single_issuer() {
+            self.owner = None;
+        }
+        Ok(())
+    }
+
+    /// Checks if the ring's fd has been registered
+    pub fn is_ring_fd_registered(&self) -> bool {
+        self.ring_fd_registered
+    }
+
+    /// Checks if the ring was set up with a polling thread
+    pub fn is_sqpoll(&self) -> bool {
+        self.ring.flags & IORING_SETUP_SQPOLL != 0
+    }
+
+    /// Checks if the ring was set up for a single submitting thread
+    pub fn is_single_issuer(&self) -> bool {
+        self.ring.flags & IORING_SETUP_SINGLE_ISSUER != 0
+    }
+
+    /// Checks that the ring is used from the thread it's tied to
+    ///
+    /// A single_issuer ring is tied to the first thread that submits to it,
+    /// and a ring with a registered fd to the thread that registered it.
+    /// From any other thread the kernel refuses a single_issuer ring's
+    /// submits, and the registered index is looked up in that thread's table
+    /// instead, where it's either missing or some other ring. So everything
+    /// that calls into the kernel checks first, and fails from the wrong
+    /// thread. Anything else is fine from any thread the ring is moved to.
+    ///
+    fn check_thread(&mut self) -> io::Result<()> {
+        let current = thread::current().id();
+
+        match self.owner {
+            Some(owner) if owner != current => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "ring is tied to another thread",
+            )),
+            Some(_) => Ok(()),
+            None => {
+                if self.is_single_issuer() {
+                    self.owner = Some(current);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks if the polling thread is asleep
+    ///
+    /// When this is true the next submit will make a syscall to wake the
+    /// thread. Useful for seeing how often that happens with a given idle
+    /// time.
+    ///
+    #[allow(dead_code)]
+    pub fn needs_wakeup(&self) -> bool {
+        if !self.is_sqpoll() {
+            return false;
+        }
+        load_shared(self.ring.sq.kflags) & IORING_SQ_NEED_WAKEUP != 0
+    }
+
+    /// Checks the completion queue for overflow
+    ///
+    /// If completions arrive faster than we read them the completion queue
+    /// fills up. Kernels with IORING_FEAT_NODROP (5.5+) hold on to the extra
+    /// completions and set a flag until we make room, while older ones drop
+    /// them and count how many were lost. Either way it means the completion
+    /// queue is too small for the load (see IoUringBuilder::cq_entries) or
+    /// that we're not reading completions often enough.
+    ///
+    pub fn cq_overflow(&self) -> CqOverflow {
+        CqOverflow {
+            pending: load_shared(self.ring.sq.kflags) & IORING_SQ_CQ_OVERFLOW != 0,
+            dropped: load_shared(self.ring.cq.koverflow),
+            invalid_sqes: load_shared(self.ring.sq.kdropped),
+        }
+    }
+
+    /// Flushes overflowed completions
+    ///
+    /// Asks the kernel to move any completions it's holding on to into the
+    /// completion queue, as far as there's room.
+    ///
+    pub fn flush_cq_overflow(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_get_events(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Waits for space in the submission queue
+    ///
+    /// With SQPOLL the queue is emptied by the kernel thread rather than by
+    /// our submit calls, so if it fills up we have to wait for the thread to
+    /// catch up before more entries can be created.
+    ///
+    #[allow(dead_code)]
+    pub fn wait_for_sq_space(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_sqring_wait(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
     }
 
     /// Create a new Entry
-    pub fn create_entry(&mut self) -> Entry {
-        Entry::new(&mut self.ring)
+    pub fn create_entry(&mut self) -> Entry<'_> {
+        Entry::new(&mut self.ring, &mut self.overflow, self.tracer.as_mut())
+    }
+
+    /// Attaches an observer
+    ///
+    /// From then on it's called for every entry prepared and every completion
+    /// reaped (see RingObserver). Only entries prepared after this are timed.
+    ///
+    #[allow(dead_code)]
+    pub fn set_observer(&mut self, observer: Box<dyn RingObserver>) {
+        self.tracer = Some(Tracer::new(observer));
+    }
+
+    /// Operations that haven't completed yet, oldest first
+    ///
+    /// Only tracked while an observer is attached. Something that has been
+    /// in here for much longer than it should is what to look at when a
+    /// connection hangs.
+    ///
+    #[allow(dead_code)]
+    pub fn in_flight(&self) -> Vec<InFlight> {
+        self.tracer
+            .as_ref()
+            .map(|tracer| tracer.in_flight())
+            .unwrap_or_default()
+    }
+
+    /// How often the submission queue has been full so far
+    pub fn sq_stats(&self) -> SqStats {
+        self.overflow.stats
+    }
+
+    /// Number of entries waiting on the overflow list
+    #[allow(dead_code)]
+    pub fn overflowed(&self) -> usize {
+        self.overflow.entries.len()
+    }
+
+    /// Receive into an owned buffer
+    ///
+    /// The ring takes the buffer and holds on to it until the operation
+    /// completes, so unlike set_receive there's no way for it to be freed or
+    /// moved while the kernel is writing into it. Up to buffer.len() bytes are
+    /// read. Get it back with take_buffer once the completion arrives.
+    ///
+    #[allow(dead_code)]
+    pub fn receive_owned(&mut self, fd: RawFd, mut buffer: Vec<u8>, user_data: u64) {
+        let (ptr, len) = (buffer.as_mut_ptr(), buffer.len());
+        self.create_entry().set_receive(fd, ptr, len, 0, user_data);
+
+        // Moving the Vec doesn't move its heap allocation, so the pointer
+        // given to the kernel stays valid.
+        self.owned.insert(
+            user_data,
+            OwnedBuffer {
+                buffer,
+                receive: true,
+            },
+        );
+    }
+
+    /// Send an owned buffer
+    ///
+    /// The whole buffer is sent, and as with receive_owned it's held until
+    /// the completion arrives.
+    ///
+    #[allow(dead_code)]
+    pub fn send_owned(&mut self, fd: RawFd, buffer: Vec<u8>, user_data: u64) {
+        self.create_entry()
+            .set_send(fd, buffer.as_ptr(), buffer.len(), 0, user_data);
+
+        self.owned.insert(
+            user_data,
+            OwnedBuffer {
+                buffer,
+                receive: false,
+            },
+        );
+    }
+
+    /// Open a file with an owned path
+    ///
+    /// The path is copied into a nul-terminated buffer that the ring holds on
+    /// to until the open completes, the same way it does for owned buffers, so
+    /// release it with take_buffer once the completion arrives. Relative paths
+    /// are resolved against the current directory.
+    ///
+    #[allow(dead_code)]
+    pub fn open_owned(
+        &mut self,
+        path: &Path,
+        flags: i32,
+        mode: u32,
+        user_data: u64,
+    ) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains a nul byte"))?;
+        let buffer = path.into_bytes_with_nul();
+
+        self.create_entry().set_openat(
+            AT_FDCWD,
+            buffer.as_ptr() as *const _,
+            flags,
+            mode,
+            user_data,
+        );
+
+        self.owned.insert(
+            user_data,
+            OwnedBuffer {
+                buffer,
+                receive: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Read a file into an owned buffer
+    ///
+    /// Like receive_owned, but from offset in a file. take_buffer truncates
+    /// the buffer to the bytes actually read.
+    ///
+    #[allow(dead_code)]
+    pub fn read_owned(&mut self, fd: RawFd, mut buffer: Vec<u8>, offset: u64, user_data: u64) {
+        let (ptr, len) = (buffer.as_mut_ptr(), buffer.len() as u32);
+        self.create_entry()
+            .set_read(fd, ptr, len, offset, user_data);
+
+        self.owned.insert(
+            user_data,
+            OwnedBuffer {
+                buffer,
+                receive: true,
+            },
+        );
+    }
+
+    /// Write an owned buffer to a file
+    ///
+    /// Like send_owned, but at offset in a file. Use u64::MAX as the offset to
+    /// write at the file's current position, which is what a log wants when
+    /// the file was opened with O_APPEND.
+    ///
+    #[allow(dead_code)]
+    pub fn write_owned(&mut self, fd: RawFd, buffer: Vec<u8>, offset: u64, user_data: u64) {
+        self.create_entry()
+            .set_write(fd, buffer.as_ptr(), buffer.len() as u32, offset, user_data);
+
+        self.owned.insert(
+            user_data,
+            OwnedBuffer {
+                buffer,
+                receive: false,
+            },
+        );
+    }
+
+    /// Receive a message
+    ///
+    /// Like receive_owned, but also captures the sender's address and any
+    /// control data, which is what a UDP socket needs. Get the message back
+    /// with take_message once the completion arrives.
+    ///
+    #[allow(dead_code)]
+    pub fn recvmsg_owned(&mut self, fd: RawFd, mut message: Box<Message>, user_data: u64) {
+        let header = message.header(true);
+        self.create_entry().set_recvmsg(fd, header, 0, user_data);
+
+        // As with owned buffers, moving the box doesn't move the message.
+        self.messages.insert(user_data, message);
+    }
+
+    /// Send a message
+    ///
+    /// Like send_owned, but to the address in the message if it has one.
+    ///
+    #[allow(dead_code)]
+    pub fn sendmsg_owned(&mut self, fd: RawFd, mut message: Box<Message>, user_data: u64) {
+        let header = message.header(false);
+        self.create_entry().set_sendmsg(fd, header, 0, user_data);
+
+        self.messages.insert(user_data, message);
+    }
+
+    /// Takes back the message of a completed operation
+    ///
+    /// Returns None if the completion didn't belong to a recvmsg_owned or
+    /// sendmsg_owned.
+    ///
+    #[allow(dead_code)]
+    pub fn take_message(&mut self, cqe: &Cqe) -> Option<Box<Message>> {
+        let mut message = self.messages.remove(&cqe.user_data)?;
+        message.complete(cqe.res);
+        Some(message)
+    }
+
+    /// Takes back the buffer of a completed operation
+    ///
+    /// Returns None if the completion didn't belong to one of the *_owned
+    /// operations. A successful receive or read's buffer is truncated to the
+    /// number of bytes read. Buffers that are never taken back are only freed
+    /// when the ring is dropped.
+    ///
+    #[allow(dead_code)]
+    pub fn take_buffer(&mut self, cqe: &Cqe) -> Option<Vec<u8>> {
+        let OwnedBuffer {
+            mut buffer,
+            receive,
+        } = self.owned.remove(&cqe.user_data)?;
+
+        if receive && cqe.res >= 0 {
+            buffer.truncate(cqe.res as usize);
+        }
+        Some(buffer)
+    }
+
+    /// Registers a buffer ring
+    ///
+    /// Creates a group of count buffers, each buffer_size bytes, that receives
+    /// can select from by group_id.
+    ///
+    pub fn register_buffer_ring(
+        &mut self,
+        group_id: u16,
+        count: u16,
+        buffer_size: usize,
+    ) -> io::Result<BufferRing> {
+        self.check_thread()?;
+        BufferRing::new(&mut self.ring, group_id, count, buffer_size)
+    }
+
+    /// Unregisters a buffer ring
+    pub fn unregister_buffer_ring(&mut self, buffers: BufferRing) -> io::Result<()> {
+        self.check_thread()?;
+        buffers.free(&mut self.ring)
+    }
+
+    /// Registers fixed buffers
+    ///
+    /// The kernel pins the memory behind each slice once, and from then on
+    /// the read_fixed/write_fixed entries can refer to them by index. The
+    /// slices are only borrowed for the call, so the memory they point to has
+    /// to stay put (no reallocating) until unregister_buffers is called or
+    /// the ring is dropped.
+    ///
+    pub fn register_buffers(&mut self, buffers: &[IoSliceMut]) -> io::Result<()> {
+        self.check_thread()?;
+        // IoSliceMut is guaranteed to have the same layout as iovec on Unix.
+        let ret = unsafe {
+            io_uring_register_buffers(
+                &mut self.ring,
+                buffers.as_ptr() as *const iovec,
+                buffers.len() as u32,
+            )
+        };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Unregisters all fixed buffers
+    #[allow(dead_code)]
+    pub fn unregister_buffers(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_unregister_buffers(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Registers fixed files
+    ///
+    /// Every operation on a normal fd has the kernel look the file up and take
+    /// a reference to it. Registered files are looked up once, and entries
+    /// flagged with fixed_file refer to them by their index in the table
+    /// instead of by fd. A -1 leaves a slot empty.
+    ///
+    #[allow(dead_code)]
+    pub fn register_files(&mut self, fds: &[RawFd]) -> io::Result<()> {
+        self.check_thread()?;
+        let ret =
+            unsafe { io_uring_register_files(&mut self.ring, fds.as_ptr(), fds.len() as u32) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Registers an empty fixed file table
+    ///
+    /// The slots are filled in by direct descriptors, such as the ones
+    /// set_accept_direct creates. Requires 5.19+.
+    ///
+    #[allow(dead_code)]
+    pub fn register_files_sparse(&mut self, count: u32) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_register_files_sparse(&mut self.ring, count) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Unregisters the fixed file table
+    ///
+    /// Any direct descriptors still in it are closed.
+    ///
+    #[allow(dead_code)]
+    pub fn unregister_files(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_unregister_files(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Turns on NAPI busy polling
+    ///
+    /// Normally a packet arriving on the network card raises an interrupt and
+    /// the waiting task is woken up once the kernel has processed it. With
+    /// NAPI busy polling, waiting for completions instead spins on the
+    /// receive queues of the devices behind the ring's sockets for up to
+    /// busy_poll, picking packets up as soon as they land. That trades a core
+    /// spinning for lower and more even latency. With prefer_busy_poll set
+    /// the device also holds off on interrupts while we're polling.
+    ///
+    /// Only sockets on real network devices have a queue to poll, so over
+    /// loopback this changes nothing. Requires 6.9+.
+    ///
+    pub fn register_napi(&mut self, busy_poll: Duration, prefer_busy_poll: bool) -> io::Result<()> {
+        self.check_thread()?;
+        let mut napi = io_uring_napi {
+            busy_poll_to: busy_poll.as_micros().min(u32::MAX as u128) as u32,
+            prefer_busy_poll: prefer_busy_poll as u8,
+            ..Default::default()
+        };
+        let ret = unsafe { io_uring_register_napi(&mut self.ring, &mut napi) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Turns NAPI busy polling off
+    #[allow(dead_code)]
+    pub fn unregister_napi(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let mut napi = io_uring_napi::default();
+        let ret = unsafe { io_uring_unregister_napi(&mut self.ring, &mut napi) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Registers an eventfd for completion notifications
+    ///
+    /// From then on the kernel bumps the eventfd's counter every time a
+    /// completion is posted. That lets something other than this ring's own
+    /// wait calls, such as epoll or another thread blocked reading the fd,
+    /// find out that there's work to do. Reading the fd only resets the
+    /// counter, the completions still have to be read from the ring.
+    ///
+    #[allow(dead_code)]
+    pub fn register_eventfd(&mut self, fd: RawFd) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_register_eventfd(&mut self.ring, fd) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
+    }
+
+    /// Unregisters the eventfd
+    #[allow(dead_code)]
+    pub fn unregister_eventfd(&mut self) -> io::Result<()> {
+        self.check_thread()?;
+        let ret = unsafe { io_uring_unregister_eventfd(&mut self.ring) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(())
     }
 
     /// Submits the entries
     ///
-    /// We can create multiple or a single entry before submitting.
+    /// We can create multiple or a single entry before submitting. Anything
+    /// left on the overflow list is moved into the queue first. If a submit
+    /// made while creating an entry failed (see SqFullPolicy::Submit), that
+    /// error is returned instead, and the entries are left for next time.
     ///
     pub fn submit(&mut self) -> io::Result<usize> {
+        self.check_thread()?;
+        if let Some(err) = self.overflow.error.take() {
+            return Err(err);
+        }
+        self.overflow.flush(&mut self.ring);
+        self.trace_submit();
         let ret = unsafe { io_uring_submit(&mut self.ring) };
 
         if ret < 0 {
@@ -49,6 +900,71 @@ impl IoUring {
         }
     }
 
+    /// Submits the entries and waits for completions
+    ///
+    /// Same as submit, except it blocks in the kernel until at least
+    /// wait_nr completions are ready. They still need to be read with
+    /// peek_completion afterwards.
+    ///
+    pub fn submit_and_wait(&mut self, wait_nr: u32) -> io::Result<usize> {
+        self.check_thread()?;
+        if let Some(err) = self.overflow.error.take() {
+            return Err(err);
+        }
+        self.overflow.flush(&mut self.ring);
+        self.trace_submit();
+        let ret = unsafe { io_uring_submit_and_wait(&mut self.ring, wait_nr) };
+
+        if ret < 0 {
+            Err(io::Error::from_raw_os_error(-ret))
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    /// Waits for a completion
+    ///
+    /// Blocks until a completion is ready and returns it, which is what an
+    /// event loop with nothing else to do should be doing instead of sleeping
+    /// and peeking.
+    ///
+    pub fn wait_completion(&mut self) -> io::Result<Cqe> {
+        self.check_thread()?;
+        let mut cqe: *mut io_uring_cqe = ptr::null_mut();
+        let ret = unsafe { io_uring_wait_cqe(&mut self.ring, &mut cqe) };
+
+        if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(self.take_completion(cqe))
+    }
+
+    /// Waits for a completion, up to a timeout
+    ///
+    /// Returns None if nothing completed in time. Anything left on the
+    /// overflow list is moved into the queue first. Whether queued entries
+    /// then get submitted depends on the kernel: with EXT_ARG the wait
+    /// submits nothing, while liburing's fallback for older kernels submits
+    /// everything queued along with its timeout entry. The raw backend has
+    /// no fallback and fails with EINVAL instead. Call submit first if the
+    /// completion depends on queued entries.
+    ///
+    pub fn wait_completion_timeout(&mut self, timeout: Duration) -> io::Result<Option<Cqe>> {
+        self.check_thread()?;
+        let mut cqe: *mut io_uring_cqe = ptr::null_mut();
+        let mut ts = timespec(timeout);
+        self.overflow.flush(&mut self.ring);
+        self.trace_submit();
+        let ret = unsafe { io_uring_wait_cqe_timeout(&mut self.ring, &mut cqe, &mut ts) };
+
+        if ret == -(ETIME as i32) {
+            return Ok(None);
+        } else if ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret));
+        }
+        Ok(Some(self.take_completion(cqe)))
+    }
+
     /// Peeks the completion queue for completions
     ///
     /// This creates space for a completion queue entry (CQE), then attempt to
@@ -56,22 +972,116 @@ impl IoUring {
     /// will read the entry based on the returned pointer to return and then
     /// register it as "seen" so that it can be cleaned up.
     ///
-    pub fn peek_completion(&mut self) -> Option<io_uring_cqe> {
+    pub fn peek_completion(&mut self) -> Option<Cqe> {
         let mut cqe: *mut io_uring_cqe = ptr::null_mut();
         let ret = unsafe { io_uring_peek_cqe(&mut self.ring, &mut cqe) };
 
         if ret < 0 || cqe.is_null() {
             None
         } else {
-            let result = unsafe { ptr::read(cqe) };
-            unsafe { io_uring_cqe_seen(&mut self.ring, cqe) };
-            Some(result)
+            Some(self.take_completion(cqe))
+        }
+    }
+
+    /// Peeks a batch of completions
+    ///
+    /// Copies as many ready completions as fit into out and marks them all as
+    /// seen with a single advance of the queue, returning how many there
+    /// were. This is a lot cheaper than one peek/seen pair per completion
+    /// when there's a lot going on.
+    ///
+    pub fn peek_batch(&mut self, out: &mut [Cqe]) -> usize {
+        let mut cqes: [*mut io_uring_cqe; PEEK_BATCH_SIZE] = [ptr::null_mut(); PEEK_BATCH_SIZE];
+        let max = out.len().min(PEEK_BATCH_SIZE);
+
+        let count =
+            unsafe { io_uring_peek_batch_cqe(&mut self.ring, cqes.as_mut_ptr(), max as u32) }
+                as usize;
+
+        for (slot, cqe) in out.iter_mut().zip(&cqes[..count]) {
+            *slot = unsafe { Cqe::from_raw(&**cqe) };
+
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.completed(slot);
+            }
+        }
+
+        unsafe { io_uring_cq_advance(&mut self.ring, count as u32) };
+        count
+    }
+
+    /// Copies a completion out of the queue
+    ///
+    /// Once the entry is marked as seen the kernel is free to reuse its slot,
+    /// so we read it first.
+    ///
+    fn take_completion(&mut self, cqe: *mut io_uring_cqe) -> Cqe {
+        let result = unsafe { Cqe::from_raw(&*cqe) };
+        unsafe { io_uring_cqe_seen(&mut self.ring, cqe) };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.completed(&result);
+        }
+        result
+    }
+
+    /// Stamps the traced entries that are about to be submitted
+    fn trace_submit(&mut self) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.submitted();
         }
     }
 }
 
+/// Completion queue overflow
+///
+///     pending: completions are waiting in the kernel for room in the queue.
+///
+///     dropped: completions lost for good on kernels without NODROP.
+///
+///     invalid_sqes: submissions the kernel dropped as invalid.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CqOverflow {
+    pub pending: bool,
+    pub dropped: u32,
+    pub invalid_sqes: u32,
+}
+
+// liburing's own flags in io_uring::int_flags, for a registered ring fd and
+// for register calls going through it
+const INT_FLAG_REG_RING: u8 = 1 << 0;
+const INT_FLAG_REG_REG_RING: u8 = 1 << 1;
+
 impl Drop for IoUring {
     fn drop(&mut self) {
+        // Exiting unregisters the ring fd, which from another thread would
+        // free whatever that thread has at the same index instead. The
+        // kernel drops our registration when its thread exits anyway.
+        if self.ring_fd_registered && self.check_thread().is_err() {
+            self.ring.int_flags &= !(INT_FLAG_REG_RING | INT_FLAG_REG_REG_RING);
+        }
         unsafe { io_uring_queue_exit(&mut self.ring) };
     }
 }
+
+// The raw pointers in io_uring all point into the ring's own mappings and the
+// ones in a Message point into its own box, so the ring can be moved to
+// another thread. It isn't Sync though: every method that touches the queues
+// needs &mut self, and to share a ring between threads it has to go behind a
+// lock (see SharedRing).
+//
+// A single_issuer ring or a registered ring fd ties the ring to one thread.
+// Moving it is still memory safe, since check_thread makes every call into
+// the kernel from another thread fail rather than reach the wrong ring, and
+// Drop doesn't touch the registration from there.
+unsafe impl Send for IoUring {}
+
+/// Reads a value the kernel updates
+///
+/// The ring's flags and counters live in memory shared with the kernel, so
+/// they have to be read atomically.
+///
+fn load_shared(ptr: *mut u32) -> u32 {
+    unsafe { (*(ptr as *const AtomicU32)).load(Ordering::Acquire) }
+}
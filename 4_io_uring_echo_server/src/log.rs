@@ -0,0 +1,160 @@
+/// Log
+///
+/// A tiny leveled logger. Every line gets a UTC timestamp and its level, and
+/// the connection it's about when there is one:
+///
+///     2026-10-16 08:05:09.123 INFO  [fd 6] Accepted new connection from 127.0.0.1:51234
+///
+/// The level is set once at startup and checked before anything is formatted,
+/// so lines below it cost next to nothing. That matters for the per-read and
+/// per-send lines at Debug, which would otherwise cost more than the echo.
+/// Lines go to stderr, leaving stdout for output like the stats line.
+///
+///     info!("Echo server listening on {}", addr);
+///     debug!(conn: fd, "Read {} bytes", len);
+///
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        };
+        // pad rather than write_str, so {:<5} lines the messages up
+        f.pad(name)
+    }
+}
+
+/// Parses a level name, like the ones written before each line but in any case
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("Unknown level: {}", s)),
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the most detailed level that gets logged
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Checks if lines at this level are logged
+///
+/// Only needed to skip expensive work done just for a log line, the macros
+/// already check it.
+///
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a line, used by the macros
+pub fn write(level: Level, conn: Option<RawFd>, args: fmt::Arguments) {
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+
+    let _ = match conn {
+        Some(fd) => writeln!(out, "{} {:<5} [fd {}] {}", timestamp(), level, fd, args),
+        None => writeln!(out, "{} {:<5} {}", timestamp(), level, args),
+    };
+}
+
+/// The current time in UTC, e.g. 2026-10-16 08:05:09.123
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Converts days since 1970-01-01 to a year, month and day
+///
+/// Howard Hinnant's days_from_civil run backwards, which works on 400 year
+/// eras since the calendar repeats after that many years.
+///
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+macro_rules! log {
+    ($level:expr, conn: $fd:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, Some($fd), format_args!($($arg)+));
+        }
+    };
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, None, format_args!($($arg)+));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+// Not warn!, which would clash with the #[warn] attribute
+macro_rules! warning {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+pub(crate) use {debug, error, info, log, warning};
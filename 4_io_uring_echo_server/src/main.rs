@@ -1,3 +1,4 @@
+#[cfg(not(feature = "raw-uring"))]
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
@@ -6,15 +7,92 @@ mod bindings {
     #[cfg(not(rust_analyzer))]
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
+
+mod access_log;
+mod addr;
+mod admin;
+mod bench;
+// With raw-uring the bindings are replaced by our own syscall-based version.
+#[cfg(feature = "raw-uring")]
+#[allow(non_upper_case_globals)]
+#[allow(non_camel_case_types)]
+#[allow(non_snake_case)]
+#[allow(dead_code)]
+#[allow(clippy::missing_safety_doc)]
+#[path = "raw/mod.rs"]
+mod bindings;
+mod config;
+mod connection;
 mod echo_server;
+mod error;
+mod fault;
+mod log;
+mod metrics;
+mod signal;
+mod socket;
+mod toml;
+mod transform;
+mod workers;
+
+// The io_uring wrapper
+mod buffer_pool;
+mod buffer_ring;
+mod cqe;
 mod entry;
 mod iouring;
+mod message;
+mod observer;
+mod overflow;
+mod probe;
+mod reactor;
+mod shared;
+mod slab;
+mod zero_copy;
 
+use crate::config::{Config, USAGE};
 use crate::echo_server::EchoServer;
+use crate::log::info;
+use std::env;
 use std::io;
+use std::process;
 
 fn main() -> io::Result<()> {
-    let mut server = EchoServer::new(8080)?;
-    println!("Echo server listening on port 8080");
+    // Run the registered buffer benchmark instead of the server
+    if env::args().nth(1).as_deref() == Some("bench-fixed") {
+        return bench::fixed_buffers();
+    }
+
+    // Compare latency with and without NAPI busy polling, optionally against
+    // an echo server at the given address
+    if env::args().nth(1).as_deref() == Some("bench-napi") {
+        return bench::napi(env::args().nth(2).as_deref());
+    }
+
+    // Compare submissions per echo with one-shot and multishot receives
+    if env::args().nth(1).as_deref() == Some("bench-recv") {
+        return bench::receive_modes();
+    }
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+
+    let config = match Config::from_args(args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    log::set_level(config.log_level);
+    if config.workers > 1 {
+        return workers::run(&config);
+    }
+
+    let mut server = EchoServer::new(&config)?;
+    info!("Echo server listening on {}", server.local_addr()?);
     server.run()
 }
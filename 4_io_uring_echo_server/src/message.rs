@@ -0,0 +1,143 @@
+/// Message
+///
+/// Everything a sendmsg or recvmsg needs in one place: the data buffer, the
+/// peer's address and an optional buffer for control data (ancillary data like
+/// IP_PKTINFO or SCM_RIGHTS). This is what lets a single UDP socket talk to
+/// many peers through the ring, since every datagram carries its own address.
+///
+/// The msghdr handed to the kernel points into the message itself, so messages
+/// are always boxed to keep those pointers valid while the box is moved around.
+///
+use crate::addr::{from_socket_addr, storage_len, to_socket_addr};
+use crate::bindings::*;
+use std::mem::zeroed;
+use std::net::SocketAddr;
+use std::ptr;
+
+pub struct Message {
+    header: msghdr,
+    iov: iovec,
+    addr: sockaddr_storage,
+    addr_len: socklen_t,
+    buffer: Vec<u8>,
+    control: Vec<u8>,
+    len: usize,
+}
+
+#[allow(dead_code)]
+impl Message {
+    /// Creates a message to receive into
+    ///
+    /// Up to buffer.len() bytes of data and control.len() bytes of control
+    /// data are received. Pass an empty Vec for control if it's not needed.
+    ///
+    pub fn receive(buffer: Vec<u8>, control: Vec<u8>) -> Box<Self> {
+        Self::new(buffer, control, None)
+    }
+
+    /// Creates a message to send
+    ///
+    /// The peer can be left out on a connected socket.
+    ///
+    pub fn send(buffer: Vec<u8>, control: Vec<u8>, to: Option<SocketAddr>) -> Box<Self> {
+        let len = buffer.len();
+        let mut message = Self::new(buffer, control, to);
+        message.len = len;
+        message
+    }
+
+    fn new(buffer: Vec<u8>, control: Vec<u8>, to: Option<SocketAddr>) -> Box<Self> {
+        let (addr, addr_len) = match to {
+            Some(addr) => from_socket_addr(&addr),
+            None => (unsafe { zeroed() }, 0),
+        };
+
+        Box::new(Self {
+            header: unsafe { zeroed() },
+            iov: iovec {
+                iov_base: ptr::null_mut(),
+                iov_len: 0,
+            },
+            addr,
+            addr_len,
+            buffer,
+            control,
+            len: 0,
+        })
+    }
+
+    /// Points the header at the message's own fields
+    ///
+    /// Called right before the message is handed to the kernel, once it's in
+    /// its final place on the heap. On a receive the whole address storage is
+    /// offered and the kernel writes back how much it used.
+    ///
+    pub fn header(&mut self, receive: bool) -> *mut msghdr {
+        if receive {
+            self.addr_len = storage_len();
+        }
+
+        self.iov.iov_base = self.buffer.as_mut_ptr() as *mut _;
+        self.iov.iov_len = self.buffer.len();
+
+        self.header.msg_name = if self.addr_len > 0 {
+            &mut self.addr as *mut _ as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        self.header.msg_namelen = self.addr_len;
+        self.header.msg_iov = &mut self.iov;
+        self.header.msg_iovlen = 1;
+        self.header.msg_control = if self.control.is_empty() {
+            ptr::null_mut()
+        } else {
+            self.control.as_mut_ptr() as *mut _
+        };
+        self.header.msg_controllen = self.control.len() as _;
+        self.header.msg_flags = 0;
+
+        &mut self.header
+    }
+
+    /// Records the result of a receive
+    ///
+    /// The kernel has updated the address and control lengths in the header,
+    /// and res is the number of data bytes.
+    ///
+    pub fn complete(&mut self, res: i32) {
+        if res >= 0 {
+            self.len = res as usize;
+            self.addr_len = self.header.msg_namelen;
+        }
+    }
+
+    /// The peer the message came from or is going to
+    pub fn peer(&self) -> Option<SocketAddr> {
+        to_socket_addr(&self.addr, self.addr_len)
+    }
+
+    /// The data received, or the data to send
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// The control data the kernel filled in on a receive
+    pub fn control(&self) -> &[u8] {
+        let len = self.header.msg_controllen.min(self.control.len());
+        &self.control[..len]
+    }
+
+    /// The MSG_* flags of a receive, e.g. MSG_TRUNC if the datagram didn't fit
+    pub fn flags(&self) -> i32 {
+        self.header.msg_flags
+    }
+
+    /// Gives back the data buffer, truncated to the data
+    pub fn into_buffer(self: Box<Self>) -> Vec<u8> {
+        let Message {
+            mut buffer, len, ..
+        } = *self;
+        buffer.truncate(len);
+        buffer
+    }
+}
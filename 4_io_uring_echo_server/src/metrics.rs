@@ -0,0 +1,112 @@
+/// Metrics
+///
+/// Counters for what the echo server has been doing, printed as a single stats
+/// line every so often (see --stats). The counters only ever go up, so the
+/// reporter remembers where they were at the last report in order to turn
+/// completions into a rate:
+///
+///     accepted 12, rejected 0, active 3, read 1.5 MiB, written 1.5 MiB, 48211 completions/s, sq full 0
+///
+use std::fmt;
+use std::time::Instant;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Metrics {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub completions: u64,
+    /// Submission queue entries the kernel took
+    pub submitted: u64,
+}
+
+/// Stats reporter
+///
+/// Takes a snapshot of the metrics each time it reports.
+///
+pub struct StatsReporter {
+    last: Metrics,
+    last_at: Instant,
+}
+
+impl StatsReporter {
+    pub fn new() -> Self {
+        Self {
+            last: Metrics::default(),
+            last_at: Instant::now(),
+        }
+    }
+
+    /// Builds a report covering the time since the last one
+    ///
+    /// Active connections and SQ-full events are counted elsewhere, so they
+    /// are passed in.
+    ///
+    pub fn report(&mut self, metrics: &Metrics, active: usize, sq_full: u64) -> Report {
+        let elapsed = self.last_at.elapsed().as_secs_f64();
+        let completions = metrics.completions - self.last.completions;
+
+        self.last = *metrics;
+        self.last_at = Instant::now();
+
+        Report {
+            metrics: *metrics,
+            active,
+            sq_full,
+            completions_per_sec: if elapsed > 0.0 {
+                completions as f64 / elapsed
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+pub struct Report {
+    metrics: Metrics,
+    active: usize,
+    sq_full: u64,
+    completions_per_sec: f64,
+}
+
+/// Report Display implementation
+///
+/// The stats line itself.
+///
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "accepted {}, rejected {}, active {}, read {}, written {}, {:.0} completions/s, sq full {}",
+            self.metrics.accepted,
+            self.metrics.rejected,
+            self.active,
+            Bytes(self.metrics.bytes_read),
+            Bytes(self.metrics.bytes_written),
+            self.completions_per_sec,
+            self.sq_full
+        )
+    }
+}
+
+/// A byte count, printed in the largest unit that keeps it above 1
+struct Bytes(u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,172 @@
+/// Observer
+///
+/// Hooks for watching operations go through the ring. An observer attached
+/// with IoUring::set_observer hears about every entry as it's prepared and
+/// every completion as it's reaped, along with how long the operation took
+/// from the submit that sent it to the kernel. That's usually enough to find
+/// an operation that never completes without adding prints to each server.
+///
+use crate::bindings::*;
+use crate::cqe::Cqe;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// RingObserver
+///
+/// The opcode is the raw IORING_OP_* value of the entry (see opcode_name). A
+/// completion whose entry the tracer never saw, e.g. one prepared before the
+/// observer was attached, has no opcode or latency.
+///
+pub trait RingObserver: Send {
+    fn on_prepare(&mut self, opcode: u8, user_data: u64);
+
+    fn on_complete(&mut self, opcode: Option<u8>, cqe: &Cqe, latency: Option<Duration>);
+}
+
+/// StderrObserver
+///
+/// The default observer, which writes a single line per entry and completion
+/// to stderr.
+///
+#[allow(dead_code)]
+pub struct StderrObserver;
+
+impl RingObserver for StderrObserver {
+    fn on_prepare(&mut self, opcode: u8, user_data: u64) {
+        eprintln!("[ring] -> {} user_data {}", opcode_name(opcode), user_data);
+    }
+
+    fn on_complete(&mut self, opcode: Option<u8>, cqe: &Cqe, latency: Option<Duration>) {
+        let name = opcode.map(opcode_name).unwrap_or("UNKNOWN");
+        let latency = latency.map(|l| l.as_micros() as i64).unwrap_or(-1);
+
+        eprintln!(
+            "[ring] <- {} user_data {} res {} flags 0x{:X} after {} us",
+            name, cqe.user_data, cqe.res, cqe.flags, latency
+        );
+    }
+}
+
+/// An operation that hasn't completed yet
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct InFlight {
+    pub opcode: u8,
+    pub user_data: u64,
+    pub age: Duration,
+}
+
+/// Tracer
+///
+/// Remembers the opcode and submit time of every entry until its last
+/// completion arrives, and passes both along to the observer. Entries are
+/// stamped when prepared and again when IoUring::submit sends them, so
+/// entries submitted some other way (like an auto-submit on a full queue)
+/// are timed from when they were prepared.
+///
+pub struct Tracer {
+    observer: Box<dyn RingObserver>,
+    in_flight: HashMap<u64, (u8, Instant)>,
+    unsubmitted: Vec<u64>,
+}
+
+impl Tracer {
+    pub fn new(observer: Box<dyn RingObserver>) -> Self {
+        Self {
+            observer,
+            in_flight: HashMap::new(),
+            unsubmitted: Vec::new(),
+        }
+    }
+
+    pub fn prepared(&mut self, opcode: u8, user_data: u64) {
+        self.in_flight.insert(user_data, (opcode, Instant::now()));
+        self.unsubmitted.push(user_data);
+        self.observer.on_prepare(opcode, user_data);
+    }
+
+    pub fn submitted(&mut self) {
+        let now = Instant::now();
+
+        for user_data in self.unsubmitted.drain(..) {
+            if let Some((_, submitted_at)) = self.in_flight.get_mut(&user_data) {
+                *submitted_at = now;
+            }
+        }
+    }
+
+    /// Multishot operations and zero-copy sends post several completions, so
+    /// an operation is only forgotten once one arrives without IORING_CQE_F_MORE.
+    pub fn completed(&mut self, cqe: &Cqe) {
+        let traced = if cqe.has_more() {
+            self.in_flight.get(&cqe.user_data).copied()
+        } else {
+            self.in_flight.remove(&cqe.user_data)
+        };
+
+        let opcode = traced.map(|(opcode, _)| opcode);
+        let latency = traced.map(|(_, submitted_at)| submitted_at.elapsed());
+        self.observer.on_complete(opcode, cqe, latency);
+    }
+
+    /// Operations still waiting on a completion, oldest first
+    pub fn in_flight(&self) -> Vec<InFlight> {
+        let mut ops: Vec<InFlight> = self
+            .in_flight
+            .iter()
+            .map(|(&user_data, &(opcode, submitted_at))| InFlight {
+                opcode,
+                user_data,
+                age: submitted_at.elapsed(),
+            })
+            .collect();
+
+        ops.sort_by_key(|op| Reverse(op.age));
+        ops
+    }
+}
+
+/// The operations the wrapper can prepare, with their names
+const OPCODE_NAMES: &[(io_uring_op, &str)] = &[
+    (io_uring_op_IORING_OP_NOP, "NOP"),
+    (io_uring_op_IORING_OP_FSYNC, "FSYNC"),
+    (io_uring_op_IORING_OP_READ_FIXED, "READ_FIXED"),
+    (io_uring_op_IORING_OP_WRITE_FIXED, "WRITE_FIXED"),
+    (io_uring_op_IORING_OP_POLL_ADD, "POLL_ADD"),
+    (io_uring_op_IORING_OP_POLL_REMOVE, "POLL_REMOVE"),
+    (io_uring_op_IORING_OP_SENDMSG, "SENDMSG"),
+    (io_uring_op_IORING_OP_RECVMSG, "RECVMSG"),
+    (io_uring_op_IORING_OP_TIMEOUT, "TIMEOUT"),
+    (io_uring_op_IORING_OP_ACCEPT, "ACCEPT"),
+    (io_uring_op_IORING_OP_ASYNC_CANCEL, "ASYNC_CANCEL"),
+    (io_uring_op_IORING_OP_LINK_TIMEOUT, "LINK_TIMEOUT"),
+    (io_uring_op_IORING_OP_OPENAT, "OPENAT"),
+    (io_uring_op_IORING_OP_CLOSE, "CLOSE"),
+    (io_uring_op_IORING_OP_STATX, "STATX"),
+    (io_uring_op_IORING_OP_READ, "READ"),
+    (io_uring_op_IORING_OP_WRITE, "WRITE"),
+    (io_uring_op_IORING_OP_SEND, "SEND"),
+    (io_uring_op_IORING_OP_RECV, "RECV"),
+    (io_uring_op_IORING_OP_SPLICE, "SPLICE"),
+    (io_uring_op_IORING_OP_TEE, "TEE"),
+    (io_uring_op_IORING_OP_SHUTDOWN, "SHUTDOWN"),
+    (io_uring_op_IORING_OP_MSG_RING, "MSG_RING"),
+    (io_uring_op_IORING_OP_SOCKET, "SOCKET"),
+    (io_uring_op_IORING_OP_SEND_ZC, "SEND_ZC"),
+    (io_uring_op_IORING_OP_FUTEX_WAIT, "FUTEX_WAIT"),
+    (io_uring_op_IORING_OP_FUTEX_WAKE, "FUTEX_WAKE"),
+];
+
+/// Names the opcode
+///
+/// Returns a readable name for the operations the wrapper can prepare.
+///
+#[allow(dead_code)]
+pub fn opcode_name(opcode: u8) -> &'static str {
+    OPCODE_NAMES
+        .iter()
+        .find(|(op, _)| *op == opcode as io_uring_op)
+        .map(|(_, name)| *name)
+        .unwrap_or("UNKNOWN")
+}
@@ -0,0 +1,112 @@
+/// Overflow
+///
+/// What to do when the submission queue is full. io_uring_get_sqe returns null
+/// once every slot in the queue is waiting to be submitted, and before this an
+/// entry created at that point was silently dropped, which in a server means
+/// a connection that just stops.
+///
+/// Entries that don't fit are prepared into a plain io_uring_sqe on our side
+/// instead and kept in order until there's room, which is the next call to
+/// submit.
+///
+/// Link chains are never split between the two. Splitting one would leave
+/// the half in the queue to be submitted on its own, e.g. a receive without
+/// its linked timeout, or a timeout linked to nothing, which fails. So the
+/// entries of a chain are held back until its last one is prepared, and then
+/// the whole chain goes into the queue if there's room for all of it, or onto
+/// the overflow list otherwise. A chain has to fit in the submission queue.
+///
+use crate::bindings::*;
+use crate::entry::LINK_FLAGS;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+
+/// What to do when the submission queue is full
+///
+///     Submit: submit what's in the queue right away to make room, and only
+///     fall back to the overflow list if that doesn't free anything up.
+///
+///     Queue: always put the entry on the overflow list, leaving it to the
+///     next submit.
+///
+/// Either way a link chain goes in whole, so with Submit the queue is
+/// submitted as soon as a chain doesn't fit, rather than when it's full.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqFullPolicy {
+    Submit,
+    #[allow(dead_code)]
+    Queue,
+}
+
+/// How often the submission queue has been full
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SqStats {
+    pub full: u64,
+    pub auto_submits: u64,
+    pub queued: u64,
+}
+
+pub struct Overflow {
+    pub policy: SqFullPolicy,
+    pub entries: VecDeque<io_uring_sqe>,
+    /// The link chain being prepared, held back until its last entry
+    pub chain: Vec<io_uring_sqe>,
+    /// An automatic submit that failed, for the next submit to return
+    pub error: Option<io::Error>,
+    pub stats: SqStats,
+}
+
+impl Overflow {
+    pub fn new(policy: SqFullPolicy) -> Self {
+        Self {
+            policy,
+            entries: VecDeque::new(),
+            chain: Vec::new(),
+            error: None,
+            stats: SqStats::default(),
+        }
+    }
+
+    /// Moves queued entries into the submission queue
+    ///
+    /// A chain still being prepared ends here, the same as it would in the
+    /// kernel at the end of a submit, and is queued behind the rest. Chains
+    /// only go in whole, so this stops at the first one that doesn't fit,
+    /// leaving the rest for next time.
+    ///
+    pub fn flush(&mut self, ring: &mut io_uring) {
+        if let Some(last) = self.chain.last_mut() {
+            last.flags &= !LINK_FLAGS;
+            self.entries.extend(mem::take(&mut self.chain));
+        }
+
+        while let Some(len) = self.front_len() {
+            if unsafe { io_uring_sq_space_left(ring) } < len as u32 {
+                break;
+            }
+
+            for entry in self.entries.drain(..len) {
+                unsafe {
+                    let sqe = io_uring_get_sqe(ring);
+                    std::ptr::write(sqe, entry);
+                }
+            }
+        }
+    }
+
+    /// How many entries the first chain on the list has, 1 if it isn't one
+    fn front_len(&self) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let end = self
+            .entries
+            .iter()
+            .position(|entry| entry.flags & LINK_FLAGS == 0)
+            .unwrap_or(self.entries.len() - 1);
+        Some(end + 1)
+    }
+}
@@ -0,0 +1,109 @@
+/// Probe
+///
+/// Asks the kernel which io_uring operations it supports. io_uring has grown a
+/// lot since 5.1, and an operation the kernel doesn't know about doesn't fail
+/// when it's submitted; it fails later as a completion with -EINVAL, which is
+/// hard to tell apart from a bad argument. Probing once at startup lets us
+/// pick a fallback instead.
+///
+use crate::bindings::*;
+use std::fmt;
+use std::io;
+
+pub struct Probe {
+    probe: *mut io_uring_probe,
+}
+
+impl Probe {
+    /// Probes the kernel through an existing ring
+    pub fn new(ring: &mut io_uring) -> io::Result<Self> {
+        let probe = unsafe { io_uring_get_probe_ring(ring) };
+
+        if probe.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Kernel does not support probing (requires 5.6+)",
+            ));
+        }
+        Ok(Self { probe })
+    }
+
+    /// Checks if an operation is supported
+    ///
+    /// The op is one of the io_uring_op_IORING_OP_* values.
+    ///
+    pub fn supports(&self, op: u32) -> bool {
+        unsafe { io_uring_opcode_supported(self.probe, op as i32) != 0 }
+    }
+
+    /// The highest opcode the kernel knows about
+    #[allow(dead_code)]
+    pub fn last_op(&self) -> u8 {
+        unsafe { (*self.probe).last_op }
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        unsafe { io_uring_free_probe(self.probe) };
+    }
+}
+
+/// Capabilities
+///
+/// The higher-level features built on top of the wrapper that need a newer
+/// kernel. Not all of these are opcodes of their own (multishot receive is
+/// just a flag on a regular receive), so they're inferred from operations that
+/// were added in the same kernel release:
+///
+///     5.18: IORING_OP_MSG_RING, registered ring fd
+///     5.19: IORING_OP_SOCKET, multishot accept, provided buffer rings
+///     6.0:  IORING_OP_SEND_ZC, multishot receive
+///     6.7:  IORING_OP_FUTEX_WAIT and IORING_OP_FUTEX_WAKE
+///
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Capabilities {
+    pub registered_ring: bool,
+    pub multishot_accept: bool,
+    pub multishot_recv: bool,
+    pub buffer_rings: bool,
+    pub send_zc: bool,
+    pub futex: bool,
+}
+
+impl Capabilities {
+    /// Works out the capabilities from a probe
+    pub fn detect(probe: &Probe) -> Self {
+        let msg_ring = probe.supports(io_uring_op_IORING_OP_MSG_RING);
+        let socket = probe.supports(io_uring_op_IORING_OP_SOCKET);
+        let send_zc = probe.supports(io_uring_op_IORING_OP_SEND_ZC);
+
+        Self {
+            registered_ring: msg_ring,
+            multishot_accept: socket,
+            multishot_recv: send_zc,
+            buffer_rings: socket,
+            send_zc,
+            futex: probe.supports(io_uring_op_IORING_OP_FUTEX_WAIT)
+                && probe.supports(io_uring_op_IORING_OP_FUTEX_WAKE),
+        }
+    }
+}
+
+/// Capabilities Display implementation
+///
+/// Prints a small report, meant for server startup.
+///
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |supported: bool| if supported { "yes" } else { "no (fallback)" };
+
+        writeln!(f, "io_uring capabilities:")?;
+        writeln!(f, "  registered ring:  {}", yes_no(self.registered_ring))?;
+        writeln!(f, "  multishot accept: {}", yes_no(self.multishot_accept))?;
+        writeln!(f, "  multishot recv:   {}", yes_no(self.multishot_recv))?;
+        writeln!(f, "  buffer rings:     {}", yes_no(self.buffer_rings))?;
+        writeln!(f, "  zero-copy send:   {}", yes_no(self.send_zc))?;
+        write!(f, "  futex wait/wake:  {}", yes_no(self.futex))
+    }
+}
@@ -0,0 +1,27 @@
+/// Raw io_uring
+///
+/// A pure-Rust stand-in for the parts of liburing the wrapper uses, enabled
+/// with the raw-uring feature. Instead of linking liburing and generating
+/// bindings for it, the ring is set up with the io_uring_setup syscall and its
+/// queues are memory-mapped by hand, which is all liburing does under the hood.
+/// No bindgen, gcc or liburing is needed to build it, just a 5.1+ kernel.
+///
+/// Everything is named and shaped after the bindgen output of liburing.h so the
+/// rest of the crate compiles against either one unchanged:
+///
+///     types:    the kernel's structures and constants (io_uring.h and friends)
+///     sys:      the syscalls and mmap
+///     queue:    setting up the ring, submitting and reaping completions
+///     prep:     the io_uring_prep_* functions that fill in entries
+///     register: registering buffers, eventfds, buffer rings and probing
+///
+mod prep;
+mod queue;
+mod register;
+mod sys;
+mod types;
+
+pub use prep::*;
+pub use queue::*;
+pub use register::*;
+pub use types::*;
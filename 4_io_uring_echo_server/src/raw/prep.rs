@@ -0,0 +1,472 @@
+/// Prep
+///
+/// The io_uring_prep_* functions, which do nothing more than fill in the
+/// fields of an entry for a given operation. Most of them go through prep_rw,
+/// which clears the entry and sets the fields every operation has; the rest is
+/// whatever the operation keeps in the remaining fields.
+///
+use super::types::*;
+use std::mem::zeroed;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::ptr;
+
+unsafe fn prep_rw<'a>(
+    op: io_uring_op,
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    addr: *const c_void,
+    len: c_uint,
+    offset: u64,
+) -> &'a mut io_uring_sqe {
+    let sqe = &mut *sqe;
+    *sqe = zeroed();
+    sqe.opcode = op as u8;
+    sqe.fd = fd;
+    sqe.off = offset;
+    sqe.addr = addr as u64;
+    sqe.len = len;
+    sqe
+}
+
+/// Makes an operation put the file it creates in the fixed file table
+///
+/// The slot is stored as index + 1, since 0 means a normal fd.
+///
+unsafe fn set_target_fixed_file(sqe: *mut io_uring_sqe, file_index: c_uint) {
+    (*sqe).file_index = file_index.wrapping_add(1);
+}
+
+pub unsafe fn io_uring_prep_nop(sqe: *mut io_uring_sqe) {
+    prep_rw(io_uring_op_IORING_OP_NOP, sqe, -1, ptr::null(), 0, 0);
+}
+
+pub unsafe fn io_uring_prep_accept(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    addr: *mut sockaddr,
+    addrlen: *mut socklen_t,
+    flags: c_int,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_ACCEPT,
+        sqe,
+        fd,
+        addr as *const _,
+        0,
+        addrlen as u64,
+    );
+    sqe.rw_flags = flags as u32;
+}
+
+/// With IORING_FILE_INDEX_ALLOC the kernel picks a free slot and returns it
+/// as the result instead of an fd.
+pub unsafe fn io_uring_prep_accept_direct(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    addr: *mut sockaddr,
+    addrlen: *mut socklen_t,
+    flags: c_int,
+    file_index: c_uint,
+) {
+    io_uring_prep_accept(sqe, fd, addr, addrlen, flags);
+
+    // Allocation is requested with a stored value of IORING_FILE_INDEX_ALLOC,
+    // so undo the + 1.
+    let file_index = if file_index == IORING_FILE_INDEX_ALLOC as c_uint {
+        file_index - 1
+    } else {
+        file_index
+    };
+    set_target_fixed_file(sqe, file_index);
+}
+
+pub unsafe fn io_uring_prep_recv(
+    sqe: *mut io_uring_sqe,
+    sockfd: c_int,
+    buf: *mut c_void,
+    len: usize,
+    flags: c_int,
+) {
+    let sqe = prep_rw(io_uring_op_IORING_OP_RECV, sqe, sockfd, buf, len as u32, 0);
+    sqe.rw_flags = flags as u32;
+}
+
+pub unsafe fn io_uring_prep_recv_multishot(
+    sqe: *mut io_uring_sqe,
+    sockfd: c_int,
+    buf: *mut c_void,
+    len: usize,
+    flags: c_int,
+) {
+    io_uring_prep_recv(sqe, sockfd, buf, len, flags);
+    (*sqe).ioprio |= IORING_RECV_MULTISHOT as u16;
+}
+
+pub unsafe fn io_uring_prep_send(
+    sqe: *mut io_uring_sqe,
+    sockfd: c_int,
+    buf: *const c_void,
+    len: usize,
+    flags: c_int,
+) {
+    let sqe = prep_rw(io_uring_op_IORING_OP_SEND, sqe, sockfd, buf, len as u32, 0);
+    sqe.rw_flags = flags as u32;
+}
+
+pub unsafe fn io_uring_prep_send_zc(
+    sqe: *mut io_uring_sqe,
+    sockfd: c_int,
+    buf: *const c_void,
+    len: usize,
+    flags: c_int,
+    zc_flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_SEND_ZC,
+        sqe,
+        sockfd,
+        buf,
+        len as u32,
+        0,
+    );
+    sqe.rw_flags = flags as u32;
+    sqe.ioprio = zc_flags as u16;
+}
+
+pub unsafe fn io_uring_prep_sendmsg(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    msg: *const msghdr,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_SENDMSG,
+        sqe,
+        fd,
+        msg as *const _,
+        1,
+        0,
+    );
+    sqe.rw_flags = flags;
+}
+
+pub unsafe fn io_uring_prep_recvmsg(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    msg: *mut msghdr,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_RECVMSG,
+        sqe,
+        fd,
+        msg as *const _,
+        1,
+        0,
+    );
+    sqe.rw_flags = flags;
+}
+
+pub unsafe fn io_uring_prep_timeout(
+    sqe: *mut io_uring_sqe,
+    ts: *mut __kernel_timespec,
+    count: c_uint,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_TIMEOUT,
+        sqe,
+        -1,
+        ts as *const _,
+        1,
+        count as u64,
+    );
+    sqe.rw_flags = flags;
+}
+
+pub unsafe fn io_uring_prep_link_timeout(
+    sqe: *mut io_uring_sqe,
+    ts: *mut __kernel_timespec,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_LINK_TIMEOUT,
+        sqe,
+        -1,
+        ts as *const _,
+        1,
+        0,
+    );
+    sqe.rw_flags = flags;
+}
+
+pub unsafe fn io_uring_prep_close(sqe: *mut io_uring_sqe, fd: c_int) {
+    prep_rw(io_uring_op_IORING_OP_CLOSE, sqe, fd, ptr::null(), 0, 0);
+}
+
+pub unsafe fn io_uring_prep_close_direct(sqe: *mut io_uring_sqe, file_index: c_uint) {
+    io_uring_prep_close(sqe, 0);
+    set_target_fixed_file(sqe, file_index);
+}
+
+pub unsafe fn io_uring_prep_read_fixed(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    buf: *mut c_void,
+    nbytes: c_uint,
+    offset: u64,
+    buf_index: c_int,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_READ_FIXED,
+        sqe,
+        fd,
+        buf,
+        nbytes,
+        offset,
+    );
+    sqe.__bindgen_anon_4.buf_index = buf_index as u16;
+}
+
+pub unsafe fn io_uring_prep_write_fixed(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    buf: *const c_void,
+    nbytes: c_uint,
+    offset: u64,
+    buf_index: c_int,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_WRITE_FIXED,
+        sqe,
+        fd,
+        buf,
+        nbytes,
+        offset,
+    );
+    sqe.__bindgen_anon_4.buf_index = buf_index as u16;
+}
+
+pub unsafe fn io_uring_prep_cancel64(sqe: *mut io_uring_sqe, user_data: u64, flags: c_int) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_ASYNC_CANCEL,
+        sqe,
+        -1,
+        ptr::null(),
+        0,
+        0,
+    );
+    sqe.addr = user_data;
+    sqe.rw_flags = flags as u32;
+}
+
+pub unsafe fn io_uring_prep_cancel_fd(sqe: *mut io_uring_sqe, fd: c_int, flags: c_uint) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_ASYNC_CANCEL,
+        sqe,
+        fd,
+        ptr::null(),
+        0,
+        0,
+    );
+    sqe.rw_flags = flags | IORING_ASYNC_CANCEL_FD;
+}
+
+/// The poll mask goes in as the 32-bit poll32_events, which is stored little
+/// endian; like the rest of the kernel ABI here this assumes a little endian
+/// machine.
+pub unsafe fn io_uring_prep_poll_add(sqe: *mut io_uring_sqe, fd: c_int, poll_mask: c_uint) {
+    let sqe = prep_rw(io_uring_op_IORING_OP_POLL_ADD, sqe, fd, ptr::null(), 0, 0);
+    sqe.rw_flags = poll_mask;
+}
+
+pub unsafe fn io_uring_prep_poll_multishot(sqe: *mut io_uring_sqe, fd: c_int, poll_mask: c_uint) {
+    io_uring_prep_poll_add(sqe, fd, poll_mask);
+    (*sqe).len = IORING_POLL_ADD_MULTI;
+}
+
+pub unsafe fn io_uring_prep_poll_remove(sqe: *mut io_uring_sqe, user_data: u64) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_POLL_REMOVE,
+        sqe,
+        -1,
+        ptr::null(),
+        0,
+        0,
+    );
+    sqe.addr = user_data;
+}
+
+pub unsafe fn io_uring_prep_openat(
+    sqe: *mut io_uring_sqe,
+    dfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_OPENAT,
+        sqe,
+        dfd,
+        path as *const _,
+        mode,
+        0,
+    );
+    sqe.rw_flags = flags as u32;
+}
+
+pub unsafe fn io_uring_prep_read(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    buf: *mut c_void,
+    nbytes: c_uint,
+    offset: u64,
+) {
+    prep_rw(io_uring_op_IORING_OP_READ, sqe, fd, buf, nbytes, offset);
+}
+
+pub unsafe fn io_uring_prep_write(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    buf: *const c_void,
+    nbytes: c_uint,
+    offset: u64,
+) {
+    prep_rw(io_uring_op_IORING_OP_WRITE, sqe, fd, buf, nbytes, offset);
+}
+
+pub unsafe fn io_uring_prep_fsync(sqe: *mut io_uring_sqe, fd: c_int, fsync_flags: c_uint) {
+    let sqe = prep_rw(io_uring_op_IORING_OP_FSYNC, sqe, fd, ptr::null(), 0, 0);
+    sqe.rw_flags = fsync_flags;
+}
+
+pub unsafe fn io_uring_prep_statx(
+    sqe: *mut io_uring_sqe,
+    dfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mask: c_uint,
+    statxbuf: *mut statx,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_STATX,
+        sqe,
+        dfd,
+        path as *const _,
+        mask,
+        statxbuf as u64,
+    );
+    sqe.rw_flags = flags as u32;
+}
+
+pub unsafe fn io_uring_prep_splice(
+    sqe: *mut io_uring_sqe,
+    fd_in: c_int,
+    off_in: i64,
+    fd_out: c_int,
+    off_out: i64,
+    nbytes: c_uint,
+    splice_flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_SPLICE,
+        sqe,
+        fd_out,
+        ptr::null(),
+        nbytes,
+        off_out as u64,
+    );
+    sqe.addr = off_in as u64;
+    sqe.file_index = fd_in as u32;
+    sqe.rw_flags = splice_flags;
+}
+
+pub unsafe fn io_uring_prep_tee(
+    sqe: *mut io_uring_sqe,
+    fd_in: c_int,
+    fd_out: c_int,
+    nbytes: c_uint,
+    splice_flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_TEE,
+        sqe,
+        fd_out,
+        ptr::null(),
+        nbytes,
+        0,
+    );
+    sqe.file_index = fd_in as u32;
+    sqe.rw_flags = splice_flags;
+}
+
+pub unsafe fn io_uring_prep_msg_ring(
+    sqe: *mut io_uring_sqe,
+    fd: c_int,
+    len: c_uint,
+    data: u64,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_MSG_RING,
+        sqe,
+        fd,
+        ptr::null(),
+        len,
+        data,
+    );
+    sqe.rw_flags = flags;
+}
+
+/// The futex flags (FUTEX2_*) go in fd and the value in off, while the
+/// operation's own flags take the place of rw_flags.
+pub unsafe fn io_uring_prep_futex_wait(
+    sqe: *mut io_uring_sqe,
+    futex: *mut u32,
+    val: u64,
+    mask: u64,
+    futex_flags: u32,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_FUTEX_WAIT,
+        sqe,
+        futex_flags as c_int,
+        futex as *const _,
+        0,
+        val,
+    );
+    sqe.rw_flags = flags;
+    sqe.addr3 = mask;
+}
+
+pub unsafe fn io_uring_prep_futex_wake(
+    sqe: *mut io_uring_sqe,
+    futex: *mut u32,
+    val: u64,
+    mask: u64,
+    futex_flags: u32,
+    flags: c_uint,
+) {
+    let sqe = prep_rw(
+        io_uring_op_IORING_OP_FUTEX_WAKE,
+        sqe,
+        futex_flags as c_int,
+        futex as *const _,
+        0,
+        val,
+    );
+    sqe.rw_flags = flags;
+    sqe.addr3 = mask;
+}
+
+pub unsafe fn io_uring_prep_shutdown(sqe: *mut io_uring_sqe, fd: c_int, how: c_int) {
+    prep_rw(
+        io_uring_op_IORING_OP_SHUTDOWN,
+        sqe,
+        fd,
+        ptr::null(),
+        how as u32,
+        0,
+    );
+}
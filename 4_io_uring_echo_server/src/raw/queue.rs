@@ -0,0 +1,432 @@
+/// Queue
+///
+/// Setting up and tearing down the ring, handing out submission queue entries,
+/// submitting them and reading completions back.
+///
+/// Both queues are ring buffers whose head and tail live in memory shared with
+/// the kernel. For the submission queue we own the tail and the kernel owns
+/// the head; for the completion queue it's the other way around. Whoever owns
+/// an index publishes it with a release store once the entries it covers are
+/// written, and the other side reads it with an acquire load before looking at
+/// those entries. Indexes only ever count up and wrap around, and are masked
+/// to find the slot.
+///
+use super::register::io_uring_unregister_ring_fd;
+use super::sys::*;
+use super::types::*;
+use std::mem::{size_of, zeroed};
+use std::os::raw::{c_int, c_uint, c_void};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+
+unsafe fn load_acquire(ptr: *const c_uint) -> c_uint {
+    (*(ptr as *const AtomicU32)).load(Ordering::Acquire)
+}
+
+unsafe fn store_release(ptr: *mut c_uint, value: c_uint) {
+    (*(ptr as *const AtomicU32)).store(value, Ordering::Release)
+}
+
+pub unsafe fn io_uring_queue_init(entries: c_uint, ring: *mut io_uring, flags: c_uint) -> c_int {
+    let mut p: io_uring_params = zeroed();
+    p.flags = flags;
+    io_uring_queue_init_params(entries, ring, &mut p)
+}
+
+/// Creates a ring and maps its queues
+///
+/// The kernel fills in p with the sizes it gave us and where in the mapped
+/// regions each of the queue's fields are. Since 5.4 (IORING_FEAT_SINGLE_MMAP)
+/// both rings live in one mapping; before that the completion ring has its
+/// own. The entries themselves are always mapped separately.
+///
+pub unsafe fn io_uring_queue_init_params(
+    entries: c_uint,
+    ring: *mut io_uring,
+    p: *mut io_uring_params,
+) -> c_int {
+    let fd = io_uring_setup(entries, p);
+    if fd < 0 {
+        return fd;
+    }
+
+    let ring = &mut *ring;
+    let p = &*p;
+    *ring = zeroed();
+
+    let sq_size = p.sq_off.array as usize + p.sq_entries as usize * size_of::<c_uint>();
+    let cq_size = p.cq_off.cqes as usize + p.cq_entries as usize * size_of::<io_uring_cqe>();
+    let single_mmap = p.features & IORING_FEAT_SINGLE_MMAP != 0;
+
+    let (sq_size, cq_size) = if single_mmap {
+        let size = sq_size.max(cq_size);
+        (size, size)
+    } else {
+        (sq_size, cq_size)
+    };
+
+    let sq_ptr = match map_ring(fd, sq_size, IORING_OFF_SQ_RING) {
+        Ok(ptr) => ptr,
+        Err(err) => {
+            close_fd(fd);
+            return err;
+        }
+    };
+
+    let cq_ptr = if single_mmap {
+        sq_ptr
+    } else {
+        match map_ring(fd, cq_size, IORING_OFF_CQ_RING) {
+            Ok(ptr) => ptr,
+            Err(err) => {
+                unmap(sq_ptr, sq_size);
+                close_fd(fd);
+                return err;
+            }
+        }
+    };
+
+    let sqes_size = p.sq_entries as usize * size_of::<io_uring_sqe>();
+    let sqes = match map_ring(fd, sqes_size, IORING_OFF_SQES) {
+        Ok(ptr) => ptr as *mut io_uring_sqe,
+        Err(err) => {
+            if !single_mmap {
+                unmap(cq_ptr, cq_size);
+            }
+            unmap(sq_ptr, sq_size);
+            close_fd(fd);
+            return err;
+        }
+    };
+
+    let at = |base: *mut c_void, offset: u32| (base as *mut u8).add(offset as usize) as *mut c_uint;
+
+    let sq = &mut ring.sq;
+    sq.khead = at(sq_ptr, p.sq_off.head);
+    sq.ktail = at(sq_ptr, p.sq_off.tail);
+    sq.kring_mask = at(sq_ptr, p.sq_off.ring_mask);
+    sq.kring_entries = at(sq_ptr, p.sq_off.ring_entries);
+    sq.kflags = at(sq_ptr, p.sq_off.flags);
+    sq.kdropped = at(sq_ptr, p.sq_off.dropped);
+    sq.array = at(sq_ptr, p.sq_off.array);
+    sq.sqes = sqes;
+    sq.ring_sz = sq_size;
+    sq.ring_ptr = sq_ptr;
+    sq.ring_mask = *sq.kring_mask;
+    sq.ring_entries = *sq.kring_entries;
+
+    let cq = &mut ring.cq;
+    cq.khead = at(cq_ptr, p.cq_off.head);
+    cq.ktail = at(cq_ptr, p.cq_off.tail);
+    cq.kring_mask = at(cq_ptr, p.cq_off.ring_mask);
+    cq.kring_entries = at(cq_ptr, p.cq_off.ring_entries);
+    cq.koverflow = at(cq_ptr, p.cq_off.overflow);
+    cq.kflags = if p.cq_off.flags != 0 {
+        at(cq_ptr, p.cq_off.flags)
+    } else {
+        ptr::null_mut()
+    };
+    cq.cqes = at(cq_ptr, p.cq_off.cqes) as *mut io_uring_cqe;
+    cq.ring_sz = cq_size;
+    cq.ring_ptr = cq_ptr;
+    cq.ring_mask = *cq.kring_mask;
+    cq.ring_entries = *cq.kring_entries;
+
+    // The array maps ring slots to entries. We always fill entries in ring
+    // order, so it can be set up once as slot i -> entry i.
+    for i in 0..ring.sq.ring_entries {
+        *ring.sq.array.add(i as usize) = i;
+    }
+
+    ring.flags = p.flags;
+    ring.ring_fd = fd;
+    ring.enter_ring_fd = fd;
+    ring.features = p.features;
+    0
+}
+
+/// Calls io_uring_enter, through the registered ring fd if there is one
+unsafe fn enter(
+    ring: &io_uring,
+    to_submit: c_uint,
+    min_complete: c_uint,
+    flags: c_uint,
+    arg: *const c_void,
+    argsz: usize,
+) -> c_int {
+    let flags = if ring.int_flags & INT_FLAG_REG_RING != 0 {
+        flags | IORING_ENTER_REGISTERED_RING
+    } else {
+        flags
+    };
+    io_uring_enter(
+        ring.enter_ring_fd,
+        to_submit,
+        min_complete,
+        flags,
+        arg,
+        argsz,
+    )
+}
+
+pub unsafe fn io_uring_queue_exit(ring: *mut io_uring) {
+    // A registered ring fd holds a reference to the ring until it's dropped
+    if (*ring).int_flags & INT_FLAG_REG_RING != 0 {
+        io_uring_unregister_ring_fd(ring);
+    }
+
+    let ring = &mut *ring;
+    let sqes_size = ring.sq.ring_entries as usize * size_of::<io_uring_sqe>();
+
+    unmap(ring.sq.sqes as *mut c_void, sqes_size);
+    if ring.cq.ring_ptr != ring.sq.ring_ptr {
+        unmap(ring.cq.ring_ptr, ring.cq.ring_sz);
+    }
+    unmap(ring.sq.ring_ptr, ring.sq.ring_sz);
+    close_fd(ring.ring_fd);
+}
+
+/// Hands out the next free submission queue entry, or null if it's full
+///
+/// The entry isn't visible to the kernel until the next submit.
+///
+pub unsafe fn io_uring_get_sqe(ring: *mut io_uring) -> *mut io_uring_sqe {
+    let sq = &mut (*ring).sq;
+    let head = load_acquire(sq.khead);
+
+    if sq.sqe_tail.wrapping_sub(head) >= sq.ring_entries {
+        return ptr::null_mut();
+    }
+
+    let sqe = sq.sqes.add((sq.sqe_tail & sq.ring_mask) as usize);
+    sq.sqe_tail = sq.sqe_tail.wrapping_add(1);
+    sqe
+}
+
+/// Makes the entries handed out since the last call visible to the kernel
+///
+/// Returns how many entries the kernel has yet to consume.
+///
+unsafe fn flush_sq(ring: &mut io_uring) -> c_uint {
+    let sq = &mut ring.sq;
+    let tail = sq.sqe_tail;
+
+    if sq.sqe_head != tail {
+        sq.sqe_head = tail;
+        store_release(sq.ktail, tail);
+    }
+
+    tail.wrapping_sub(load_acquire(sq.khead))
+}
+
+/// Checks if the kernel has completions to flush or work to run
+unsafe fn cq_needs_flush(ring: &io_uring) -> bool {
+    load_acquire(ring.sq.kflags) & (IORING_SQ_CQ_OVERFLOW | IORING_SQ_TASKRUN) != 0
+}
+
+/// Checks if submitting needs a syscall
+///
+/// Without SQPOLL it always does. With it, only when the polling thread has
+/// gone to sleep and needs waking up.
+///
+unsafe fn sq_needs_enter(ring: &io_uring, submitted: c_uint, flags: &mut c_uint) -> bool {
+    if submitted == 0 {
+        return false;
+    }
+    if ring.flags & IORING_SETUP_SQPOLL == 0 {
+        return true;
+    }
+
+    // Make sure the tail we just stored is visible before checking whether the
+    // thread is asleep, otherwise it could fall asleep without seeing it.
+    fence(Ordering::SeqCst);
+
+    if load_acquire(ring.sq.kflags) & IORING_SQ_NEED_WAKEUP != 0 {
+        *flags |= IORING_ENTER_SQ_WAKEUP;
+        return true;
+    }
+    false
+}
+
+pub unsafe fn io_uring_submit_and_wait(ring: *mut io_uring, wait_nr: c_uint) -> c_int {
+    let ring = &mut *ring;
+    let submitted = flush_sq(ring);
+    let mut flags = 0;
+
+    let needs_enter = sq_needs_enter(ring, submitted, &mut flags);
+    if wait_nr > 0 || cq_needs_flush(ring) || ring.flags & IORING_SETUP_IOPOLL != 0 {
+        flags |= IORING_ENTER_GETEVENTS;
+    }
+
+    if needs_enter || flags & IORING_ENTER_GETEVENTS != 0 {
+        enter(ring, submitted, wait_nr, flags, ptr::null(), 0)
+    } else {
+        submitted as c_int
+    }
+}
+
+pub unsafe fn io_uring_submit(ring: *mut io_uring) -> c_int {
+    io_uring_submit_and_wait(ring, 0)
+}
+
+/// Number of completions ready to be read
+pub unsafe fn io_uring_cq_ready(ring: *const io_uring) -> c_uint {
+    let cq = &(*ring).cq;
+    load_acquire(cq.ktail).wrapping_sub(*cq.khead)
+}
+
+pub unsafe fn io_uring_cq_has_overflow(ring: *const io_uring) -> bool {
+    load_acquire((*ring).sq.kflags) & IORING_SQ_CQ_OVERFLOW != 0
+}
+
+/// Marks completions as read, handing their slots back to the kernel
+pub unsafe fn io_uring_cq_advance(ring: *mut io_uring, nr: c_uint) {
+    if nr > 0 {
+        let cq = &(*ring).cq;
+        store_release(cq.khead, (*cq.khead).wrapping_add(nr));
+    }
+}
+
+pub unsafe fn io_uring_cqe_seen(ring: *mut io_uring, cqe: *mut io_uring_cqe) {
+    if !cqe.is_null() {
+        io_uring_cq_advance(ring, 1);
+    }
+}
+
+/// Points cqe_ptr at the next completion without marking it as read
+unsafe fn next_cqe(ring: &io_uring, cqe_ptr: *mut *mut io_uring_cqe) -> bool {
+    let cq = &ring.cq;
+    let head = *cq.khead;
+
+    if load_acquire(cq.ktail) == head {
+        *cqe_ptr = ptr::null_mut();
+        return false;
+    }
+
+    *cqe_ptr = cq.cqes.add((head & cq.ring_mask) as usize);
+    true
+}
+
+/// Asks the kernel to flush overflowed completions and run pending work
+pub unsafe fn io_uring_get_events(ring: *mut io_uring) -> c_int {
+    enter(&*ring, 0, 0, IORING_ENTER_GETEVENTS, ptr::null(), 0)
+}
+
+pub unsafe fn io_uring_peek_cqe(ring: *mut io_uring, cqe_ptr: *mut *mut io_uring_cqe) -> c_int {
+    if next_cqe(&*ring, cqe_ptr) {
+        return 0;
+    }
+
+    if cq_needs_flush(&*ring) {
+        io_uring_get_events(ring);
+        if next_cqe(&*ring, cqe_ptr) {
+            return 0;
+        }
+    }
+
+    -(EAGAIN as c_int)
+}
+
+pub unsafe fn io_uring_wait_cqe(ring: *mut io_uring, cqe_ptr: *mut *mut io_uring_cqe) -> c_int {
+    loop {
+        if next_cqe(&*ring, cqe_ptr) {
+            return 0;
+        }
+
+        let ret = enter(&*ring, 0, 1, IORING_ENTER_GETEVENTS, ptr::null(), 0);
+        if ret < 0 && ret != -(EINTR as c_int) {
+            return ret;
+        }
+    }
+}
+
+/// Waits for a completion with a timeout
+///
+/// Passes the timeout straight to io_uring_enter, which needs
+/// IORING_FEAT_EXT_ARG (5.11+). liburing falls back to submitting a timeout
+/// entry on older kernels; here they get -EINVAL instead. Returns -ETIME if
+/// nothing completed in time.
+///
+pub unsafe fn io_uring_wait_cqe_timeout(
+    ring: *mut io_uring,
+    cqe_ptr: *mut *mut io_uring_cqe,
+    ts: *mut __kernel_timespec,
+) -> c_int {
+    if ts.is_null() {
+        return io_uring_wait_cqe(ring, cqe_ptr);
+    }
+    if next_cqe(&*ring, cqe_ptr) {
+        return 0;
+    }
+    if (*ring).features & IORING_FEAT_EXT_ARG == 0 {
+        return -(EINVAL as c_int);
+    }
+
+    let arg = io_uring_getevents_arg {
+        ts: ts as u64,
+        ..Default::default()
+    };
+    let ret = enter(
+        &*ring,
+        0,
+        1,
+        IORING_ENTER_GETEVENTS | IORING_ENTER_EXT_ARG,
+        &arg as *const _ as *const c_void,
+        size_of::<io_uring_getevents_arg>(),
+    );
+
+    if next_cqe(&*ring, cqe_ptr) {
+        return 0;
+    }
+    if ret < 0 {
+        ret
+    } else {
+        -(ETIME as c_int)
+    }
+}
+
+/// Fills cqes with up to count completions without marking them as read
+pub unsafe fn io_uring_peek_batch_cqe(
+    ring: *mut io_uring,
+    cqes: *mut *mut io_uring_cqe,
+    count: c_uint,
+) -> c_uint {
+    let mut ready = io_uring_cq_ready(ring);
+
+    if ready == 0 && cq_needs_flush(&*ring) {
+        io_uring_get_events(ring);
+        ready = io_uring_cq_ready(ring);
+    }
+
+    let cq = &(*ring).cq;
+    let head = *cq.khead;
+    let count = count.min(ready);
+
+    for i in 0..count {
+        let index = head.wrapping_add(i) & cq.ring_mask;
+        *cqes.add(i as usize) = cq.cqes.add(index as usize);
+    }
+    count
+}
+
+/// Number of entries handed out that the kernel hasn't consumed yet
+pub unsafe fn io_uring_sq_ready(ring: *const io_uring) -> c_uint {
+    let sq = &(*ring).sq;
+    sq.sqe_tail.wrapping_sub(load_acquire(sq.khead))
+}
+
+pub unsafe fn io_uring_sq_space_left(ring: *const io_uring) -> c_uint {
+    (*ring).sq.ring_entries - io_uring_sq_ready(ring)
+}
+
+/// Waits for the polling thread to free up space in the submission queue
+///
+/// Only needed with SQPOLL; otherwise every submit empties the queue.
+///
+pub unsafe fn io_uring_sqring_wait(ring: *mut io_uring) -> c_int {
+    if (*ring).flags & IORING_SETUP_SQPOLL == 0 || io_uring_sq_space_left(ring) > 0 {
+        return 0;
+    }
+
+    enter(&*ring, 0, 0, IORING_ENTER_SQ_WAIT, ptr::null(), 0)
+}
@@ -0,0 +1,307 @@
+/// Register
+///
+/// Everything that goes through the io_uring_register syscall: fixed buffers,
+/// fixed files, eventfds, provided buffer rings, NAPI busy polling and probing
+/// for supported operations.
+///
+use super::sys::*;
+use super::types::*;
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::mem::size_of;
+use std::os::raw::{c_int, c_uint, c_ushort, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// How many operations a probe has room for
+const PROBE_OPS: usize = 256;
+
+pub unsafe fn io_uring_register_buffers(
+    ring: *mut io_uring,
+    iovecs: *const iovec,
+    nr_iovecs: c_uint,
+) -> c_int {
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_BUFFERS,
+        iovecs as *const _,
+        nr_iovecs,
+    )
+}
+
+pub unsafe fn io_uring_unregister_buffers(ring: *mut io_uring) -> c_int {
+    io_uring_register((*ring).ring_fd, IORING_UNREGISTER_BUFFERS, ptr::null(), 0)
+}
+
+pub unsafe fn io_uring_register_files(
+    ring: *mut io_uring,
+    files: *const c_int,
+    nr_files: c_uint,
+) -> c_int {
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_FILES,
+        files as *const _,
+        nr_files,
+    )
+}
+
+/// Registers a file table of nr empty slots
+///
+/// Slots get filled in later by operations that create direct descriptors,
+/// such as io_uring_prep_accept_direct.
+///
+pub unsafe fn io_uring_register_files_sparse(ring: *mut io_uring, nr: c_uint) -> c_int {
+    let reg = io_uring_rsrc_register {
+        nr,
+        flags: IORING_RSRC_REGISTER_SPARSE,
+        ..Default::default()
+    };
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_FILES2,
+        &reg as *const _ as *const c_void,
+        size_of::<io_uring_rsrc_register>() as c_uint,
+    )
+}
+
+pub unsafe fn io_uring_unregister_files(ring: *mut io_uring) -> c_int {
+    io_uring_register((*ring).ring_fd, IORING_UNREGISTER_FILES, ptr::null(), 0)
+}
+
+/// Turns on NAPI busy polling for the ring's sockets
+pub unsafe fn io_uring_register_napi(ring: *mut io_uring, napi: *mut io_uring_napi) -> c_int {
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_NAPI,
+        napi as *const c_void,
+        1,
+    )
+}
+
+/// Turns NAPI busy polling off again, writing the old settings to napi
+pub unsafe fn io_uring_unregister_napi(ring: *mut io_uring, napi: *mut io_uring_napi) -> c_int {
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_UNREGISTER_NAPI,
+        napi as *const c_void,
+        1,
+    )
+}
+
+pub unsafe fn io_uring_register_eventfd(ring: *mut io_uring, fd: c_int) -> c_int {
+    io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_EVENTFD,
+        &fd as *const _ as *const _,
+        1,
+    )
+}
+
+pub unsafe fn io_uring_unregister_eventfd(ring: *mut io_uring) -> c_int {
+    io_uring_register((*ring).ring_fd, IORING_UNREGISTER_EVENTFD, ptr::null(), 0)
+}
+
+/// Registers the ring fd with the kernel
+///
+/// io_uring_enter then gets the index it was registered at instead of the fd,
+/// which saves looking up the file on every call. The registration belongs to
+/// the calling thread.
+///
+pub unsafe fn io_uring_register_ring_fd(ring: *mut io_uring) -> c_int {
+    let ring = &mut *ring;
+    if ring.int_flags & INT_FLAG_REG_RING != 0 {
+        return -(EEXIST as c_int);
+    }
+
+    // An offset of -1 lets the kernel pick a free slot
+    let mut update = io_uring_rsrc_update {
+        offset: u32::MAX,
+        data: ring.ring_fd as u64,
+        ..Default::default()
+    };
+    let ret = io_uring_register(
+        ring.ring_fd,
+        IORING_REGISTER_RING_FDS,
+        &mut update as *mut _ as *const c_void,
+        1,
+    );
+
+    if ret == 1 {
+        ring.enter_ring_fd = update.offset as c_int;
+        ring.int_flags |= INT_FLAG_REG_RING;
+    }
+    ret
+}
+
+pub unsafe fn io_uring_unregister_ring_fd(ring: *mut io_uring) -> c_int {
+    let ring = &mut *ring;
+    if ring.int_flags & INT_FLAG_REG_RING == 0 {
+        return -(EINVAL as c_int);
+    }
+
+    let update = io_uring_rsrc_update {
+        offset: ring.enter_ring_fd as u32,
+        ..Default::default()
+    };
+    let ret = io_uring_register(
+        ring.ring_fd,
+        IORING_UNREGISTER_RING_FDS,
+        &update as *const _ as *const c_void,
+        1,
+    );
+
+    if ret == 1 {
+        ring.enter_ring_fd = ring.ring_fd;
+        ring.int_flags &= !INT_FLAG_REG_RING;
+    }
+    ret
+}
+
+fn probe_layout() -> Layout {
+    let size = size_of::<io_uring_probe>() + PROBE_OPS * size_of::<io_uring_probe_op>();
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// Asks the kernel which operations it supports
+///
+/// Returns null if it can't tell us (before 5.6). Free the probe with
+/// io_uring_free_probe.
+///
+pub unsafe fn io_uring_get_probe_ring(ring: *mut io_uring) -> *mut io_uring_probe {
+    let probe = alloc_zeroed(probe_layout()) as *mut io_uring_probe;
+    if probe.is_null() {
+        return probe;
+    }
+
+    let ret = io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_PROBE,
+        probe as *const _,
+        PROBE_OPS as c_uint,
+    );
+    if ret < 0 {
+        io_uring_free_probe(probe);
+        return ptr::null_mut();
+    }
+    probe
+}
+
+pub unsafe fn io_uring_free_probe(probe: *mut io_uring_probe) {
+    if !probe.is_null() {
+        dealloc(probe as *mut u8, probe_layout());
+    }
+}
+
+pub unsafe fn io_uring_opcode_supported(p: *const io_uring_probe, op: c_int) -> c_int {
+    let probe = &*p;
+    if op < 0 || op > probe.last_op as c_int {
+        return 0;
+    }
+
+    let entry = &*probe.ops.as_ptr().add(op as usize);
+    (entry.flags as u32 & IO_URING_OP_SUPPORTED != 0) as c_int
+}
+
+/// Sets up a provided buffer ring
+///
+/// The ring is page-aligned memory of our own that the kernel reads buffers
+/// from, registered under the group id bgid. On failure ret is set to the
+/// negative errno and null is returned.
+///
+pub unsafe fn io_uring_setup_buf_ring(
+    ring: *mut io_uring,
+    nentries: c_uint,
+    bgid: c_int,
+    flags: c_uint,
+    ret: *mut c_int,
+) -> *mut io_uring_buf_ring {
+    let size = nentries as usize * size_of::<io_uring_buf>();
+    let br = match map_anonymous(size) {
+        Ok(ptr) => ptr as *mut io_uring_buf_ring,
+        Err(err) => {
+            *ret = err;
+            return ptr::null_mut();
+        }
+    };
+
+    let reg = io_uring_buf_reg {
+        ring_addr: br as u64,
+        ring_entries: nentries,
+        bgid: bgid as u16,
+        flags: flags as u16,
+        ..Default::default()
+    };
+    let err = io_uring_register(
+        (*ring).ring_fd,
+        IORING_REGISTER_PBUF_RING,
+        &reg as *const _ as *const c_void,
+        1,
+    );
+    if err < 0 {
+        unmap(br as *mut c_void, size);
+        *ret = err;
+        return ptr::null_mut();
+    }
+
+    // The memory comes zeroed, so the tail already starts out at 0.
+    *ret = 0;
+    br
+}
+
+pub unsafe fn io_uring_free_buf_ring(
+    ring: *mut io_uring,
+    br: *mut io_uring_buf_ring,
+    nentries: c_uint,
+    bgid: c_int,
+) -> c_int {
+    let reg = io_uring_buf_reg {
+        bgid: bgid as u16,
+        ..Default::default()
+    };
+    let ret = io_uring_register(
+        (*ring).ring_fd,
+        IORING_UNREGISTER_PBUF_RING,
+        &reg as *const _ as *const c_void,
+        1,
+    );
+    if ret < 0 {
+        return ret;
+    }
+
+    unmap(
+        br as *mut c_void,
+        nentries as usize * size_of::<io_uring_buf>(),
+    );
+    0
+}
+
+pub unsafe fn io_uring_buf_ring_mask(ring_entries: u32) -> c_int {
+    (ring_entries - 1) as c_int
+}
+
+/// Adds a buffer buf_offset slots past the ring's tail
+///
+/// It isn't visible to the kernel until io_uring_buf_ring_advance moves the
+/// tail past it.
+///
+pub unsafe fn io_uring_buf_ring_add(
+    br: *mut io_uring_buf_ring,
+    addr: *mut c_void,
+    len: c_uint,
+    bid: c_ushort,
+    mask: c_int,
+    buf_offset: c_int,
+) {
+    let index = ((*br).tail as c_int + buf_offset) & mask;
+    let buf = &mut *(br as *mut io_uring_buf).add(index as usize);
+
+    buf.addr = addr as u64;
+    buf.len = len;
+    buf.bid = bid;
+}
+
+pub unsafe fn io_uring_buf_ring_advance(br: *mut io_uring_buf_ring, count: c_int) {
+    let tail = &*(ptr::addr_of_mut!((*br).tail) as *const AtomicU16);
+    let new_tail = tail.load(Ordering::Relaxed).wrapping_add(count as u16);
+    tail.store(new_tail, Ordering::Release);
+}
@@ -0,0 +1,139 @@
+/// Syscalls
+///
+/// The three io_uring syscalls along with mmap, called through libc's
+/// syscall(2) since std doesn't expose them. std already links libc, so this
+/// doesn't add a dependency. The syscall numbers are the same on every
+/// architecture, and every argument is passed as a long since that's how
+/// syscall(2) reads them back out.
+///
+/// Everything here returns a negative errno on failure, the same as liburing.
+///
+use super::types::*;
+use std::io;
+use std::os::raw::{c_int, c_long, c_uint, c_void};
+use std::ptr;
+
+const SYS_IO_URING_SETUP: c_long = 425;
+const SYS_IO_URING_ENTER: c_long = 426;
+const SYS_IO_URING_REGISTER: c_long = 427;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const MAP_SHARED: c_int = 0x01;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_POPULATE: c_int = 0x8000;
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+/// The last error as a negative errno
+fn errno() -> c_int {
+    -io::Error::last_os_error()
+        .raw_os_error()
+        .unwrap_or(EINVAL as c_int)
+}
+
+fn check(ret: c_long) -> c_int {
+    if ret < 0 {
+        errno()
+    } else {
+        ret as c_int
+    }
+}
+
+pub unsafe fn io_uring_setup(entries: c_uint, p: *mut io_uring_params) -> c_int {
+    check(syscall(SYS_IO_URING_SETUP, entries as c_long, p as c_long))
+}
+
+pub unsafe fn io_uring_enter(
+    fd: c_int,
+    to_submit: c_uint,
+    min_complete: c_uint,
+    flags: c_uint,
+    arg: *const c_void,
+    argsz: usize,
+) -> c_int {
+    check(syscall(
+        SYS_IO_URING_ENTER,
+        fd as c_long,
+        to_submit as c_long,
+        min_complete as c_long,
+        flags as c_long,
+        arg as c_long,
+        argsz as c_long,
+    ))
+}
+
+pub unsafe fn io_uring_register(
+    fd: c_int,
+    opcode: c_uint,
+    arg: *const c_void,
+    nr_args: c_uint,
+) -> c_int {
+    check(syscall(
+        SYS_IO_URING_REGISTER,
+        fd as c_long,
+        opcode as c_long,
+        arg as c_long,
+        nr_args as c_long,
+    ))
+}
+
+/// Maps one of the ring's regions, at one of the IORING_OFF_* offsets
+pub unsafe fn map_ring(fd: c_int, len: usize, offset: u64) -> Result<*mut c_void, c_int> {
+    let ptr = mmap(
+        ptr::null_mut(),
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_SHARED | MAP_POPULATE,
+        fd,
+        offset as i64,
+    );
+
+    if ptr as isize == -1 {
+        Err(errno())
+    } else {
+        Ok(ptr)
+    }
+}
+
+/// Maps plain zeroed memory, page aligned, for structures we share with the
+/// kernel like buffer rings
+pub unsafe fn map_anonymous(len: usize) -> Result<*mut c_void, c_int> {
+    let ptr = mmap(
+        ptr::null_mut(),
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+
+    if ptr as isize == -1 {
+        Err(errno())
+    } else {
+        Ok(ptr)
+    }
+}
+
+pub unsafe fn unmap(ptr: *mut c_void, len: usize) {
+    if !ptr.is_null() {
+        munmap(ptr, len);
+    }
+}
+
+pub unsafe fn close_fd(fd: c_int) {
+    close(fd);
+}
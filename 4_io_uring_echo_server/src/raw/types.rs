@@ -0,0 +1,469 @@
+/// Types
+///
+/// The kernel's io_uring structures and constants from linux/io_uring.h, along
+/// with the handful of socket, file and errno definitions liburing.h pulls in
+/// from the C library. Names follow bindgen's output, which is why there are
+/// some odd ones like io_uring_op_IORING_OP_SEND or __bindgen_anon_4.
+///
+/// io_uring, io_uring_sq and io_uring_cq are liburing's own bookkeeping rather
+/// than kernel structures, so their layout only has to make sense to us.
+///
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+pub type __u8 = u8;
+pub type __u16 = u16;
+pub type __u32 = u32;
+pub type __u64 = u64;
+pub type __s32 = i32;
+pub type __s64 = i64;
+pub type socklen_t = c_uint;
+pub type sa_family_t = u16;
+pub type mode_t = c_uint;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct __kernel_timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sockaddr {
+    pub sa_family: sa_family_t,
+    pub sa_data: [c_char; 14],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct sockaddr_storage {
+    pub ss_family: sa_family_t,
+    pub __ss_padding: [c_char; 118],
+    pub __ss_align: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct iovec {
+    pub iov_base: *mut c_void,
+    pub iov_len: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct msghdr {
+    pub msg_name: *mut c_void,
+    pub msg_namelen: socklen_t,
+    pub msg_iov: *mut iovec,
+    pub msg_iovlen: usize,
+    pub msg_control: *mut c_void,
+    pub msg_controllen: usize,
+    pub msg_flags: c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct statx_timestamp {
+    pub tv_sec: __s64,
+    pub tv_nsec: __u32,
+    pub __reserved: __s32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct statx {
+    pub stx_mask: __u32,
+    pub stx_blksize: __u32,
+    pub stx_attributes: __u64,
+    pub stx_nlink: __u32,
+    pub stx_uid: __u32,
+    pub stx_gid: __u32,
+    pub stx_mode: __u16,
+    pub __spare0: [__u16; 1],
+    pub stx_ino: __u64,
+    pub stx_size: __u64,
+    pub stx_blocks: __u64,
+    pub stx_attributes_mask: __u64,
+    pub stx_atime: statx_timestamp,
+    pub stx_btime: statx_timestamp,
+    pub stx_ctime: statx_timestamp,
+    pub stx_mtime: statx_timestamp,
+    pub stx_rdev_major: __u32,
+    pub stx_rdev_minor: __u32,
+    pub stx_dev_major: __u32,
+    pub stx_dev_minor: __u32,
+    pub __spare2: [__u64; 14],
+}
+
+/// Submission queue entry
+///
+/// The kernel's version is mostly unions, since what a field means depends on
+/// the opcode. Only the union the wrapper reaches into directly (buf_index /
+/// buf_group) is kept as one; the rest are named after their most common use:
+///
+///     off:        offset, addr2
+///     addr:       addr, splice_off_in
+///     rw_flags:   the per-opcode flags (msg_flags, poll32_events, ...)
+///     file_index: file_index, splice_fd_in
+///
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct io_uring_sqe {
+    pub opcode: __u8,
+    pub flags: __u8,
+    pub ioprio: __u16,
+    pub fd: __s32,
+    pub off: __u64,
+    pub addr: __u64,
+    pub len: __u32,
+    pub rw_flags: __u32,
+    pub user_data: __u64,
+    pub __bindgen_anon_4: io_uring_sqe__bindgen_ty_4,
+    pub personality: __u16,
+    pub file_index: __u32,
+    pub addr3: __u64,
+    pub __pad2: [__u64; 1],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union io_uring_sqe__bindgen_ty_4 {
+    pub buf_index: __u16,
+    pub buf_group: __u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_cqe {
+    pub user_data: __u64,
+    pub res: __s32,
+    pub flags: __u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_sqring_offsets {
+    pub head: __u32,
+    pub tail: __u32,
+    pub ring_mask: __u32,
+    pub ring_entries: __u32,
+    pub flags: __u32,
+    pub dropped: __u32,
+    pub array: __u32,
+    pub resv1: __u32,
+    pub user_addr: __u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_cqring_offsets {
+    pub head: __u32,
+    pub tail: __u32,
+    pub ring_mask: __u32,
+    pub ring_entries: __u32,
+    pub overflow: __u32,
+    pub cqes: __u32,
+    pub flags: __u32,
+    pub resv1: __u32,
+    pub user_addr: __u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_params {
+    pub sq_entries: __u32,
+    pub cq_entries: __u32,
+    pub flags: __u32,
+    pub sq_thread_cpu: __u32,
+    pub sq_thread_idle: __u32,
+    pub features: __u32,
+    pub wq_fd: __u32,
+    pub resv: [__u32; 3],
+    pub sq_off: io_sqring_offsets,
+    pub cq_off: io_cqring_offsets,
+}
+
+/// Argument for waiting with a timeout (IORING_ENTER_EXT_ARG)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_getevents_arg {
+    pub sigmask: __u64,
+    pub sigmask_sz: __u32,
+    pub pad: __u32,
+    pub ts: __u64,
+}
+
+/// Submission queue
+///
+/// The k* pointers point into the ring shared with the kernel. The kernel
+/// consumes entries from khead while we add them at ktail. sqe_head and
+/// sqe_tail track the entries we've handed out but not yet made visible to
+/// the kernel.
+///
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct io_uring_sq {
+    pub khead: *mut c_uint,
+    pub ktail: *mut c_uint,
+    pub kring_mask: *mut c_uint,
+    pub kring_entries: *mut c_uint,
+    pub kflags: *mut c_uint,
+    pub kdropped: *mut c_uint,
+    pub array: *mut c_uint,
+    pub sqes: *mut io_uring_sqe,
+    pub sqe_head: c_uint,
+    pub sqe_tail: c_uint,
+    pub ring_sz: usize,
+    pub ring_ptr: *mut c_void,
+    pub ring_mask: c_uint,
+    pub ring_entries: c_uint,
+}
+
+/// Completion queue
+///
+/// The kernel adds completions at ktail and we consume them from khead.
+///
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct io_uring_cq {
+    pub khead: *mut c_uint,
+    pub ktail: *mut c_uint,
+    pub kring_mask: *mut c_uint,
+    pub kring_entries: *mut c_uint,
+    pub kflags: *mut c_uint,
+    pub koverflow: *mut c_uint,
+    pub cqes: *mut io_uring_cqe,
+    pub ring_sz: usize,
+    pub ring_ptr: *mut c_void,
+    pub ring_mask: c_uint,
+    pub ring_entries: c_uint,
+}
+
+/// The ring
+///
+/// enter_ring_fd is what io_uring_enter gets called with. It starts out as
+/// ring_fd, and once the ring fd is registered it's the registered index
+/// instead, with INT_FLAG_REG_RING set in int_flags.
+///
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct io_uring {
+    pub sq: io_uring_sq,
+    pub cq: io_uring_cq,
+    pub flags: c_uint,
+    pub ring_fd: c_int,
+    pub features: c_uint,
+    pub enter_ring_fd: c_int,
+    pub int_flags: __u8,
+    pub pad: [__u8; 3],
+    pub pad2: c_uint,
+}
+
+/// Flags in io_uring::int_flags
+pub const INT_FLAG_REG_RING: __u8 = 1 << 0;
+
+/// A provided buffer, one slot of a buffer ring
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_buf {
+    pub addr: __u64,
+    pub len: __u32,
+    pub bid: __u16,
+    pub resv: __u16,
+}
+
+/// Buffer ring
+///
+/// An array of io_uring_buf, where the reserved field of the first one holds
+/// the ring's tail.
+///
+#[repr(C)]
+#[derive(Debug)]
+pub struct io_uring_buf_ring {
+    pub resv1: __u64,
+    pub resv2: __u32,
+    pub resv3: __u16,
+    pub tail: __u16,
+}
+
+/// Argument for registering a buffer ring
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_buf_reg {
+    pub ring_addr: __u64,
+    pub ring_entries: __u32,
+    pub bgid: __u16,
+    pub flags: __u16,
+    pub resv: [__u64; 3],
+}
+
+/// Argument for updating a registered resource, such as the ring fd
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_rsrc_update {
+    pub offset: __u32,
+    pub resv: __u32,
+    pub data: __u64,
+}
+
+/// NAPI busy poll settings, with the timeout in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_napi {
+    pub busy_poll_to: __u32,
+    pub prefer_busy_poll: __u8,
+    pub pad: [__u8; 3],
+    pub resv: __u64,
+}
+
+/// Argument for registering resources such as files, used for a sparse table
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_rsrc_register {
+    pub nr: __u32,
+    pub flags: __u32,
+    pub resv2: __u64,
+    pub data: __u64,
+    pub tags: __u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_probe_op {
+    pub op: __u8,
+    pub resv: __u8,
+    pub flags: __u16,
+    pub resv2: __u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct io_uring_probe {
+    pub last_op: __u8,
+    pub ops_len: __u8,
+    pub resv: __u16,
+    pub resv2: [__u32; 3],
+    pub ops: [io_uring_probe_op; 0],
+}
+
+// errno
+pub const EAGAIN: u32 = 11;
+pub const EINTR: u32 = 4;
+pub const EEXIST: u32 = 17;
+pub const EINVAL: u32 = 22;
+pub const EPIPE: u32 = 32;
+pub const ETIME: u32 = 62;
+pub const ENOBUFS: u32 = 105;
+pub const ECONNRESET: u32 = 104;
+pub const ENOTCONN: u32 = 107;
+pub const ECANCELED: u32 = 125;
+
+// Sockets and files
+pub const AF_INET: u32 = 2;
+pub const AF_INET6: u32 = 10;
+pub const AT_FDCWD: i32 = -100;
+pub const SHUT_RD: u32 = 0;
+pub const SHUT_WR: u32 = 1;
+pub const SHUT_RDWR: u32 = 2;
+pub const STATX_MTIME: u32 = 0x40;
+pub const STATX_SIZE: u32 = 0x200;
+
+// futex2
+pub const FUTEX2_SIZE_U32: u32 = 0x02;
+pub const FUTEX2_PRIVATE: u32 = 128;
+pub const FUTEX_BITSET_MATCH_ANY: u32 = 0xffffffff;
+
+// io_uring_setup flags
+pub const IORING_SETUP_IOPOLL: u32 = 1 << 0;
+pub const IORING_SETUP_SQPOLL: u32 = 1 << 1;
+pub const IORING_SETUP_SQ_AFF: u32 = 1 << 2;
+pub const IORING_SETUP_CQSIZE: u32 = 1 << 3;
+pub const IORING_SETUP_CLAMP: u32 = 1 << 4;
+pub const IORING_SETUP_COOP_TASKRUN: u32 = 1 << 8;
+pub const IORING_SETUP_SINGLE_ISSUER: u32 = 1 << 12;
+pub const IORING_SETUP_DEFER_TASKRUN: u32 = 1 << 13;
+
+// Features reported back by io_uring_setup
+pub const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+pub const IORING_FEAT_NODROP: u32 = 1 << 1;
+pub const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+
+// Offsets to mmap the rings at
+pub const IORING_OFF_SQ_RING: u64 = 0;
+pub const IORING_OFF_CQ_RING: u64 = 0x8000000;
+pub const IORING_OFF_SQES: u64 = 0x10000000;
+
+// Submission queue ring flags
+pub const IORING_SQ_NEED_WAKEUP: u32 = 1 << 0;
+pub const IORING_SQ_CQ_OVERFLOW: u32 = 1 << 1;
+pub const IORING_SQ_TASKRUN: u32 = 1 << 2;
+
+// io_uring_enter flags
+pub const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+pub const IORING_ENTER_SQ_WAKEUP: u32 = 1 << 1;
+pub const IORING_ENTER_SQ_WAIT: u32 = 1 << 2;
+pub const IORING_ENTER_EXT_ARG: u32 = 1 << 3;
+pub const IORING_ENTER_REGISTERED_RING: u32 = 1 << 4;
+
+// Completion flags
+pub const IORING_CQE_F_BUFFER: u32 = 1 << 0;
+pub const IORING_CQE_F_MORE: u32 = 1 << 1;
+pub const IORING_CQE_F_NOTIF: u32 = 1 << 3;
+pub const IORING_CQE_BUFFER_SHIFT: u32 = 16;
+
+// Per-opcode flags
+pub const IORING_FSYNC_DATASYNC: u32 = 1 << 0;
+pub const IORING_TIMEOUT_ABS: u32 = 1 << 0;
+pub const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+pub const IORING_ASYNC_CANCEL_ALL: u32 = 1 << 0;
+pub const IORING_ASYNC_CANCEL_FD: u32 = 1 << 1;
+pub const IORING_RECV_MULTISHOT: u32 = 1 << 1;
+pub const IORING_FILE_INDEX_ALLOC: i32 = -1;
+
+// io_uring_register opcodes
+pub const IORING_REGISTER_BUFFERS: u32 = 0;
+pub const IORING_UNREGISTER_BUFFERS: u32 = 1;
+pub const IORING_REGISTER_FILES: u32 = 2;
+pub const IORING_UNREGISTER_FILES: u32 = 3;
+pub const IORING_REGISTER_EVENTFD: u32 = 4;
+pub const IORING_UNREGISTER_EVENTFD: u32 = 5;
+pub const IORING_REGISTER_PROBE: u32 = 8;
+pub const IORING_REGISTER_FILES2: u32 = 13;
+pub const IORING_REGISTER_RING_FDS: u32 = 20;
+pub const IORING_UNREGISTER_RING_FDS: u32 = 21;
+pub const IORING_REGISTER_PBUF_RING: u32 = 22;
+pub const IORING_UNREGISTER_PBUF_RING: u32 = 23;
+pub const IORING_REGISTER_NAPI: u32 = 27;
+pub const IORING_UNREGISTER_NAPI: u32 = 28;
+
+pub const IORING_RSRC_REGISTER_SPARSE: u32 = 1 << 0;
+pub const IO_URING_OP_SUPPORTED: u32 = 1 << 0;
+
+pub type io_uring_op = c_uint;
+pub const io_uring_op_IORING_OP_NOP: io_uring_op = 0;
+pub const io_uring_op_IORING_OP_FSYNC: io_uring_op = 3;
+pub const io_uring_op_IORING_OP_READ_FIXED: io_uring_op = 4;
+pub const io_uring_op_IORING_OP_WRITE_FIXED: io_uring_op = 5;
+pub const io_uring_op_IORING_OP_POLL_ADD: io_uring_op = 6;
+pub const io_uring_op_IORING_OP_POLL_REMOVE: io_uring_op = 7;
+pub const io_uring_op_IORING_OP_SENDMSG: io_uring_op = 9;
+pub const io_uring_op_IORING_OP_RECVMSG: io_uring_op = 10;
+pub const io_uring_op_IORING_OP_TIMEOUT: io_uring_op = 11;
+pub const io_uring_op_IORING_OP_ACCEPT: io_uring_op = 13;
+pub const io_uring_op_IORING_OP_ASYNC_CANCEL: io_uring_op = 14;
+pub const io_uring_op_IORING_OP_LINK_TIMEOUT: io_uring_op = 15;
+pub const io_uring_op_IORING_OP_OPENAT: io_uring_op = 18;
+pub const io_uring_op_IORING_OP_CLOSE: io_uring_op = 19;
+pub const io_uring_op_IORING_OP_STATX: io_uring_op = 21;
+pub const io_uring_op_IORING_OP_READ: io_uring_op = 22;
+pub const io_uring_op_IORING_OP_WRITE: io_uring_op = 23;
+pub const io_uring_op_IORING_OP_SEND: io_uring_op = 26;
+pub const io_uring_op_IORING_OP_RECV: io_uring_op = 27;
+pub const io_uring_op_IORING_OP_SPLICE: io_uring_op = 30;
+pub const io_uring_op_IORING_OP_TEE: io_uring_op = 33;
+pub const io_uring_op_IORING_OP_SHUTDOWN: io_uring_op = 34;
+pub const io_uring_op_IORING_OP_MSG_RING: io_uring_op = 40;
+pub const io_uring_op_IORING_OP_SOCKET: io_uring_op = 45;
+pub const io_uring_op_IORING_OP_SEND_ZC: io_uring_op = 47;
+pub const io_uring_op_IORING_OP_FUTEX_WAIT: io_uring_op = 51;
+pub const io_uring_op_IORING_OP_FUTEX_WAKE: io_uring_op = 52;
@@ -0,0 +1,341 @@
+/// Reactor
+///
+/// Puts futures on top of the ring. Each Op is a future for a single
+/// operation: creating one prepares its entry, and polling it checks if the
+/// completion has arrived, leaving its waker behind if it hasn't. The reactor
+/// reads completions off the ring and wakes whichever Op each one belongs to,
+/// using the user_data to find it:
+///
+///     Op::poll -> pending, waker stored under user_data
+///     reactor  -> submit, wait, completion for user_data, wake
+///     Op::poll -> ready with the result
+///
+/// The user_data is a Slab key, so a completion for an op that's gone can't be
+/// mistaken for one that reused its slot. Buffers and addresses live in the Op
+/// while it's pending. If an Op is dropped before it completes they're handed
+/// to the reactor, which keeps them until the completion arrives, and the
+/// operation is cancelled.
+///
+/// block_on is a minimal executor for driving a single future with it.
+///
+use crate::addr::AcceptSlot;
+use crate::bindings::*;
+use crate::cqe::Cqe;
+use crate::entry::{timespec, Entry};
+use crate::iouring::IoUring;
+use crate::slab::Slab;
+use std::any::Any;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+/// The user_data for cancelling a dropped Op, whose completions are ignored
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+#[allow(dead_code)]
+enum OpState {
+    Waiting(Option<Waker>),
+    Done(Cqe),
+    Orphaned(Box<dyn Any>),
+}
+
+struct Inner {
+    ring: IoUring,
+    ops: Slab<OpState>,
+}
+
+pub struct UringReactor {
+    inner: Rc<RefCell<Inner>>,
+}
+
+#[allow(dead_code)]
+impl UringReactor {
+    /// Creates a reactor that owns the ring
+    pub fn new(ring: IoUring) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                ring,
+                ops: Slab::new(),
+            })),
+        }
+    }
+
+    /// Accepts a connection on a listening socket
+    ///
+    /// Resolves to the new fd and the peer's address.
+    ///
+    pub fn accept(&self, fd: RawFd) -> Op<AcceptOp> {
+        let mut slot = AcceptSlot::new();
+        let (addr, addrlen) = slot.as_mut_ptrs();
+
+        self.submit(AcceptOp { slot }, |entry, user_data| {
+            entry.set_accept(fd, addr, addrlen, user_data)
+        })
+    }
+
+    /// Receives into an owned buffer
+    ///
+    /// Resolves to the number of bytes read along with the buffer, which is
+    /// given back whether or not the receive worked.
+    ///
+    pub fn recv(&self, fd: RawFd, mut buffer: Vec<u8>) -> Op<RecvOp> {
+        let (ptr, len) = (buffer.as_mut_ptr(), buffer.len());
+
+        self.submit(RecvOp { buffer }, |entry, user_data| {
+            entry.set_receive(fd, ptr, len, 0, user_data)
+        })
+    }
+
+    /// Sends an owned buffer
+    ///
+    /// Resolves to the number of bytes sent along with the buffer. Like a
+    /// plain send this can be short.
+    ///
+    pub fn send(&self, fd: RawFd, buffer: Vec<u8>) -> Op<SendOp> {
+        let (ptr, len) = (buffer.as_ptr(), buffer.len());
+
+        self.submit(SendOp { buffer }, |entry, user_data| {
+            entry.set_send(fd, ptr, len, 0, user_data)
+        })
+    }
+
+    /// Resolves once the duration has passed
+    pub fn timeout(&self, duration: Duration) -> Op<TimeoutOp> {
+        let mut ts = Box::new(timespec(duration));
+        let ptr = &mut *ts as *mut __kernel_timespec;
+
+        self.submit(TimeoutOp { ts }, |entry, user_data| {
+            entry.set_timeout(ptr, 0, 0, user_data)
+        })
+    }
+
+    /// Drives a future to completion
+    ///
+    /// Polls the future, and whenever it's pending submits whatever it queued
+    /// and waits for a completion to wake it up.
+    ///
+    pub fn block_on<F: Future>(&self, future: F) -> io::Result<F::Output> {
+        let mut future = pin!(future);
+        let flag = Arc::new(Flag(AtomicBool::new(true)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if flag.0.swap(false, Ordering::AcqRel) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return Ok(output);
+                }
+            }
+
+            self.turn()?;
+        }
+    }
+
+    /// Submits queued entries, waits for a completion, and wakes its Ops
+    ///
+    /// This is what an executor calls when all of its tasks are pending.
+    ///
+    pub fn turn(&self) -> io::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        inner.ring.submit_and_wait(1)?;
+
+        while let Some(cqe) = inner.ring.peek_completion() {
+            inner.complete(cqe);
+        }
+        Ok(())
+    }
+
+    /// Number of operations still in flight, including dropped ones
+    pub fn in_flight(&self) -> usize {
+        self.inner.borrow().ops.len()
+    }
+
+    fn submit<T: Completion, F>(&self, data: T, prepare: F) -> Op<T>
+    where
+        F: FnOnce(&mut Entry, u64),
+    {
+        let mut inner = self.inner.borrow_mut();
+        let user_data = inner.ops.insert(OpState::Waiting(None));
+        prepare(&mut inner.ring.create_entry(), user_data);
+
+        Op {
+            inner: self.inner.clone(),
+            user_data,
+            data: Some(data),
+        }
+    }
+}
+
+impl Inner {
+    /// Hands a completion to its Op
+    fn complete(&mut self, cqe: Cqe) {
+        let Some(state) = self.ops.get_mut(cqe.user_data) else {
+            return;
+        };
+
+        match state {
+            OpState::Waiting(waker) => {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+                *state = OpState::Done(cqe);
+            }
+            OpState::Orphaned(_) => {
+                self.ops.remove(cqe.user_data);
+            }
+            OpState::Done(_) => {}
+        }
+    }
+}
+
+/// Wakes block_on by setting a flag
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Turns a completion into an Op's output
+///
+/// The operation's buffers and addresses are handed back along with the
+/// completion, so they can be returned to the caller.
+///
+pub trait Completion: 'static {
+    type Output;
+
+    fn complete(self, cqe: Cqe) -> Self::Output;
+}
+
+/// A pending operation
+///
+/// Has to be polled from inside block_on (or something else calling turn) to
+/// make progress.
+///
+pub struct Op<T: Completion> {
+    inner: Rc<RefCell<Inner>>,
+    user_data: u64,
+    data: Option<T>,
+}
+
+impl<T: Completion> Unpin for Op<T> {}
+
+impl<T: Completion> Future for Op<T> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T::Output> {
+        let mut inner = self.inner.borrow_mut();
+
+        match inner.ops.get_mut(self.user_data) {
+            Some(OpState::Waiting(waker)) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Some(OpState::Done(_)) => {
+                let Some(OpState::Done(cqe)) = inner.ops.remove(self.user_data) else {
+                    unreachable!();
+                };
+                drop(inner);
+
+                let data = self.data.take().expect("Op polled after completion");
+                Poll::Ready(data.complete(cqe))
+            }
+            _ => panic!("Op polled after completion"),
+        }
+    }
+}
+
+impl<T: Completion> Drop for Op<T> {
+    fn drop(&mut self) {
+        let Some(data) = self.data.take() else {
+            return;
+        };
+        let mut inner = self.inner.borrow_mut();
+
+        // Already completed, so there's nothing left for the kernel to touch
+        if let Some(OpState::Done(_)) = inner.ops.get(self.user_data) {
+            inner.ops.remove(self.user_data);
+            return;
+        }
+
+        if let Some(state) = inner.ops.get_mut(self.user_data) {
+            *state = OpState::Orphaned(Box::new(data));
+            inner
+                .ring
+                .create_entry()
+                .set_cancel(self.user_data, CANCEL_USER_DATA);
+        }
+    }
+}
+
+fn result(res: i32) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::from_raw_os_error(-res))
+    } else {
+        Ok(res as usize)
+    }
+}
+
+pub struct AcceptOp {
+    slot: Box<AcceptSlot>,
+}
+
+impl Completion for AcceptOp {
+    type Output = io::Result<(RawFd, Option<SocketAddr>)>;
+
+    fn complete(self, cqe: Cqe) -> Self::Output {
+        result(cqe.res).map(|fd| (fd as RawFd, self.slot.peer()))
+    }
+}
+
+pub struct RecvOp {
+    buffer: Vec<u8>,
+}
+
+impl Completion for RecvOp {
+    type Output = (io::Result<usize>, Vec<u8>);
+
+    fn complete(self, cqe: Cqe) -> Self::Output {
+        (result(cqe.res), self.buffer)
+    }
+}
+
+pub struct SendOp {
+    buffer: Vec<u8>,
+}
+
+impl Completion for SendOp {
+    type Output = (io::Result<usize>, Vec<u8>);
+
+    fn complete(self, cqe: Cqe) -> Self::Output {
+        (result(cqe.res), self.buffer)
+    }
+}
+
+/// The kernel is done with the timespec once the entry is submitted, but
+/// keeping it until the completion is simpler than tracking that.
+#[allow(dead_code)]
+pub struct TimeoutOp {
+    ts: Box<__kernel_timespec>,
+}
+
+impl Completion for TimeoutOp {
+    type Output = io::Result<()>;
+
+    fn complete(self, cqe: Cqe) -> Self::Output {
+        // A timeout that expires completes with -ETIME, which is what we want
+        match cqe.res {
+            res if res == -(ETIME as i32) => Ok(()),
+            res => result(res).map(|_| ()),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// Shared ring
+///
+/// There are two ways to use io_uring from several threads. The simplest, and
+/// the fastest, is to give every thread a ring of its own: IoUring is Send, so
+/// it can be built on one thread and moved to the one that uses it, and no
+/// locking is needed at all.
+///
+/// The other is to share one ring, which is what SharedRing is for. The
+/// submission and completion queues are plain memory with head and tail
+/// indices, and two threads filling in entries or advancing the queue at the
+/// same time would corrupt them, so everything goes through a single lock:
+///
+///     let mut ring = shared.lock();
+///     ring.create_entry().set_send(fd, buf, len, 0, user_data);
+///     ring.submit()?;
+///
+/// Any thread can reap any completion, so the user_data has to be enough to
+/// route a completion to whoever is waiting on it. Blocking while holding the
+/// lock stalls every other thread, which is why there's no plain wait here,
+/// only one with a timeout.
+///
+use crate::cqe::Cqe;
+use crate::iouring::IoUring;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct SharedRing {
+    ring: Arc<Mutex<IoUring>>,
+}
+
+#[allow(dead_code)]
+impl SharedRing {
+    /// Shares a ring between threads
+    ///
+    /// Rings set up with single_issuer only take submissions from one thread,
+    /// so they can't be shared. Neither can a ring with a registered fd,
+    /// since the registration only holds on the thread that made it.
+    /// Registering one through the lock later on ties the ring to whichever
+    /// thread did it, and the other threads' calls fail from then on.
+    ///
+    pub fn new(ring: IoUring) -> io::Result<Self> {
+        if ring.is_single_issuer() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "single_issuer rings can't be shared",
+            ));
+        }
+        if ring.is_ring_fd_registered() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "rings with a registered fd can't be shared",
+            ));
+        }
+
+        Ok(Self {
+            ring: Arc::new(Mutex::new(ring)),
+        })
+    }
+
+    /// Locks the ring
+    ///
+    /// Entries have to be created and submitted while holding the lock.
+    /// A thread that panicked while holding it may have left a half-filled
+    /// entry in the queue, so a poisoned lock is treated as fatal.
+    ///
+    pub fn lock(&self) -> MutexGuard<'_, IoUring> {
+        self.ring.lock().expect("ring lock poisoned")
+    }
+
+    /// Submits whatever has been queued
+    pub fn submit(&self) -> io::Result<usize> {
+        self.lock().submit()
+    }
+
+    /// Peeks for a completion
+    pub fn peek_completion(&self) -> Option<Cqe> {
+        self.lock().peek_completion()
+    }
+
+    /// Waits for a completion, up to a timeout
+    ///
+    /// The lock is held for the whole wait, so keep the timeout short.
+    ///
+    pub fn wait_completion_timeout(&self, timeout: Duration) -> io::Result<Option<Cqe>> {
+        self.lock().wait_completion_timeout(timeout)
+    }
+}
@@ -0,0 +1,97 @@
+/// Signals
+///
+/// Rather than installing a handler, the signals we care about are blocked and
+/// read from a signalfd. That turns a signal into a plain read on the ring,
+/// which completes alongside everything else and can be handled in the event
+/// loop like any other completion.
+///
+/// Blocking only applies to the calling thread and the threads it spawns
+/// afterwards, so the signalfd should be created before any threads are. A
+/// thread that still has the signals unblocked would be killed by them as
+/// usual.
+///
+/// std already links libc, so the handful of functions needed are declared
+/// here rather than pulling in a crate for them.
+///
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+pub const SIGINT: c_int = 2;
+pub const SIGTERM: c_int = 15;
+
+/// Every read of a signalfd returns one signalfd_siginfo of this size
+pub const SIGINFO_SIZE: usize = 128;
+
+const SIG_BLOCK: c_int = 0;
+const SFD_CLOEXEC: c_int = 0o2000000;
+
+/// glibc's sigset_t, which has room for 1024 signals
+#[repr(C)]
+struct SigSet([u64; 16]);
+
+extern "C" {
+    fn sigemptyset(set: *mut SigSet) -> c_int;
+    fn sigaddset(set: *mut SigSet, signum: c_int) -> c_int;
+    fn pthread_sigmask(how: c_int, set: *const SigSet, old: *mut SigSet) -> c_int;
+    fn signalfd(fd: c_int, mask: *const SigSet, flags: c_int) -> c_int;
+}
+
+pub struct SignalFd {
+    fd: OwnedFd,
+}
+
+impl SignalFd {
+    /// Blocks the signals and creates a signalfd for them
+    pub fn new(signals: &[c_int]) -> io::Result<Self> {
+        let mut set = MaybeUninit::<SigSet>::uninit();
+
+        unsafe {
+            if sigemptyset(set.as_mut_ptr()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            for &signal in signals {
+                if sigaddset(set.as_mut_ptr(), signal) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        let set = unsafe { set.assume_init() };
+
+        // Unlike most of libc this returns the error rather than setting errno
+        let ret = unsafe { pthread_sigmask(SIG_BLOCK, &set, std::ptr::null_mut()) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+
+        let fd = unsafe { signalfd(-1, &set, SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Gets the signal number out of a signalfd_siginfo
+pub fn signal_number(info: &[u8; SIGINFO_SIZE]) -> c_int {
+    u32::from_ne_bytes([info[0], info[1], info[2], info[3]]) as c_int
+}
+
+/// Names the signals we catch
+pub fn signal_name(signal: c_int) -> &'static str {
+    match signal {
+        SIGINT => "SIGINT",
+        SIGTERM => "SIGTERM",
+        _ => "signal",
+    }
+}
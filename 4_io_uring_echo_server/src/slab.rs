@@ -0,0 +1,185 @@
+/// Slab
+///
+/// Storage for the state of in-flight operations, keyed by the u64 that goes
+/// into an entry's user_data. A key is the slot index in the lower 32 bits and
+/// the slot's generation in the upper 32. The generation is bumped every time
+/// a slot is freed, so a stale key (say, the completion of an operation we've
+/// already given up on) can never find the state of whatever reuses the slot.
+///
+/// The usual pattern is to insert the state when preparing an entry and to
+/// remove it when its completion is handled, which frees the slot for reuse.
+/// Multishot operations post several completions for the same key, so their
+/// state should only be removed once Cqe::has_more is false.
+///
+enum Slot<T> {
+    Occupied { generation: u32, value: T },
+    Vacant { generation: u32 },
+}
+
+pub struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    /// Creates an empty slab
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Stores a value and returns its key
+    ///
+    /// Freed slots are reused before the slab grows.
+    ///
+    pub fn insert(&mut self, value: T) -> u64 {
+        self.len += 1;
+
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            let generation = match slot {
+                Slot::Vacant { generation } => *generation,
+                Slot::Occupied { .. } => unreachable!("free list points at an occupied slot"),
+            };
+
+            *slot = Slot::Occupied { generation, value };
+            return key(index, generation);
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot::Occupied {
+            generation: 0,
+            value,
+        });
+        key(index, 0)
+    }
+
+    /// Looks up a value
+    pub fn get(&self, key: u64) -> Option<&T> {
+        let (index, generation) = split(key);
+
+        match self.slots.get(index as usize) {
+            Some(Slot::Occupied {
+                generation: current,
+                value,
+            }) if *current == generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Looks up a value mutably
+    pub fn get_mut(&mut self, key: u64) -> Option<&mut T> {
+        let (index, generation) = split(key);
+
+        match self.slots.get_mut(index as usize) {
+            Some(Slot::Occupied {
+                generation: current,
+                value,
+            }) if *current == generation => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Removes a value, freeing its slot
+    ///
+    /// Returns None if the key is stale or was never handed out.
+    ///
+    pub fn remove(&mut self, key: u64) -> Option<T> {
+        self.get(key)?;
+
+        let (index, generation) = split(key);
+        let vacant = Slot::Vacant {
+            generation: generation.wrapping_add(1),
+        };
+
+        match std::mem::replace(&mut self.slots[index as usize], vacant) {
+            Slot::Occupied { value, .. } => {
+                self.free.push(index);
+                self.len -= 1;
+                Some(value)
+            }
+            Slot::Vacant { .. } => None,
+        }
+    }
+
+    /// Iterates over the keys and values that are in use
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => {
+                    Some((key(index as u32, *generation), value))
+                }
+                Slot::Vacant { .. } => None,
+            })
+    }
+
+    /// Number of values stored
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a key out of a slot index and generation
+fn key(index: u32, generation: u32) -> u64 {
+    (generation as u64) << 32 | index as u64
+}
+
+/// Splits a key into its slot index and generation
+fn split(key: u64) -> (u32, u32) {
+    (key as u32, (key >> 32) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_key_misses_reused_slot() {
+        let mut slab = Slab::new();
+        let first = slab.insert("first");
+        assert_eq!(slab.remove(first), Some("first"));
+
+        let second = slab.insert("second");
+        assert_eq!(split(second).0, split(first).0);
+        assert_ne!(second, first);
+
+        assert_eq!(slab.get(first), None);
+        assert_eq!(slab.get_mut(first), None);
+        assert_eq!(slab.remove(first), None);
+        assert_eq!(slab.get(second), Some(&"second"));
+    }
+
+    #[test]
+    fn len_counts_what_is_stored() {
+        let mut slab = Slab::new();
+        let a = slab.insert(1);
+        let b = slab.insert(2);
+        assert_eq!(slab.len(), 2);
+
+        slab.remove(a);
+        assert_eq!(slab.remove(a), None);
+        assert_eq!(slab.len(), 1);
+
+        slab.insert(3);
+        assert_eq!(slab.len(), 2);
+        slab.remove(b);
+        assert_eq!(slab.len(), 1);
+        assert_eq!(slab.iter().count(), 1);
+    }
+}
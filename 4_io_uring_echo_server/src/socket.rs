@@ -0,0 +1,164 @@
+/// Sockets
+///
+/// std's TcpListener binds as soon as it's created, while SO_REUSEPORT and
+/// IPV6_V6ONLY only count if they're set before the bind. So listeners are
+/// created here instead.
+///
+/// With SO_REUSEPORT set on every socket, several listeners can bind the same
+/// address and port, and the kernel spreads the incoming connections across
+/// them. That's how the workers share a port without sharing a listener (see
+/// workers.rs).
+///
+/// An IPv6 listener on :: takes IPv4 connections as well unless IPV6_V6ONLY
+/// is set, and those show up with IPv4-mapped addresses like
+/// ::ffff:127.0.0.1 (see addr::unmap). The default for it comes from the
+/// net.ipv6.bindv6only sysctl, so it's always set one way or the other.
+///
+/// Accepted sockets only come to us as fds, so the options set on those
+/// (see SocketOptions) go through setsockopt as well.
+///
+/// Like in signal.rs, the handful of libc functions needed are declared here.
+///
+use crate::addr::from_socket_addr;
+use crate::bindings::{sockaddr, sockaddr_storage, socklen_t, AF_INET, AF_INET6};
+use std::io;
+use std::mem::size_of;
+use std::net::{SocketAddr, TcpListener};
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+const SOCK_STREAM: c_int = 1;
+const SOCK_CLOEXEC: c_int = 0o2000000;
+const SOL_SOCKET: c_int = 1;
+const SO_REUSEADDR: c_int = 2;
+const SO_REUSEPORT: c_int = 15;
+const SO_KEEPALIVE: c_int = 9;
+const IPPROTO_IPV6: c_int = 41;
+const IPV6_V6ONLY: c_int = 26;
+const IPPROTO_TCP: c_int = 6;
+const TCP_NODELAY: c_int = 1;
+const TCP_KEEPIDLE: c_int = 4;
+const TCP_KEEPINTVL: c_int = 5;
+
+/// How many connections may wait to be accepted, capped by net.core.somaxconn
+const BACKLOG: c_int = 1024;
+
+extern "C" {
+    fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+    fn setsockopt(
+        fd: c_int,
+        level: c_int,
+        name: c_int,
+        value: *const c_void,
+        len: socklen_t,
+    ) -> c_int;
+    fn bind(fd: c_int, addr: *const sockaddr, len: socklen_t) -> c_int;
+    fn listen(fd: c_int, backlog: c_int) -> c_int;
+}
+
+/// Creates a listener
+///
+/// With reuseport it shares its port with others like it. v6_only only
+/// matters for an IPv6 address, where it turns away IPv4 connections.
+/// SO_REUSEADDR is always set, like std does on its own listeners, so a
+/// restarted server doesn't have to wait out connections in TIME_WAIT.
+///
+pub fn listener(addr: SocketAddr, reuseport: bool, v6_only: bool) -> io::Result<TcpListener> {
+    let domain = match addr {
+        SocketAddr::V4(_) => AF_INET,
+        SocketAddr::V6(_) => AF_INET6,
+    };
+
+    let fd = unsafe { socket(domain as c_int, SOCK_STREAM | SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owned straight away so the socket is closed if anything below fails
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let raw = fd.as_raw_fd();
+
+    set_option(raw, SOL_SOCKET, SO_REUSEADDR, 1)?;
+    if reuseport {
+        set_option(raw, SOL_SOCKET, SO_REUSEPORT, 1)?;
+    }
+    if addr.is_ipv6() {
+        set_option(raw, IPPROTO_IPV6, IPV6_V6ONLY, v6_only as c_int)?;
+    }
+
+    let (storage, len) = from_socket_addr(&addr);
+    let storage_ptr = &storage as *const sockaddr_storage as *const sockaddr;
+    if unsafe { bind(raw, storage_ptr, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { listen(raw, BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(TcpListener::from(fd))
+}
+
+/// Options for accepted connections
+///
+/// Nagle's algorithm holds back small sends while earlier data is still
+/// unacknowledged, which for an echo means a reply can sit around waiting on
+/// the peer's delayed ACK for up to 40ms. So nodelay is on unless asked
+/// otherwise. Keepalive probes a connection that's gone quiet for that long,
+/// and again every so often after, so a peer that vanished without closing is
+/// found out.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Sets the options on an accepted socket
+    ///
+    /// Sockets start out with Nagle's algorithm and keepalive as the system
+    /// has them, so only what differs from that is set.
+    ///
+    pub fn apply(&self, fd: RawFd) -> io::Result<()> {
+        if self.nodelay {
+            set_option(fd, IPPROTO_TCP, TCP_NODELAY, 1)?;
+        }
+
+        if let Some(keepalive) = self.keepalive {
+            // The kernel wants whole seconds, and at least one
+            let secs = keepalive.as_secs().clamp(1, c_int::MAX as u64) as c_int;
+            set_option(fd, SOL_SOCKET, SO_KEEPALIVE, 1)?;
+            set_option(fd, IPPROTO_TCP, TCP_KEEPIDLE, secs)?;
+            set_option(fd, IPPROTO_TCP, TCP_KEEPINTVL, secs)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets an option that takes an int
+fn set_option(fd: c_int, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const c_int as *const c_void,
+            size_of::<c_int>() as socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
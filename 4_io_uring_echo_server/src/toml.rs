@@ -0,0 +1,267 @@
+/// TOML
+///
+/// Just enough TOML for config files: tables, and keys set to strings,
+/// integers or booleans, with a comment allowed at the end of any line.
+///
+///     # echo.toml
+///     [listen]
+///     address = "::"
+///     port = 9000
+///
+/// Keys come back flattened, with their table in front, so the above gives
+/// listen.address and listen.port. Arrays, inline tables, floats, dates and
+/// multi-line strings are reported as errors rather than skipped, so a file
+/// that uses them doesn't half work. Nothing in here is specific to the echo
+/// server.
+///
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+/// Writes the value the way it would be given on the command line, so strings
+/// go without their quotes
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(value) => f.write_str(value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A key, its value and the line it was set on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a whole file into its entries, in the order they appear
+pub fn parse(text: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut entries = Vec::new();
+    let mut keys = HashSet::new();
+    let mut tables = HashSet::new();
+    let mut table = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| ParseError {
+            line: number,
+            message,
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = header
+                .split_once(']')
+                .ok_or_else(|| error(format!("Unclosed table header: {}", line)))?;
+            end_of_line(rest).map_err(error)?;
+
+            table =
+                parse_key(name).ok_or_else(|| error(format!("Invalid table name: {}", name)))?;
+            if !tables.insert(table.clone()) {
+                return Err(error(format!("Table {} is defined twice", table)));
+            }
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("Expected key = value: {}", line)))?;
+        let key = parse_key(key).ok_or_else(|| error(format!("Invalid key: {}", key.trim())))?;
+        let key = match table.as_str() {
+            "" => key,
+            table => format!("{}.{}", table, key),
+        };
+        let value = parse_value(value.trim()).map_err(error)?;
+
+        if !keys.insert(key.clone()) {
+            return Err(error(format!("{} is set twice", key)));
+        }
+        entries.push(Entry {
+            key,
+            value,
+            line: number,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parses a bare key, which may be dotted like buffers.size
+///
+/// Quoted keys aren't supported.
+///
+fn parse_key(key: &str) -> Option<String> {
+    let parts: Vec<&str> = key.split('.').map(str::trim).collect();
+    let bare = |part: &&str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    };
+
+    parts.iter().all(bare).then(|| parts.join("."))
+}
+
+/// Parses everything after the equals sign, trailing comment included
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(rest) = text.strip_prefix('"') {
+        let (value, rest) = basic_string(rest)?;
+        end_of_line(rest)?;
+        return Ok(Value::String(value));
+    }
+
+    if let Some(rest) = text.strip_prefix('\'') {
+        let (value, rest) = rest
+            .split_once('\'')
+            .ok_or_else(|| format!("Unclosed string: {}", text))?;
+        end_of_line(rest)?;
+        return Ok(Value::String(value.to_string()));
+    }
+
+    let value = match text.split_once('#') {
+        Some((value, _)) => value.trim_end(),
+        None => text,
+    };
+    match value {
+        "" => Err("Missing value".to_string()),
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => value
+            .replace('_', "")
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("Unsupported value: {}", value)),
+    }
+}
+
+/// Parses a double quoted string up to its closing quote
+///
+/// Returns the string with its escapes replaced and whatever follows it.
+///
+fn basic_string(text: &str) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[index + 1..])),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, '"')) => '"',
+                    Some((_, '\\')) => '\\',
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    Some((_, 'r')) => '\r',
+                    Some((_, c)) => return Err(format!("Unsupported escape: \\{}", c)),
+                    None => break,
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+    }
+
+    Err(format!("Unclosed string: \"{}", text))
+}
+
+/// Checks that nothing but a comment follows a value or table header
+fn end_of_line(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("Unexpected text: {}", rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<Value, ParseError> {
+        parse(&format!("key = {}", text)).map(|entries| entries[0].value.clone())
+    }
+
+    fn error(text: &str) -> String {
+        parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn tables_prefix_their_keys() {
+        let entries = parse("top = 1\n\n[listen]\naddress = \"::\"\nport = 9000\n").unwrap();
+        let keys: Vec<(&str, usize)> = entries.iter().map(|e| (e.key.as_str(), e.line)).collect();
+
+        assert_eq!(
+            keys,
+            [("top", 1), ("listen.address", 4), ("listen.port", 5)]
+        );
+    }
+
+    #[test]
+    fn hash_inside_a_string_is_not_a_comment() {
+        assert_eq!(value("\"a # b\"").unwrap(), Value::String("a # b".into()));
+        assert_eq!(value("'a # b'").unwrap(), Value::String("a # b".into()));
+    }
+
+    #[test]
+    fn trailing_comments_are_ignored() {
+        assert_eq!(value("42 # answer").unwrap(), Value::Integer(42));
+        assert_eq!(value("true# yes").unwrap(), Value::Boolean(true));
+        assert_eq!(value("\"x\" # why").unwrap(), Value::String("x".into()));
+    }
+
+    #[test]
+    fn integers_may_have_underscores() {
+        assert_eq!(value("1_000").unwrap(), Value::Integer(1000));
+        assert_eq!(value("-4_096").unwrap(), Value::Integer(-4096));
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        assert_eq!(error("a = 1\na = 2"), "line 2: a is set twice");
+        assert_eq!(error("[t]\na = 1\n[t]"), "line 3: Table t is defined twice");
+        assert_eq!(error("t.a = 1\n[t]\na = 2"), "line 3: t.a is set twice");
+    }
+
+    #[test]
+    fn unclosed_strings_are_rejected() {
+        assert_eq!(error("a = \"abc"), "line 1: Unclosed string: \"abc");
+        assert_eq!(error("a = 'abc"), "line 1: Unclosed string: 'abc");
+    }
+
+    #[test]
+    fn unsupported_values_are_rejected() {
+        assert_eq!(error("a = [1, 2]"), "line 1: Unsupported value: [1, 2]");
+        assert_eq!(error("a = 1.5"), "line 1: Unsupported value: 1.5");
+        assert_eq!(error("a = "), "line 1: Missing value");
+    }
+}
@@ -0,0 +1,156 @@
+/// Transforms
+///
+/// What the echo server does to the data between receiving it and sending it
+/// back, so that it can stand in for a peer that does more than echo. A
+/// transform works in place on the bytes about to be sent and can't change
+/// their length, which keeps the echo in the connection's buffer. It can also
+/// ask for the echo to be held back for a while, which the server does with a
+/// timeout on the ring rather than by sleeping.
+///
+/// Transforms are chained into a pipeline with --transform, applied left to
+/// right:
+///
+///     io_uring_tcp --transform upper,reverse,delay:50
+///
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub trait EchoTransform: Send + Sync {
+    /// The name it's given on the command line
+    fn name(&self) -> String;
+
+    /// Changes the data in place
+    fn apply(&self, data: &mut [u8]);
+
+    /// How long to hold the echo back for
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Sends the data back as it came in
+pub struct Identity;
+
+impl EchoTransform for Identity {
+    fn name(&self) -> String {
+        "identity".to_string()
+    }
+
+    fn apply(&self, _data: &mut [u8]) {}
+}
+
+/// Uppercases ASCII letters, leaving every other byte alone
+pub struct Uppercase;
+
+impl EchoTransform for Uppercase {
+    fn name(&self) -> String {
+        "upper".to_string()
+    }
+
+    fn apply(&self, data: &mut [u8]) {
+        data.make_ascii_uppercase();
+    }
+}
+
+/// Reverses each line, leaving the newlines where they are
+///
+/// Data without a newline is reversed as a whole. Bytes are reversed rather
+/// than characters, so multi-byte UTF-8 comes back garbled.
+///
+pub struct Reverse;
+
+impl EchoTransform for Reverse {
+    fn name(&self) -> String {
+        "reverse".to_string()
+    }
+
+    fn apply(&self, data: &mut [u8]) {
+        for line in data.split_mut(|&b| b == b'\n') {
+            line.reverse();
+        }
+    }
+}
+
+/// Holds the echo back for a fixed time, like a slow peer would
+pub struct Delay(pub Duration);
+
+impl EchoTransform for Delay {
+    fn name(&self) -> String {
+        format!("delay:{}", self.0.as_millis())
+    }
+
+    fn apply(&self, _data: &mut [u8]) {}
+
+    fn delay(&self) -> Duration {
+        self.0
+    }
+}
+
+/// A chain of transforms
+///
+/// The transforms are shared rather than copied, so a pipeline can be cloned
+/// into every worker. An empty one is the same as identity.
+///
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    transforms: Vec<Arc<dyn EchoTransform>>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, transform: Arc<dyn EchoTransform>) {
+        self.transforms.push(transform);
+    }
+
+    /// Applies every transform in turn
+    pub fn apply(&self, data: &mut [u8]) {
+        for transform in &self.transforms {
+            transform.apply(data);
+        }
+    }
+
+    /// The delays of every transform added up
+    pub fn delay(&self) -> Duration {
+        self.transforms
+            .iter()
+            .map(|transform| transform.delay())
+            .sum()
+    }
+}
+
+/// Parses a comma separated list of names, like upper,delay:50
+///
+/// The delay is given in milliseconds.
+///
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pipeline = Pipeline::default();
+
+        for name in s.split(',') {
+            let transform: Arc<dyn EchoTransform> = match name.split_once(':') {
+                None if name == "identity" => Arc::new(Identity),
+                None if name == "upper" => Arc::new(Uppercase),
+                None if name == "reverse" => Arc::new(Reverse),
+                Some(("delay", ms)) => {
+                    let ms = ms.parse().map_err(|_| format!("Invalid delay: {}", ms))?;
+                    Arc::new(Delay(Duration::from_millis(ms)))
+                }
+                _ => return Err(format!("Unknown transform: {}", name)),
+            };
+            pipeline.push(transform);
+        }
+
+        Ok(pipeline)
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.transforms.iter().map(|transform| transform.name()))
+            .finish()
+    }
+}
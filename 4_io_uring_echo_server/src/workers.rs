@@ -0,0 +1,171 @@
+/// Workers
+///
+/// Runs the echo server on several threads at once, each with its own ring
+/// and its own listener on the shared port (see EchoServer::worker). Nothing
+/// is shared between them: the kernel picks a listener for every incoming
+/// connection and that worker handles it from then on. That's the sharded
+/// alternative to one ring doing everything, and what lets the echo scale past
+/// a single core.
+///
+/// The main thread is left with the signals. It blocks them before starting
+/// any workers, so they inherit that, and then reads them on a small ring of
+/// its own and forwards each one to every worker's ring with a message (see
+/// SIGNAL_USER_DATA). As each worker finishes it sends a message back, and
+/// once all of them have we're done:
+///
+///     main ring:    read signalfd ---> msg_ring to each worker
+///     worker rings: shut down     ---> msg_ring WORKER_DONE back to main
+///
+use crate::config::Config;
+use crate::cqe::Cqe;
+use crate::echo_server::{EchoServer, SIGNAL_USER_DATA};
+use crate::iouring::IoUring;
+use crate::log::{debug, error, info};
+use crate::signal::{signal_number, SignalFd, SIGINFO_SIZE, SIGINT, SIGTERM};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+const SIGNAL_READ: u64 = 1;
+const FORWARD: u64 = 2;
+const WORKER_DONE: u64 = 3;
+
+/// A running worker, and the ring fd to forward signals to
+struct Worker {
+    handle: JoinHandle<io::Result<()>>,
+    ring_fd: RawFd,
+}
+
+/// Runs the workers until a signal has shut them all down
+pub fn run(config: &Config) -> io::Result<()> {
+    let signals = SignalFd::new(&[SIGINT, SIGTERM])?;
+    // Room to forward a signal to every worker and read the next one
+    let mut ring = IoUring::builder(config.workers as u32 + 1)
+        .clamp()
+        .build()?;
+
+    let workers = spawn(config, ring.ring_fd())?;
+
+    let mut info = Box::new([0; SIGINFO_SIZE]);
+    read_signal(&mut ring, &signals, &mut info);
+
+    let mut running = workers.len();
+    while running > 0 {
+        match ring.submit_and_wait(1) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => result?,
+        };
+
+        while let Some(cqe) = ring.peek_completion() {
+            match cqe.user_data {
+                SIGNAL_READ => {
+                    forward(&mut ring, &cqe, &info, &workers);
+                    read_signal(&mut ring, &signals, &mut info);
+                }
+                // Only failures are posted, and a worker that's already gone
+                // doesn't need the signal anyway
+                FORWARD => debug!("Forwarding a signal failed: {}", cqe.res),
+                WORKER_DONE => running -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    let mut result = Ok(());
+    for worker in workers {
+        match worker.handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => result = Err(err),
+            Err(_) => result = Err(io::Error::other("worker panicked")),
+        }
+    }
+    info!("Shut down cleanly");
+    result
+}
+
+/// Starts the workers
+///
+/// Each worker creates its server on its own thread, since the ring's fd is
+/// registered to the thread that creates it, and hands back its ring's fd
+/// once it's listening. The first one binds the configured port and the rest
+/// bind whatever port it ended up with, which only differs when asked for
+/// port 0.
+///
+fn spawn(config: &Config, main_ring_fd: RawFd) -> io::Result<Vec<Worker>> {
+    let mut config = config.clone();
+    let mut workers = Vec::with_capacity(config.workers);
+
+    for id in 0..config.workers {
+        let (ready, listening) = mpsc::channel();
+        let worker_config = config.clone();
+
+        let handle = thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || {
+                let mut server = match EchoServer::worker(&worker_config, id) {
+                    Ok(server) => server,
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return Ok(());
+                    }
+                };
+                let _ = ready.send(server.local_addr().map(|addr| (addr, server.ring_fd())));
+
+                let result = server.run();
+                if let Err(err) = &result {
+                    error!("Worker {} failed: {}", id, err);
+                }
+                server.notify(main_ring_fd, id as u32, WORKER_DONE)?;
+                result
+            })?;
+
+        let (addr, ring_fd) = listening
+            .recv()
+            .map_err(|_| io::Error::other("worker exited before listening"))??;
+        if id == 0 {
+            info!(
+                "Echo server listening on {} with {} workers",
+                addr, config.workers
+            );
+            config.port = addr.port();
+        }
+
+        workers.push(Worker { handle, ring_fd });
+    }
+
+    Ok(workers)
+}
+
+/// Queues a read of the next signal
+fn read_signal(ring: &mut IoUring, signals: &SignalFd, info: &mut [u8; SIGINFO_SIZE]) {
+    ring.create_entry().set_read(
+        signals.as_raw_fd(),
+        info.as_mut_ptr(),
+        SIGINFO_SIZE as u32,
+        u64::MAX,
+        SIGNAL_READ,
+    );
+}
+
+/// Posts a signal that was read to every worker
+fn forward(ring: &mut IoUring, cqe: &Cqe, info: &[u8; SIGINFO_SIZE], workers: &[Worker]) {
+    if cqe.res < 0 {
+        error!(
+            "Reading a signal failed: {}",
+            io::Error::from_raw_os_error(-cqe.res)
+        );
+        return;
+    }
+
+    let signal = signal_number(info);
+    for worker in workers {
+        ring.create_entry().set_msg_ring(
+            worker.ring_fd,
+            signal as u32,
+            SIGNAL_USER_DATA,
+            true,
+            FORWARD,
+        );
+    }
+}
@@ -0,0 +1,76 @@
+/// Zero-copy sends
+///
+/// A zero-copy send reads from our buffer while it's being sent, so the buffer
+/// has to outlive the send completion and stay untouched until the kernel's
+/// notification arrives. The ZeroCopySender takes ownership of the buffer for
+/// that window and hands it back once it's safe.
+///
+/// Kernels older than 6.0 don't have send_zc, in which case a regular send is
+/// used instead and the buffer is handed back on its only completion.
+///
+use crate::cqe::Cqe;
+use crate::iouring::IoUring;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+
+pub struct ZeroCopySender {
+    supported: bool,
+    pending: HashMap<u64, Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl ZeroCopySender {
+    /// Creates the sender
+    ///
+    /// Checks once whether the kernel supports zero-copy sends.
+    ///
+    pub fn new(ring: &mut IoUring) -> Self {
+        Self {
+            supported: ring.capabilities().send_zc,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Whether sends are actually zero-copy or falling back
+    pub fn is_zero_copy(&self) -> bool {
+        self.supported
+    }
+
+    /// Send a buffer
+    ///
+    /// The user_data needs to be unique among the sends still pending on this
+    /// sender, since it's what the completions are matched on.
+    ///
+    pub fn send(&mut self, ring: &mut IoUring, fd: RawFd, buffer: Vec<u8>, user_data: u64) {
+        let mut entry = ring.create_entry();
+
+        if self.supported {
+            entry.set_send_zc(fd, buffer.as_ptr(), buffer.len(), 0, user_data);
+        } else {
+            entry.set_send(fd, buffer.as_ptr(), buffer.len(), 0, user_data);
+        }
+
+        // Moving the Vec doesn't move its heap allocation, so the pointer
+        // given to the kernel stays valid.
+        self.pending.insert(user_data, buffer);
+    }
+
+    /// Handle a send completion
+    ///
+    /// Call this for every completion belonging to a send. Returns the buffer
+    /// once the kernel is finished with it: on the notification for a
+    /// zero-copy send, on the only completion for a regular send, or right
+    /// away if the zero-copy send failed and no notification will follow.
+    ///
+    pub fn complete(&mut self, cqe: &Cqe) -> Option<Vec<u8>> {
+        if self.supported && !cqe.is_notification() && cqe.has_more() {
+            return None;
+        }
+        self.pending.remove(&cqe.user_data)
+    }
+
+    /// Number of buffers still held for the kernel
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}